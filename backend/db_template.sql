@@ -30,7 +30,11 @@ CREATE TABLE users (
     username VARCHAR(255) UNIQUE NOT NULL,
     password_hash VARCHAR(127) NOT NULL,
     date_of_registration BIGINT NOT NULL,
-    country VARCHAR(3)
+    country VARCHAR(3),
+    scheduler VARCHAR(10) NOT NULL DEFAULT 'sm2',
+    fsrs_parameters VARCHAR(255) NOT NULL DEFAULT '',
+    new_cards_per_day INT NOT NULL DEFAULT 20,
+    reviews_per_day INT NOT NULL DEFAULT 200
 );
 
 DELIMITER //
@@ -52,6 +56,8 @@ DELIMITER ;
 
 
 -- Modify the 'stacks' table
+-- directions is 'forward' or 'both', cards of 'both' stacks are also studied from the backside
+-- upstream_id is the stack a fork was copied from, forks_count how many times the stack was forked
 CREATE TABLE stacks (
     unique_id VARCHAR(10) PRIMARY KEY,
     owner_id VARCHAR(10) NOT NULL,
@@ -59,7 +65,17 @@ CREATE TABLE stacks (
     name VARCHAR(255) NOT NULL,
     visibility BOOLEAN NOT NULL,
     cards_count INT NOT NULL DEFAULT 0,
-    tags VARCHAR(255) NOT NULL
+    tags VARCHAR(255) NOT NULL,
+    learning_steps VARCHAR(255) NOT NULL DEFAULT '1m,10m',
+    relearning_steps VARCHAR(255) NOT NULL DEFAULT '10m',
+    leech_threshold INT NOT NULL DEFAULT 8,
+    leech_suspend BOOLEAN NOT NULL DEFAULT FALSE,
+    scheduler VARCHAR(16) NOT NULL DEFAULT '',
+    leitner_intervals VARCHAR(64) NOT NULL DEFAULT '1,2,4,8,16',
+    directions VARCHAR(8) NOT NULL DEFAULT 'forward',
+    upstream_id VARCHAR(10) NULL DEFAULT NULL,
+    FOREIGN KEY (upstream_id) REFERENCES stacks(unique_id) ON DELETE SET NULL,
+    forks_count INT NOT NULL DEFAULT 0
 );
 
 DELIMITER //
@@ -80,13 +96,50 @@ END //
 DELIMITER ;
 
 
+-- Modify the 'note_types' table
+-- fields is a JSON list of field names, templates a JSON list of {name, front, back} objects
+CREATE TABLE note_types (
+    unique_id VARCHAR(10) PRIMARY KEY,
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    fields TEXT NOT NULL,
+    templates TEXT NOT NULL
+);
+
+DELIMITER //
+CREATE FUNCTION generate_note_type_id() RETURNS VARCHAR(10) DETERMINISTIC
+BEGIN
+    DECLARE collision_count INT DEFAULT 0;
+    DECLARE new_id VARCHAR(10);
+    SET new_id = generate_random_base64_10char();
+    -- Check for collisions and regenerate the unique_id if needed
+    SELECT COUNT(*) INTO collision_count FROM note_types WHERE unique_id = new_id;
+    WHILE collision_count > 0 DO
+        SET new_id = generate_random_base64_10char();
+        SELECT COUNT(*) INTO collision_count FROM note_types WHERE unique_id = new_id;
+    END WHILE;
+
+    RETURN new_id;
+END //
+DELIMITER ;
+
+
 -- Modify the 'cards' table
+-- card_type is 'basic' or 'cloze', cloze cards keep the marked text in frontside and extra notes in backside
+-- directions overrides the one of the stack, empty means the stack decides
+-- note_type_id is empty for the built-in Basic note type (fields Front and Back kept in frontside and backside),
+-- cards of other note types keep their field values in fields (JSON object) and the first rendered card in frontside and backside
 CREATE TABLE cards (
     unique_id VARCHAR(10) PRIMARY KEY,
     stack_id VARCHAR(10) NOT NULL,
     FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
     frontside TEXT NOT NULL,
-    backside TEXT NOT NULL
+    backside TEXT NOT NULL,
+    card_type VARCHAR(16) NOT NULL DEFAULT 'basic',
+    directions VARCHAR(8) NOT NULL DEFAULT '',
+    note_type_id VARCHAR(10) NOT NULL DEFAULT '',
+    fields TEXT NOT NULL DEFAULT ('')
 );
 
 DELIMITER //
@@ -131,4 +184,204 @@ BEGIN
     WHERE unique_id = OLD.stack_id;
 END//
 
-DELIMITER ;
\ No newline at end of file
+DELIMITER ;
+
+-- Modify the 'card_states' table
+-- ordinal is the cloze number of a sub-card, 0 for basic cards, direction is 'forward' or 'backward'
+CREATE TABLE card_states (
+    user_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    card_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (card_id) REFERENCES cards(unique_id) ON DELETE CASCADE,
+    ease_factor DOUBLE NOT NULL,
+    interval_days INT NOT NULL,
+    repetitions INT NOT NULL,
+    due BIGINT NOT NULL,
+    stability DOUBLE NOT NULL DEFAULT 0,
+    difficulty DOUBLE NOT NULL DEFAULT 0,
+    last_review BIGINT NOT NULL DEFAULT 0,
+    phase VARCHAR(10) NOT NULL DEFAULT 'new',
+    step INT NOT NULL DEFAULT 0,
+    lapses INT NOT NULL DEFAULT 0,
+    leech BOOLEAN NOT NULL DEFAULT FALSE,
+    suspended BOOLEAN NOT NULL DEFAULT FALSE,
+    buried_until BIGINT NOT NULL DEFAULT 0,
+    ordinal INT NOT NULL DEFAULT 0,
+    direction VARCHAR(8) NOT NULL DEFAULT 'forward',
+    PRIMARY KEY (user_id, card_id, ordinal, direction)
+);
+
+
+-- Modify the 'card_lineage' table
+-- Links cards of a fork (stack_id) to the upstream cards they came from, base is the upstream.rs CardContent (JSON) when it was forked or last pulled
+-- card_id becomes NULL when the fork owner deletes the card, upstream_card_id is not a foreign key so cards deleted upstream can be found
+CREATE TABLE card_lineage (
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    upstream_card_id VARCHAR(10) NOT NULL,
+    card_id VARCHAR(10) NULL,
+    FOREIGN KEY (card_id) REFERENCES cards(unique_id) ON DELETE SET NULL,
+    base TEXT NOT NULL,
+    PRIMARY KEY (stack_id, upstream_card_id)
+);
+
+
+
+-- Modify the 'review_log' table
+-- Append only, card_id and stack_id are not foreign keys so the history outlives deleted cards
+CREATE TABLE review_log (
+    unique_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    user_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    card_id VARCHAR(10) NOT NULL,
+    stack_id VARCHAR(10) NOT NULL,
+    reviewed_at BIGINT NOT NULL,
+    grade INT NOT NULL,
+    previous_interval INT NOT NULL,
+    next_interval INT NOT NULL,
+    time_taken INT NOT NULL,
+    phase VARCHAR(10) NOT NULL,
+    ordinal INT NOT NULL DEFAULT 0,
+    direction VARCHAR(8) NOT NULL DEFAULT 'forward',
+    INDEX (user_id, reviewed_at)
+);
+
+
+-- Modify the 'study_sessions' table
+-- queue is a comma separated list of card ids (card_id:ordinal:direction for cloze sub-cards and backward cards), stack_id is NULL for sessions over all stacks of the user
+-- mode is either 'review' or 'cram', cram sessions don't change card states
+CREATE TABLE study_sessions (
+    unique_id VARCHAR(10) PRIMARY KEY,
+    user_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    stack_id VARCHAR(10),
+    started_at BIGINT NOT NULL,
+    finished_at BIGINT,
+    queue TEXT NOT NULL,
+    new_count INT NOT NULL,
+    position INT NOT NULL DEFAULT 0,
+    answered_count INT NOT NULL DEFAULT 0,
+    correct_count INT NOT NULL DEFAULT 0,
+    mode VARCHAR(16) NOT NULL DEFAULT 'review'
+);
+
+DELIMITER //
+CREATE FUNCTION generate_session_id() RETURNS VARCHAR(10) DETERMINISTIC
+BEGIN
+    DECLARE collision_count INT DEFAULT 0;
+    DECLARE new_id VARCHAR(10);
+    SET new_id = generate_random_base64_10char();
+    -- Check for collisions and regenerate the unique_id if needed
+    SELECT COUNT(*) INTO collision_count FROM study_sessions WHERE unique_id = new_id;
+    WHILE collision_count > 0 DO
+        SET new_id = generate_random_base64_10char();
+        SELECT COUNT(*) INTO collision_count FROM study_sessions WHERE unique_id = new_id;
+    END WHILE;
+
+    RETURN new_id;
+END //
+DELIMITER ;
+
+
+-- Modify the 'quiz_attempts' table
+-- kind is either 'multiple_choice' or 'test', questions are stored as generated (JSON),
+-- answers are comma separated option indexes for multiple choice quizzes and stored as submitted (JSON) for tests
+CREATE TABLE quiz_attempts (
+    unique_id VARCHAR(10) PRIMARY KEY,
+    user_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    seed INT UNSIGNED NOT NULL,
+    questions TEXT NOT NULL,
+    answers TEXT,
+    score INT,
+    question_count INT NOT NULL,
+    created_at BIGINT NOT NULL,
+    submitted_at BIGINT,
+    kind VARCHAR(16) NOT NULL DEFAULT 'multiple_choice',
+    max_score INT NOT NULL
+);
+
+DELIMITER //
+CREATE FUNCTION generate_quiz_id() RETURNS VARCHAR(10) DETERMINISTIC
+BEGIN
+    DECLARE collision_count INT DEFAULT 0;
+    DECLARE new_id VARCHAR(10);
+    SET new_id = generate_random_base64_10char();
+    -- Check for collisions and regenerate the unique_id if needed
+    SELECT COUNT(*) INTO collision_count FROM quiz_attempts WHERE unique_id = new_id;
+    WHILE collision_count > 0 DO
+        SET new_id = generate_random_base64_10char();
+        SELECT COUNT(*) INTO collision_count FROM quiz_attempts WHERE unique_id = new_id;
+    END WHILE;
+
+    RETURN new_id;
+END //
+DELIMITER ;
+
+
+-- Modify the 'suggestions' table
+-- kind is 'new', 'edit' or 'delete', card_id is the card an edit or deletion is for and NULL for new cards or once the card was deleted
+-- status is 'pending', 'accepted' or 'rejected', resolved_at is set when the owner accepts or rejects
+CREATE TABLE suggestions (
+    unique_id VARCHAR(10) PRIMARY KEY,
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    author_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (author_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    kind VARCHAR(8) NOT NULL,
+    card_id VARCHAR(10) NULL,
+    FOREIGN KEY (card_id) REFERENCES cards(unique_id) ON DELETE SET NULL,
+    frontside TEXT NOT NULL,
+    backside TEXT NOT NULL,
+    card_type VARCHAR(16) NOT NULL DEFAULT 'basic',
+    status VARCHAR(10) NOT NULL DEFAULT 'pending',
+    created_at BIGINT NOT NULL,
+    resolved_at BIGINT
+);
+
+DELIMITER //
+CREATE FUNCTION generate_suggestion_id() RETURNS VARCHAR(10) DETERMINISTIC
+BEGIN
+    DECLARE collision_count INT DEFAULT 0;
+    DECLARE new_id VARCHAR(10);
+    SET new_id = generate_random_base64_10char();
+    -- Check for collisions and regenerate the unique_id if needed
+    SELECT COUNT(*) INTO collision_count FROM suggestions WHERE unique_id = new_id;
+    WHILE collision_count > 0 DO
+        SET new_id = generate_random_base64_10char();
+        SELECT COUNT(*) INTO collision_count FROM suggestions WHERE unique_id = new_id;
+    END WHILE;
+
+    RETURN new_id;
+END //
+DELIMITER ;
+
+
+-- Modify the 'stack_members' table
+-- role is 'editor' or 'viewer', the owner of a stack is never a member, accepted becomes TRUE once the invited user accepts
+CREATE TABLE stack_members (
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    user_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(unique_id) ON DELETE CASCADE,
+    role VARCHAR(8) NOT NULL,
+    accepted BOOL NOT NULL DEFAULT FALSE,
+    invited_at BIGINT NOT NULL,
+    PRIMARY KEY (stack_id, user_id),
+    INDEX (user_id)
+);
+
+
+-- Modify the 'share_tokens' table
+-- Anyone with a token can view and fork the stack, tokens are generated by the backend and revoked by deleting them
+-- expires_at is NULL for tokens that stay valid until they are revoked
+CREATE TABLE share_tokens (
+    token VARCHAR(32) PRIMARY KEY,
+    stack_id VARCHAR(10) NOT NULL,
+    FOREIGN KEY (stack_id) REFERENCES stacks(unique_id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT,
+    INDEX (stack_id)
+);
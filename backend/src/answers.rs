@@ -0,0 +1,164 @@
+//! ## Typed answer checking
+//!
+//! Answers are compared after normalization (case, whitespace, punctuation and diacritics),
+//! so `Zażółć gęślą jaźń!` and `zazolc gesla jazn` are the same answer.
+
+/// Answers longer than this are not compared, edit distance is quadratic
+pub const MAX_ANSWER_LENGTH: usize = 1000;
+
+pub const DIFF_MATCH: &str = "match";
+/// Typed by the user but not in the expected answer
+pub const DIFF_EXTRA: &str = "extra";
+/// In the expected answer but not typed by the user
+pub const DIFF_MISSING: &str = "missing";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffSegment {
+    pub kind: &'static str,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnswerCheck {
+    pub correct: bool,
+    /// 0.0 - 1.0, 1.0 means the normalized answers are the same
+    pub similarity: f64,
+    pub suggested_grade: u8,
+    pub diff: Vec<DiffSegment>,
+}
+
+/// ## Compares a typed answer to the expected one.
+///
+/// ### Arguments
+///
+/// * `expected` - &str, backside of the card
+/// * `typed` - &str, what the user typed
+pub fn check_answer(expected: &str, typed: &str) -> AnswerCheck {
+    let expected: Vec<char> = normalize(expected).chars().collect();
+    let typed: Vec<char> = normalize(typed).chars().collect();
+
+    let longest = expected.len().max(typed.len());
+    let similarity = if longest == 0 {
+        1.0
+    } else {
+        1.0 - edit_distance(&expected, &typed) as f64 / longest as f64
+    };
+
+    AnswerCheck {
+        correct: expected == typed,
+        similarity,
+        suggested_grade: suggested_grade(similarity),
+        diff: diff(&expected, &typed),
+    }
+}
+
+/// Lower case without punctuation and diacritics, words separated by single spaces, ex. `Don't  stop!` becomes `dont stop`
+pub fn normalize(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            output.push_str(&fold_diacritic(c));
+        } else if c.is_whitespace() && ! output.ends_with(' ') {
+            output.push(' ');
+        }
+        // Punctuation and other symbols are dropped
+    }
+    output.trim().to_owned()
+}
+
+/// Maps common latin letters with diacritics to their base letters
+fn fold_diacritic(c: char) -> String {
+    let base = match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ą' | 'ā' | 'ă' => "a",
+        'ç' | 'ć' | 'č' | 'ĉ' | 'ċ' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ę' | 'ē' | 'ě' | 'ė' => "e",
+        'ğ' | 'ĝ' | 'ġ' | 'ģ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+        'ł' | 'ľ' | 'ĺ' | 'ļ' => "l",
+        'ñ' | 'ń' | 'ň' | 'ņ' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'ŕ' | 'ř' => "r",
+        'ś' | 'š' | 'ş' | 'ŝ' | 'ș' => "s",
+        'ť' | 'ţ' | 'ț' => "t",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        _ => return c.to_string(),
+    };
+    base.to_owned()
+}
+
+/// Levenshtein distance
+pub fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// - 1.0 -> 5
+/// - 0.9 -> 4, a typo
+/// - 0.75 -> 3
+/// - 0.5 -> 2
+/// - anything typed -> 1
+pub fn suggested_grade(similarity: f64) -> u8 {
+    match similarity {
+        s if s >= 1.0 => 5,
+        s if s >= 0.9 => 4,
+        s if s >= 0.75 => 3,
+        s if s >= 0.5 => 2,
+        s if s > 0.0 => 1,
+        _ => 0,
+    }
+}
+
+/// Character diff based on the longest common subsequence, neighbouring characters of the same kind are merged
+fn diff(expected: &[char], typed: &[char]) -> Vec<DiffSegment> {
+    // lcs[i][j] - longest common subsequence of expected[i..] and typed[j..]
+    let mut lcs = vec![vec![0usize; typed.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..typed.len()).rev() {
+            lcs[i][j] = if expected[i] == typed[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut segments: Vec<DiffSegment> = Vec::new();
+    let mut push = |kind: &'static str, c: char| {
+        match segments.last_mut() {
+            Some(last) if last.kind == kind => last.text.push(c),
+            _ => segments.push(DiffSegment { kind, text: c.to_string() }),
+        }
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < typed.len() {
+        if i < expected.len() && j < typed.len() && expected[i] == typed[j] {
+            push(DIFF_MATCH, expected[i]);
+            i += 1;
+            j += 1;
+        } else if j < typed.len() && (i == expected.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            push(DIFF_EXTRA, typed[j]);
+            j += 1;
+        } else {
+            push(DIFF_MISSING, expected[i]);
+            i += 1;
+        }
+    }
+    segments
+}
@@ -1,4 +1,4 @@
-use crate::models;
+use crate::{models, scheduler, answers, quiz, notes, transfer, upstream, suggestions, members};
 use std::fmt;
 
 // --- request type
@@ -42,6 +42,8 @@ pub enum UsersRequestType {
     UpdateUser,
     #[serde(rename = "delete_user")]
     DeleteUser,
+    #[serde(rename = "optimize_scheduler")]
+    OptimizeScheduler,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -52,6 +54,10 @@ pub struct UserRequestContent {
     pub password: Option<String>,
     pub date_of_registration: Option<u64>,
     pub country: Option<String>,
+    pub scheduler: Option<String>,
+    pub fsrs_parameters: Option<Vec<f64>>,
+    pub new_cards_per_day: Option<i32>,
+    pub reviews_per_day: Option<i32>,
 }
 
 impl UserRequestContent {
@@ -62,7 +68,11 @@ impl UserRequestContent {
             username: None,
             password: None,
             date_of_registration: None,
-            country: None
+            country: None,
+            scheduler: None,
+            fsrs_parameters: None,
+            new_cards_per_day: None,
+            reviews_per_day: None,
         }
     }
 }
@@ -105,6 +115,62 @@ pub enum CardsRequestType {
     DeleteStack,
     #[serde(rename = "delete_card")]
     DeleteCard,
+    #[serde(rename = "get_due_cards")]
+    GetDueCards,
+    #[serde(rename = "submit_review")]
+    SubmitReview,
+    #[serde(rename = "get_review_history")]
+    GetReviewHistory,
+    #[serde(rename = "export_review_history")]
+    ExportReviewHistory,
+    #[serde(rename = "get_leeches")]
+    GetLeeches,
+    #[serde(rename = "suspend_card")]
+    SuspendCard,
+    #[serde(rename = "unsuspend_card")]
+    UnsuspendCard,
+    #[serde(rename = "bury_card")]
+    BuryCard,
+    #[serde(rename = "check_answer")]
+    CheckAnswer,
+    #[serde(rename = "get_note_types")]
+    GetNoteTypes,
+    #[serde(rename = "create_note_type")]
+    CreateNoteType,
+    #[serde(rename = "update_note_type")]
+    UpdateNoteType,
+    #[serde(rename = "delete_note_type")]
+    DeleteNoteType,
+    #[serde(rename = "fork_stack")]
+    ForkStack,
+    #[serde(rename = "get_upstream_changes")]
+    GetUpstreamChanges,
+    #[serde(rename = "pull_upstream_changes")]
+    PullUpstreamChanges,
+    #[serde(rename = "create_suggestion")]
+    CreateSuggestion,
+    #[serde(rename = "get_suggestions")]
+    GetSuggestions,
+    #[serde(rename = "accept_suggestion")]
+    AcceptSuggestion,
+    #[serde(rename = "reject_suggestion")]
+    RejectSuggestion,
+    #[serde(rename = "invite_member")]
+    InviteMember,
+    #[serde(rename = "accept_invite")]
+    AcceptInvite,
+    #[serde(rename = "remove_member")]
+    RemoveMember,
+    #[serde(rename = "get_members")]
+    GetMembers,
+    #[serde(rename = "get_memberships")]
+    GetMemberships,
+    #[serde(rename = "create_share_token")]
+    CreateShareToken,
+    #[serde(rename = "get_share_tokens")]
+    GetShareTokens,
+    #[serde(rename = "revoke_share_token")]
+    RevokeShareToken,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -116,6 +182,36 @@ pub struct CardsRequestContent {
     pub visibility: Option<bool>,
     pub frontside: Option<String>,
     pub backside: Option<String>,
+    pub grade: Option<u8>,
+    pub time_taken: Option<i32>,
+    pub format: Option<String>,
+    pub learning_steps: Option<String>,
+    pub relearning_steps: Option<String>,
+    pub leech_threshold: Option<i32>,
+    pub leech_suspend: Option<bool>,
+    pub scheduler: Option<String>,
+    pub leitner_intervals: Option<String>,
+    pub answer: Option<String>,
+    pub card_type: Option<String>,
+    pub ordinal: Option<i32>,
+    pub direction: Option<String>,
+    pub directions: Option<String>,
+    pub note_type_id: Option<String>,
+    pub fields: Option<notes::Fields>,
+    pub field_names: Option<Vec<String>>,
+    pub templates: Option<Vec<notes::Template>>,
+    /// Ids of upstream cards
+    pub card_ids: Option<Vec<String>>,
+    /// Kind of a suggestion
+    pub kind: Option<String>,
+    pub card_id: Option<String>,
+    pub username: Option<String>,
+    pub user_id: Option<String>,
+    /// Role of a stack member
+    pub role: Option<String>,
+    /// Share token of a stack
+    pub token: Option<String>,
+    pub expires_at: Option<i64>,
 }
 
 impl CardsRequestContent {
@@ -128,6 +224,32 @@ impl CardsRequestContent {
             visibility: None,
             frontside: None,
             backside: None,
+            grade: None,
+            time_taken: None,
+            format: None,
+            learning_steps: None,
+            relearning_steps: None,
+            leech_threshold: None,
+            leech_suspend: None,
+            scheduler: None,
+            leitner_intervals: None,
+            answer: None,
+            card_type: None,
+            ordinal: None,
+            direction: None,
+            directions: None,
+            note_type_id: None,
+            fields: None,
+            field_names: None,
+            templates: None,
+            card_ids: None,
+            kind: None,
+            card_id: None,
+            username: None,
+            user_id: None,
+            role: None,
+            token: None,
+            expires_at: None,
         }
     }
 }
@@ -146,6 +268,197 @@ pub struct CardsRequest {
     pub content: Option<CardsRequestContent>,
 }
 
+// ---
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum StudyRequestType {
+    #[serde(rename = "open_session")]
+    OpenSession,
+    #[serde(rename = "next_card")]
+    NextCard,
+    #[serde(rename = "answer_card")]
+    AnswerCard,
+    #[serde(rename = "close_session")]
+    CloseSession,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StudyRequestContent {
+    pub unique_id: Option<String>,
+    pub stack_id: Option<String>,
+    pub grade: Option<u8>,
+    pub time_taken: Option<i32>,
+    pub mode: Option<String>,
+    pub tags: Option<String>,
+    pub min_failures: Option<i32>,
+    pub reviewed_within_days: Option<i32>,
+    pub order: Option<String>,
+    pub card_id: Option<String>,
+    pub ordinal: Option<i32>,
+    pub direction: Option<String>,
+}
+
+impl StudyRequestContent {
+    pub fn empty() -> Self {
+        StudyRequestContent {
+            unique_id: None,
+            stack_id: None,
+            grade: None,
+            time_taken: None,
+            mode: None,
+            tags: None,
+            min_failures: None,
+            reviewed_within_days: None,
+            order: None,
+            card_id: None,
+            ordinal: None,
+            direction: None,
+        }
+    }
+}
+
+pub trait ConvertStudyFromOptional {
+    fn try_from_optional(optional: &StudyRequestContent) -> Result<Self, &'static str>
+    where
+        Self: Sized;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StudyRequest {
+    #[serde(rename = "type")]
+    pub request_type: StudyRequestType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<StudyRequestContent>,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum QuizRequestType {
+    #[serde(rename = "generate_quiz")]
+    GenerateQuiz,
+    #[serde(rename = "submit_quiz")]
+    SubmitQuiz,
+    #[serde(rename = "generate_test")]
+    GenerateTest,
+    #[serde(rename = "submit_test")]
+    SubmitTest,
+    #[serde(rename = "get_test_attempts")]
+    GetTestAttempts,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuizRequestContent {
+    pub unique_id: Option<String>,
+    pub stack_id: Option<String>,
+    pub count: Option<i32>,
+    pub seed: Option<u32>,
+    pub answers: Option<Vec<i32>>,
+    pub test_answers: Option<Vec<Option<quiz::TestAnswer>>>,
+}
+
+impl QuizRequestContent {
+    pub fn empty() -> Self {
+        QuizRequestContent {
+            unique_id: None,
+            stack_id: None,
+            count: None,
+            seed: None,
+            answers: None,
+            test_answers: None,
+        }
+    }
+}
+
+pub trait ConvertQuizFromOptional {
+    fn try_from_optional(optional: &QuizRequestContent) -> Result<Self, &'static str>
+    where
+        Self: Sized;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuizRequest {
+    #[serde(rename = "type")]
+    pub request_type: QuizRequestType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<QuizRequestContent>,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum TransferRequestType {
+    #[serde(rename = "import_apkg")]
+    ImportApkg,
+    #[serde(rename = "export_apkg")]
+    ExportApkg,
+    #[serde(rename = "import_cards")]
+    ImportCards,
+    #[serde(rename = "export_cards")]
+    ExportCards,
+    #[serde(rename = "import_text")]
+    ImportText,
+    #[serde(rename = "import_markdown")]
+    ImportMarkdown,
+    #[serde(rename = "export_markdown")]
+    ExportMarkdown,
+    #[serde(rename = "export_account")]
+    ExportAccount,
+    #[serde(rename = "import_account")]
+    ImportAccount,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferRequestContent {
+    /// Base64 encoded file
+    pub file: Option<String>,
+    pub include_history: Option<bool>,
+    pub stack_id: Option<String>,
+    /// CSV, TSV, plain text, Markdown or an account archive, not encoded
+    pub text: Option<String>,
+    pub delimiter: Option<String>,
+    /// Empty when values are never quoted
+    pub quote: Option<String>,
+    pub header: Option<bool>,
+    pub columns: Option<Vec<String>>,
+    pub note_type_id: Option<String>,
+    pub term_separator: Option<String>,
+    pub card_separator: Option<String>,
+    /// Only returns the parsed cards
+    pub preview: Option<bool>,
+}
+
+impl TransferRequestContent {
+    pub fn empty() -> Self {
+        TransferRequestContent {
+            file: None,
+            include_history: None,
+            stack_id: None,
+            text: None,
+            delimiter: None,
+            quote: None,
+            header: None,
+            columns: None,
+            note_type_id: None,
+            term_separator: None,
+            card_separator: None,
+            preview: None,
+        }
+    }
+}
+
+pub trait ConvertTransferFromOptional {
+    fn try_from_optional(optional: &TransferRequestContent) -> Result<Self, &'static str>
+    where
+        Self: Sized;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferRequest {
+    #[serde(rename = "type")]
+    pub request_type: TransferRequestType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<TransferRequestContent>,
+}
+
 // --- Response
 use serde_derive::Serialize;
 
@@ -204,16 +517,32 @@ pub struct UserData {
     pub username: String,
     pub date_of_registration: i64,
     pub country: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fsrs_parameters: Option<Vec<f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_cards_per_day: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reviews_per_day: Option<i32>,
 }
 
 impl UserData {
     pub fn from(user: models::User, authorized: bool) -> Self {
+        let fsrs_parameters = if authorized {
+            Some(scheduler::fsrs::Fsrs::from_parameters_str(&user.fsrs_parameters).parameters.to_vec())
+        } else { None };
+
         UserData {
             unique_id: user.unique_id,
             email: if authorized { Some(user.email) } else { None },
             username: user.username,
             date_of_registration: user.date_of_registration,
             country: user.country,
+            scheduler: if authorized { Some(user.scheduler) } else { None },
+            fsrs_parameters,
+            new_cards_per_day: if authorized { Some(user.new_cards_per_day) } else { None },
+            reviews_per_day: if authorized { Some(user.reviews_per_day) } else { None },
         }
     }
 }
@@ -225,6 +554,15 @@ pub struct StackData {
     pub cards_count: i32,
     pub tags: String,
     pub visibility: bool,
+    pub learning_steps: String,
+    pub relearning_steps: String,
+    pub leech_threshold: i32,
+    pub leech_suspend: bool,
+    pub scheduler: String,
+    pub leitner_intervals: String,
+    pub directions: String,
+    pub upstream_id: Option<String>,
+    pub forks_count: i32,
 }
 
 impl From<models::StackFull> for StackData {
@@ -235,6 +573,15 @@ impl From<models::StackFull> for StackData {
             cards_count: val.cards_count,
             tags: val.tags,
             visibility: val.visibility,
+            learning_steps: val.learning_steps,
+            relearning_steps: val.relearning_steps,
+            leech_threshold: val.leech_threshold,
+            leech_suspend: val.leech_suspend,
+            scheduler: val.scheduler,
+            leitner_intervals: val.leitner_intervals,
+            directions: val.directions,
+            upstream_id: val.upstream_id,
+            forks_count: val.forks_count,
         }
     }
 }
@@ -244,14 +591,539 @@ pub struct CardData {
     pub unique_id: String,
     pub frontside: String,
     pub backside: String,
+    pub card_type: String,
+    /// Set for stored cards, sub-cards served for studying get ordinal and direction instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_type_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<notes::Fields>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ordinal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<String>,
 }
 
 impl From<models::Card> for CardData {
     fn from(val: models::Card) -> Self {
+        let fields = val.field_values();
         CardData {
             unique_id: val.unique_id,
             frontside: val.frontside,
             backside: val.backside,
+            card_type: val.card_type,
+            directions: Some(val.directions),
+            note_type_id: Some(val.note_type_id),
+            fields: Some(fields),
+            ordinal: None,
+            direction: None,
+        }
+    }
+}
+
+impl From<models::SubCard> for CardData {
+    fn from(val: models::SubCard) -> Self {
+        CardData {
+            unique_id: val.card_id,
+            frontside: val.frontside,
+            backside: val.backside,
+            card_type: val.card_type,
+            directions: None,
+            note_type_id: None,
+            fields: None,
+            ordinal: Some(val.ordinal),
+            direction: Some(val.direction),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteTypeData {
+    /// Empty for the built-in Basic note type
+    pub unique_id: String,
+    pub name: String,
+    pub fields: Vec<String>,
+    pub templates: Vec<notes::Template>,
+}
+
+impl NoteTypeData {
+    pub fn basic() -> Self {
+        let (fields, templates) = notes::basic_note_type();
+        NoteTypeData {
+            unique_id: notes::BASIC_NOTE_TYPE_ID.to_owned(),
+            name: notes::BASIC_NOTE_TYPE_NAME.to_owned(),
+            fields,
+            templates,
+        }
+    }
+}
+
+impl From<models::NoteType> for NoteTypeData {
+    fn from(val: models::NoteType) -> Self {
+        NoteTypeData {
+            fields: val.field_names(),
+            templates: val.template_list(),
+            unique_id: val.unique_id,
+            name: val.name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WarningData {
+    pub item: String,
+    pub message: String,
+}
+
+impl From<transfer::Warning> for WarningData {
+    fn from(val: transfer::Warning) -> Self {
+        WarningData {
+            item: val.item,
+            message: val.message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamChangeData {
+    pub upstream_card_id: String,
+    pub card_id: Option<String>,
+    pub kind: String,
+    pub base: Option<upstream::CardContent>,
+    pub local: Option<upstream::CardContent>,
+    pub upstream: Option<upstream::CardContent>,
+    pub merged: Option<upstream::CardContent>,
+    pub conflicts: Vec<String>,
+}
+
+impl From<upstream::Change> for UpstreamChangeData {
+    fn from(val: upstream::Change) -> Self {
+        UpstreamChangeData {
+            upstream_card_id: val.upstream_card_id,
+            card_id: val.card_id,
+            kind: val.kind.to_owned(),
+            base: val.base,
+            local: val.local,
+            upstream: val.upstream,
+            merged: val.merged,
+            conflicts: val.conflicts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SuggestionData {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub author_id: String,
+    pub kind: String,
+    pub card_id: Option<String>,
+    pub frontside: String,
+    pub backside: String,
+    pub card_type: String,
+    pub status: String,
+    pub created_at: i64,
+    pub resolved_at: Option<i64>,
+    /// Card as it is now, None for new cards
+    pub current: Option<CardData>,
+    /// Word diffs of the current card and the suggestion
+    pub frontside_diff: Vec<DiffSegmentData>,
+    pub backside_diff: Vec<DiffSegmentData>,
+}
+
+impl SuggestionData {
+    pub fn new(suggestion: models::Suggestion, current: Option<models::Card>) -> Self {
+        let (current_frontside, current_backside) = match &current {
+            Some(card) => (card.frontside.as_str(), card.backside.as_str()),
+            None => ("", ""),
+        };
+        let diff = |current: &str, suggested: &str| -> Vec<DiffSegmentData> {
+            suggestions::diff(current, suggested).into_iter().map(|segment| DiffSegmentData {
+                kind: segment.kind.to_owned(),
+                text: segment.text,
+            }).collect()
+        };
+        SuggestionData {
+            frontside_diff: diff(current_frontside, &suggestion.frontside),
+            backside_diff: diff(current_backside, &suggestion.backside),
+            current: current.map(CardData::from),
+            unique_id: suggestion.unique_id,
+            stack_id: suggestion.stack_id,
+            author_id: suggestion.author_id,
+            kind: suggestion.kind,
+            card_id: suggestion.card_id,
+            frontside: suggestion.frontside,
+            backside: suggestion.backside,
+            card_type: suggestion.card_type,
+            status: suggestion.status,
+            created_at: suggestion.created_at,
+            resolved_at: suggestion.resolved_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberData {
+    pub stack_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub accepted: bool,
+    /// None for the owner
+    pub invited_at: Option<i64>,
+}
+
+impl MemberData {
+    pub fn new(member: models::StackMember, username: String) -> Self {
+        MemberData {
+            stack_id: member.stack_id,
+            user_id: member.user_id,
+            username,
+            role: member.role,
+            accepted: member.accepted,
+            invited_at: Some(member.invited_at),
+        }
+    }
+
+    pub fn owner(stack: &models::StackFull, username: String) -> Self {
+        MemberData {
+            stack_id: stack.unique_id.clone(),
+            user_id: stack.owner_id.clone(),
+            username,
+            role: members::ROLE_OWNER.to_owned(),
+            accepted: true,
+            invited_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareTokenData {
+    pub token: String,
+    pub stack_id: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl From<models::ShareToken> for ShareTokenData {
+    fn from(val: models::ShareToken) -> Self {
+        ShareTokenData {
+            token: val.token,
+            stack_id: val.stack_id,
+            created_at: val.created_at,
+            expires_at: val.expires_at,
+        }
+    }
+}
+
+/// Why a row of an imported file can't become a card
+#[derive(Debug, Clone, Serialize)]
+pub struct RowErrorData {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CardStateData {
+    pub card_id: String,
+    pub ordinal: i32,
+    pub direction: String,
+    pub ease_factor: f64,
+    pub interval: i32,
+    pub repetitions: i32,
+    pub due: i64,
+    pub phase: String,
+    pub lapses: i32,
+    pub leech: bool,
+    pub suspended: bool,
+    pub buried_until: i64,
+}
+
+impl From<models::CardState> for CardStateData {
+    fn from(val: models::CardState) -> Self {
+        CardStateData {
+            card_id: val.card_id,
+            ordinal: val.ordinal,
+            direction: val.direction,
+            ease_factor: val.ease_factor,
+            interval: val.interval_days,
+            repetitions: val.repetitions,
+            due: val.due,
+            phase: val.phase,
+            lapses: val.lapses,
+            leech: val.leech,
+            suspended: val.suspended,
+            buried_until: val.buried_until,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewData {
+    pub unique_id: i64,
+    pub card_id: String,
+    pub stack_id: String,
+    pub reviewed_at: i64,
+    pub grade: i32,
+    pub previous_interval: i32,
+    pub next_interval: i32,
+    pub time_taken: i32,
+    pub phase: String,
+    pub ordinal: i32,
+    pub direction: String,
+}
+
+impl From<models::ReviewLogFull> for ReviewData {
+    fn from(val: models::ReviewLogFull) -> Self {
+        ReviewData {
+            unique_id: val.unique_id,
+            card_id: val.card_id,
+            stack_id: val.stack_id,
+            reviewed_at: val.reviewed_at,
+            grade: val.grade,
+            previous_interval: val.previous_interval,
+            next_interval: val.next_interval,
+            time_taken: val.time_taken,
+            phase: val.phase,
+            ordinal: val.ordinal,
+            direction: val.direction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StudySessionData {
+    pub unique_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_id: Option<String>,
+    pub started_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<i64>,
+    pub cards_count: i32,
+    pub new_count: i32,
+    pub answered_count: i32,
+    pub correct_count: i32,
+    pub mode: String,
+}
+
+impl From<models::StudySession> for StudySessionData {
+    fn from(val: models::StudySession) -> Self {
+        StudySessionData {
+            cards_count: val.queue_items().len() as i32,
+            unique_id: val.unique_id,
+            stack_id: val.stack_id,
+            started_at: val.started_at,
+            finished_at: val.finished_at,
+            new_count: val.new_count,
+            answered_count: val.answered_count,
+            correct_count: val.correct_count,
+            mode: val.mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuizQuestionData {
+    pub card_id: String,
+    pub prompt: String,
+    pub options: Vec<String>,
+    /// Only revealed once the quiz is submitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correct: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuizData {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub seed: u32,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    pub question_count: i32,
+    pub questions: Vec<QuizQuestionData>,
+}
+
+impl QuizData {
+    pub fn new(attempt: models::QuizAttempt, questions: Vec<quiz::Question>) -> Self {
+        let submitted = attempt.submitted_at.is_some();
+        let selected: Vec<i32> = attempt.answers.as_deref().map(quiz::parse_selected).unwrap_or_default();
+
+        QuizData {
+            unique_id: attempt.unique_id,
+            stack_id: attempt.stack_id,
+            seed: attempt.seed,
+            created_at: attempt.created_at,
+            submitted_at: attempt.submitted_at,
+            score: attempt.score,
+            question_count: attempt.question_count,
+            questions: questions.into_iter().enumerate().map(|(i, question)| QuizQuestionData {
+                card_id: question.card_id,
+                prompt: question.prompt,
+                options: question.options,
+                correct: submitted.then_some(question.correct),
+                selected: selected.get(i).copied(),
+            }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestQuestionData {
+    pub id: usize,
+    #[serde(rename = "type")]
+    pub question_type: String,
+    pub card_ids: Vec<String>,
+    pub prompts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shown_answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<String>>,
+    pub max_points: i32,
+    // Feedback, only present once the test is submitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<quiz::TestAnswer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correct_answer: Option<quiz::TestAnswer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestData {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub seed: u32,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    pub max_score: i32,
+    pub questions: Vec<TestQuestionData>,
+}
+
+impl TestData {
+    pub fn new(attempt: models::QuizAttempt, questions: Vec<quiz::TestQuestion>) -> Self {
+        let submitted: Option<Vec<Option<quiz::TestAnswer>>> = attempt.answers.as_deref()
+            .and_then(|answers| serde_json::from_str(answers).ok());
+        let feedback = submitted.as_ref().map(|answers| quiz::grade_test(&questions, answers));
+
+        let questions = questions.into_iter().enumerate().map(|(id, question)| {
+            let max_points = question.max_points();
+            let (question_type, card_ids, prompts, shown_answer, options, correct_answer) = match question {
+                quiz::TestQuestion::TrueFalse { card_id, prompt, shown_answer, correct } => {
+                    ("true_false", vec![card_id], vec![prompt], Some(shown_answer), None, quiz::TestAnswer::TrueFalse(correct))
+                }
+                quiz::TestQuestion::Matching { card_ids, prompts, options, correct } => {
+                    let correct = correct.into_iter().map(|option| option as i32).collect();
+                    ("matching", card_ids, prompts, None, Some(options), quiz::TestAnswer::Matching(correct))
+                }
+                quiz::TestQuestion::Written { card_id, prompt, expected } => {
+                    ("written", vec![card_id], vec![prompt], None, None, quiz::TestAnswer::Written(expected))
+                }
+            };
+            let question_feedback = feedback.as_ref().and_then(|feedback| feedback.get(id));
+
+            TestQuestionData {
+                id,
+                question_type: question_type.to_owned(),
+                card_ids,
+                prompts,
+                shown_answer,
+                options,
+                max_points,
+                answer: submitted.as_ref().and_then(|answers| answers.get(id).cloned().flatten()),
+                points: question_feedback.map(|feedback| feedback.points),
+                correct_answer: question_feedback.map(|_| correct_answer),
+                similarity: question_feedback.and_then(|feedback| feedback.similarity),
+            }
+        }).collect();
+
+        TestData {
+            unique_id: attempt.unique_id,
+            stack_id: attempt.stack_id,
+            seed: attempt.seed,
+            created_at: attempt.created_at,
+            submitted_at: attempt.submitted_at,
+            score: attempt.score,
+            max_score: attempt.max_score,
+            questions,
+        }
+    }
+}
+
+/// Summary of a submitted quiz or test, without the questions
+#[derive(Debug, Clone, Serialize)]
+pub struct AttemptData {
+    pub unique_id: String,
+    pub user_id: String,
+    pub stack_id: String,
+    pub kind: String,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    pub max_score: i32,
+    pub question_count: i32,
+}
+
+impl From<models::QuizAttempt> for AttemptData {
+    fn from(val: models::QuizAttempt) -> Self {
+        AttemptData {
+            unique_id: val.unique_id,
+            user_id: val.user_id,
+            stack_id: val.stack_id,
+            kind: val.kind,
+            created_at: val.created_at,
+            submitted_at: val.submitted_at,
+            score: val.score,
+            max_score: val.max_score,
+            question_count: val.question_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffSegmentData {
+    pub kind: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnswerCheckData {
+    pub card_id: String,
+    pub ordinal: i32,
+    pub direction: String,
+    pub expected: String,
+    pub correct: bool,
+    pub similarity: f64,
+    pub suggested_grade: u8,
+    pub diff: Vec<DiffSegmentData>,
+}
+
+impl AnswerCheckData {
+    pub fn new(card: models::SubCard, check: answers::AnswerCheck) -> Self {
+        AnswerCheckData {
+            card_id: card.card_id,
+            ordinal: card.ordinal,
+            direction: card.direction,
+            expected: card.answer,
+            correct: check.correct,
+            similarity: check.similarity,
+            suggested_grade: check.suggested_grade,
+            diff: check.diff.into_iter().map(|segment| DiffSegmentData {
+                kind: segment.kind.to_owned(),
+                text: segment.text,
+            }).collect(),
         }
     }
 }
@@ -267,6 +1139,34 @@ pub struct ResponseContent {
     #[serde(skip_serializing_if = "Option::is_none")]
     cards: Option<Vec<CardData>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    note_types: Option<Vec<NoteTypeData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<WarningData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row_errors: Option<Vec<RowErrorData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_changes: Option<Vec<UpstreamChangeData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Vec<SuggestionData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    members: Option<Vec<MemberData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    share_tokens: Option<Vec<ShareTokenData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    card_states: Option<Vec<CardStateData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviews: Option<Vec<ReviewData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<StudySessionData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer_check: Option<AnswerCheckData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quiz: Option<QuizData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    test: Option<TestData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<Vec<AttemptData>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     authenticated: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     unique_id: Option<String>,
@@ -274,7 +1174,28 @@ pub struct ResponseContent {
 
 impl ResponseContent {
     pub fn new() -> Self {
-        ResponseContent { errors: None, user: None, stacks: None, cards: None, authenticated: None, unique_id: None }
+        ResponseContent {
+            errors: None,
+            user: None,
+            stacks: None,
+            cards: None,
+            note_types: None,
+            warnings: None,
+            row_errors: None,
+            upstream_changes: None,
+            suggestions: None,
+            members: None,
+            share_tokens: None,
+            card_states: None,
+            reviews: None,
+            session: None,
+            answer_check: None,
+            quiz: None,
+            test: None,
+            attempts: None,
+            authenticated: None,
+            unique_id: None,
+        }
     }
 
     // pub fn push_err(&mut self, error: ResponseErrorValues) {
@@ -296,6 +1217,62 @@ impl ResponseContent {
         self.cards = Some(cards);
     }
 
+    pub fn set_note_types(&mut self, note_types: Vec<NoteTypeData>) {
+        self.note_types = Some(note_types);
+    }
+
+    pub fn set_warnings(&mut self, warnings: Vec<WarningData>) {
+        self.warnings = Some(warnings);
+    }
+
+    pub fn set_row_errors(&mut self, row_errors: Vec<RowErrorData>) {
+        self.row_errors = Some(row_errors);
+    }
+
+    pub fn set_upstream_changes(&mut self, upstream_changes: Vec<UpstreamChangeData>) {
+        self.upstream_changes = Some(upstream_changes);
+    }
+
+    pub fn set_suggestions(&mut self, suggestions: Vec<SuggestionData>) {
+        self.suggestions = Some(suggestions);
+    }
+
+    pub fn set_members(&mut self, members: Vec<MemberData>) {
+        self.members = Some(members);
+    }
+
+    pub fn set_share_tokens(&mut self, share_tokens: Vec<ShareTokenData>) {
+        self.share_tokens = Some(share_tokens);
+    }
+
+    pub fn set_card_states(&mut self, card_states: Vec<CardStateData>) {
+        self.card_states = Some(card_states);
+    }
+
+    pub fn set_reviews(&mut self, reviews: Vec<ReviewData>) {
+        self.reviews = Some(reviews);
+    }
+
+    pub fn set_session(&mut self, session: StudySessionData) {
+        self.session = Some(session);
+    }
+
+    pub fn set_answer_check(&mut self, answer_check: AnswerCheckData) {
+        self.answer_check = Some(answer_check);
+    }
+
+    pub fn set_quiz(&mut self, quiz: QuizData) {
+        self.quiz = Some(quiz);
+    }
+
+    pub fn set_test(&mut self, test: TestData) {
+        self.test = Some(test);
+    }
+
+    pub fn set_attempts(&mut self, attempts: Vec<AttemptData>) {
+        self.attempts = Some(attempts);
+    }
+
     pub fn set_unique_id(&mut self, unique_id: &str) {
         self.unique_id = Some(unique_id.to_owned());
     }
@@ -334,11 +1311,7 @@ impl Response {
             status: ResponseStatus::Err, 
             content: Some( ResponseContent {
                 errors: Some(errors),
-                user: None,
-                stacks: None,
-                cards: None,
-                authenticated: None,
-                unique_id: None,
+                ..ResponseContent::new()
             }),
         }
     }
@@ -390,6 +1363,174 @@ impl Response {
         self
     }
 
+    pub fn set_note_types(&mut self, note_types: Vec<NoteTypeData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_note_types(note_types),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_note_types(note_types);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_warnings(&mut self, warnings: Vec<WarningData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_warnings(warnings),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_warnings(warnings);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_row_errors(&mut self, row_errors: Vec<RowErrorData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_row_errors(row_errors),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_row_errors(row_errors);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_upstream_changes(&mut self, upstream_changes: Vec<UpstreamChangeData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_upstream_changes(upstream_changes),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_upstream_changes(upstream_changes);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_suggestions(&mut self, suggestions: Vec<SuggestionData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_suggestions(suggestions),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_suggestions(suggestions);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_members(&mut self, members: Vec<MemberData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_members(members),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_members(members);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_share_tokens(&mut self, share_tokens: Vec<ShareTokenData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_share_tokens(share_tokens),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_share_tokens(share_tokens);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_card_states(&mut self, card_states: Vec<CardStateData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_card_states(card_states),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_card_states(card_states);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_reviews(&mut self, reviews: Vec<ReviewData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_reviews(reviews),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_reviews(reviews);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_session(&mut self, session: StudySessionData) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_session(session),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_session(session);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_answer_check(&mut self, answer_check: AnswerCheckData) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_answer_check(answer_check),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_answer_check(answer_check);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_quiz(&mut self, quiz: QuizData) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_quiz(quiz),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_quiz(quiz);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_test(&mut self, test: TestData) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_test(test),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_test(test);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
+    pub fn set_attempts(&mut self, attempts: Vec<AttemptData>) -> &mut Self {
+        match &mut self.content {
+            Some(content) => content.set_attempts(attempts),
+            None => {
+                let mut content = ResponseContent::new();
+                content.set_attempts(attempts);
+                self.content = Some(content);
+            }
+        }
+        self
+    }
+
     pub fn set_unique_id(&mut self, unique_id: &str) -> &mut Self {
         match &mut self.content {
             Some(content) => content.set_unique_id(unique_id),
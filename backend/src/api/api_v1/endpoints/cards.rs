@@ -1,7 +1,8 @@
 use super::{generate_err_response, api_models::{self, ResponseError}, wrapped, api_models::ConvertCardsFromOptional};
-use crate::{utils, db, models};
+use crate::{utils, db, models, scheduler, answers, cloze, notes, upstream, suggestions, transfer, members::{self, Permission}};
 
 use actix_web::{web, HttpRequest, HttpResponse};
+use diesel::Connection;
 
 macro_rules! impl_try_from {
     ($struct_name:ident { $($field:ident : $field_type:ty,)* }) => {
@@ -55,19 +56,23 @@ pub async fn cards_handler<'a>(
                     _ => return Err(ResponseError::InvalidData),
                 };
 
-                let authorized = {
-                    if let Ok(user_id) = wrapped::authenticate(&req, &app_data.jwt_secret) {
-                        user_id == unique_id
-                    } else { false }
-                };
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret).ok();
 
                 let mut stacks = match db::get_stacks_by_owner(&mut conn, &unique_id) {
                     Ok(value) => value,
                     Err(_) => return Err(ResponseError::InternalError)
                 };
 
-                if ! authorized {
-                    stacks.retain(|elem| elem.visibility);
+                // Other users see the public stacks and the private ones they are members of
+                if user_id.as_deref() != Some(unique_id.as_str()) {
+                    let memberships = match &user_id {
+                        Some(user_id) => db::get_stack_members_by_user(&mut conn, user_id).map_err(|_| ResponseError::InternalError)?,
+                        None => Vec::new(),
+                    };
+                    stacks.retain(|elem| {
+                        let member = memberships.iter().find(|member| member.stack_id == elem.unique_id);
+                        members::allows(elem, user_id.as_deref().and_then(|user_id| members::role(elem, member, user_id)), Permission::Read)
+                    });
                 }
 
                 let mut response_struct = api_models::Response::new();
@@ -86,20 +91,11 @@ pub async fn cards_handler<'a>(
                     _ => return Err(ResponseError::InvalidData),
                 };
 
-                let stacks: Vec<api_models::StackData> = match db::get_stack(&mut conn, &unique_id) {
-                    Ok(stack) => {
-                        let authorized = {
-                            if let Ok(user_id) = wrapped::authenticate(&req, &app_data.jwt_secret) {
-                                user_id == stack.owner_id
-                            } else { false }
-                        };
-                        if stack.visibility || authorized {
-                            db_stacks_to_resp_stacks(vec![stack])
-                        } else {
-                            Vec::new()
-                        }
-                    },
-                    _ => Vec::new()
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret).ok();
+                let stacks: Vec<api_models::StackData> = match wrapped::get_shared_stack(&mut conn, &unique_id, user_id.as_deref(), content.token.as_deref()) {
+                    Ok(stack) => db_stacks_to_resp_stacks(vec![stack]),
+                    Err(ResponseError::Unauthorized) => Vec::new(),
+                    Err(err) => return Err(err),
                 };
 
                 let mut response_struct = api_models::Response::new();
@@ -125,21 +121,13 @@ pub async fn cards_handler<'a>(
                     Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
                 };
 
-                // We first need to check if that stack exists and if it is public
-                match db::get_stack(&mut conn, &unique_id) {
-                    Ok(stack) => {
-                        let authorized = {
-                            if let Ok(user_id) = wrapped::authenticate(&req, &app_data.jwt_secret) {
-                                user_id == stack.owner_id
-                            } else { false }
-                        };
-                        if ! stack.visibility && ! authorized {
-                            // Stack is private so send a response as if no cards were found
-                            return no_stacks_found();
-                        }
-                    }
-                    Err(diesel::result::Error::NotFound) => return no_stacks_found(),
-                    _ => return Err(ResponseError::InternalError)
+                // We first need to check if that stack exists and if the user can read it, or has a share token for it
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret).ok();
+                match wrapped::get_shared_stack(&mut conn, &unique_id, user_id.as_deref(), content.token.as_deref()) {
+                    Ok(_) => {}
+                    // Stack is private so send a response as if no cards were found
+                    Err(ResponseError::Unauthorized) => return no_stacks_found(),
+                    Err(err) => return Err(err),
                 }
 
                 let cards = match db::get_cards_by_stack(&mut conn, &unique_id) {
@@ -176,21 +164,13 @@ pub async fn cards_handler<'a>(
                     _ => return Err(ResponseError::InternalError)
                 };
 
-                // Check if the stack that this card belongs to is private
-                match db::get_stack(&mut conn, &card.stack_id) {
-                    Ok(stack) => {
-                        let authorized = {
-                            if let Ok(user_id) = wrapped::authenticate(&req, &app_data.jwt_secret) {
-                                user_id == stack.owner_id
-                            } else { false }
-                        };
-                        if ! stack.visibility && ! authorized {
-                            // Stack is private so send a response as if no card was found
-                            return no_cards_found();
-                        }
-                    }
-                    Err(diesel::result::Error::NotFound) => return no_cards_found(),
-                    _ => return Err(ResponseError::InternalError)
+                // Check if the user can read the stack that this card belongs to
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret).ok();
+                match wrapped::get_permitted_stack(&mut conn, &card.stack_id, user_id.as_deref(), Permission::Read) {
+                    Ok(_) => {}
+                    // Stack is private so send a response as if no card was found
+                    Err(ResponseError::Unauthorized) => return no_cards_found(),
+                    Err(err) => return Err(err),
                 }
 
                 let mut response_struct = api_models::Response::new();
@@ -224,6 +204,30 @@ pub async fn cards_handler<'a>(
                     return Err(ResponseError::InvalidData)
                 }
 
+                // Validate learning steps, defaults are used if they are not present
+                let learning_steps = content.learning_steps.as_deref().unwrap_or(scheduler::steps::DEFAULT_LEARNING_STEPS);
+                let relearning_steps = content.relearning_steps.as_deref().unwrap_or(scheduler::steps::DEFAULT_RELEARNING_STEPS);
+                if scheduler::steps::parse_steps(learning_steps).is_none() || scheduler::steps::parse_steps(relearning_steps).is_none() {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let leech_threshold = content.leech_threshold.unwrap_or(scheduler::steps::DEFAULT_LEECH_THRESHOLD);
+                if ! utils::is_valid_leech_threshold(leech_threshold) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Empty scheduler means every user studies the stack with their own one
+                let stack_scheduler = content.scheduler.as_deref().unwrap_or("");
+                let leitner_intervals = content.leitner_intervals.as_deref().unwrap_or(scheduler::leitner::DEFAULT_INTERVALS);
+                if ! scheduler::is_valid_stack_scheduler_name(stack_scheduler) || ! scheduler::leitner::is_valid_intervals(leitner_intervals) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let directions = content.directions.as_deref().unwrap_or(scheduler::DIRECTION_FORWARD);
+                if ! scheduler::is_valid_stack_directions(directions) {
+                    return Err(ResponseError::InvalidData)
+                }
+
                 let stack_id = match db::generate_stack_id(&mut conn) {
                     Ok(value) => value,
                     _ => return Err(ResponseError::InternalError),
@@ -235,6 +239,14 @@ pub async fn cards_handler<'a>(
                     name: stack_data.name,
                     tags: stack_data.tags,
                     visibility: stack_data.visibility,
+                    learning_steps: scheduler::steps::format_steps(learning_steps),
+                    relearning_steps: scheduler::steps::format_steps(relearning_steps),
+                    leech_threshold,
+                    leech_suspend: content.leech_suspend.unwrap_or(false),
+                    scheduler: stack_scheduler.to_owned(),
+                    leitner_intervals: scheduler::leitner::format_intervals(leitner_intervals),
+                    directions: directions.to_owned(),
+                    upstream_id: None,
                 };
 
                 if db::add_stack(&mut conn, new_stack_data).is_err() {
@@ -250,8 +262,6 @@ pub async fn cards_handler<'a>(
 
                 impl_try_from!( ParsedCardData {
                     stack_id: String,
-                    frontside: String,
-                    backside: String,
                 });
 
                 let card_data = match ParsedCardData::try_from_optional(&content) {
@@ -260,34 +270,43 @@ pub async fn cards_handler<'a>(
                 };
 
                 // Validate user
-                match db::get_stack(&mut conn, &card_data.stack_id) {
-                    Ok(value) => {
-                        if value.owner_id != user_id {
-                            return Err(ResponseError::Unauthorized)
-                        }
-                    }
-                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
-                    _ => return Err(ResponseError::InternalError)
-                }
+                wrapped::get_permitted_stack(&mut conn, &card_data.stack_id, Some(&user_id), Permission::Edit)?;
 
-                // Validate input data
-                if card_data.frontside.len() > 255 || card_data.backside.len() > 255 {
+                // Empty directions follow the stack
+                let directions = content.directions.unwrap_or_default();
+                if ! scheduler::is_valid_card_directions(&directions) {
                     return Err(ResponseError::InvalidData)
                 }
 
-                let card_id = match db::generate_card_id(&mut conn) {
-                    Ok(value) => value,
-                    _ => return Err(ResponseError::InternalError),
-                };
-
-                let new_card_data = models::Card {
-                    unique_id: card_id,
+                let mut new_card_data = models::Card {
+                    unique_id: String::new(),
                     stack_id: card_data.stack_id,
-                    frontside: card_data.frontside,
-                    backside: card_data.backside,
+                    frontside: String::new(),
+                    backside: String::new(),
+                    card_type: content.card_type.unwrap_or(cloze::CARD_TYPE_BASIC.to_owned()),
+                    directions,
+                    note_type_id: String::new(),
+                    fields: String::new(),
                 };
 
-                if db::add_card(&mut conn, new_card_data).is_err() {
+                // Cards of the built-in Basic note type are made of a frontside and a backside, other ones of fields
+                match content.note_type_id {
+                    Some(note_type_id) if note_type_id != notes::BASIC_NOTE_TYPE_ID => {
+                        let fields = match (content.fields, &content.frontside, &content.backside) {
+                            (Some(fields), None, None) => fields,
+                            _ => return Err(ResponseError::InvalidData),
+                        };
+                        set_note_fields(&mut conn, &mut new_card_data, &note_type_id, fields)?;
+                    }
+                    _ => {
+                        match (content.frontside, content.backside, &content.fields) {
+                            (Some(frontside), Some(backside), None) => set_basic_sides(&mut new_card_data, frontside, backside)?,
+                            _ => return Err(ResponseError::InvalidData),
+                        }
+                    }
+                }
+
+                if add_new_card(&mut conn, new_card_data).is_err() {
                     return Err(ResponseError::InternalError);
                 }
 
@@ -304,22 +323,12 @@ pub async fn cards_handler<'a>(
                     _ => {}
                 }
 
+                // Validate user
                 let mut stack_data = match content.unique_id {
-                    Some(id) => {
-                        match db::get_stack(&mut conn, &id) {
-                            Ok(value) => value,
-                            Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
-                            _ => return Err(ResponseError::InternalError)
-                        }
-                    }
+                    Some(id) => wrapped::get_permitted_stack(&mut conn, &id, Some(&user_id), Permission::Manage)?,
                     None => return Err(ResponseError::InvalidData),
                 };
 
-                // Validate user
-                if stack_data.owner_id != user_id {
-                    return Err(ResponseError::Unauthorized)
-                }
-
                 // Udate values
                 if let Some(value) = content.name {
                     if ! utils::is_valid_stack_name(&value) {
@@ -340,6 +349,52 @@ pub async fn cards_handler<'a>(
                     stack_data.visibility = value
                 }
 
+                if let Some(value) = content.learning_steps {
+                    if scheduler::steps::parse_steps(&value).is_none() {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.learning_steps = scheduler::steps::format_steps(&value)
+                }
+
+                if let Some(value) = content.relearning_steps {
+                    if scheduler::steps::parse_steps(&value).is_none() {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.relearning_steps = scheduler::steps::format_steps(&value)
+                }
+
+                if let Some(value) = content.leech_threshold {
+                    if ! utils::is_valid_leech_threshold(value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.leech_threshold = value
+                }
+
+                if let Some(value) = content.leech_suspend {
+                    stack_data.leech_suspend = value
+                }
+
+                if let Some(value) = content.scheduler {
+                    if ! scheduler::is_valid_stack_scheduler_name(&value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.scheduler = value
+                }
+
+                if let Some(value) = content.leitner_intervals {
+                    if ! scheduler::leitner::is_valid_intervals(&value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.leitner_intervals = scheduler::leitner::format_intervals(&value)
+                }
+
+                if let Some(value) = content.directions {
+                    if ! scheduler::is_valid_stack_directions(&value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    stack_data.directions = value
+                }
+
                 // Send the update call
                 if db::update_stack(&mut conn, stack_data.into()).is_err() {
                     return Err(ResponseError::InternalError);
@@ -370,28 +425,37 @@ pub async fn cards_handler<'a>(
                 };
 
                 // Validate user
-                match db::get_stack(&mut conn, &card_data.stack_id) {
-                    Ok(value) => {
-                        if value.owner_id != user_id {
-                            return Err(ResponseError::Unauthorized)
-                        }
-                    }
-                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
-                    _ => return Err(ResponseError::InternalError),
-                }
+                wrapped::get_permitted_stack(&mut conn, &card_data.stack_id, Some(&user_id), Permission::Edit)?;
 
                 // Udate values
-                if let Some(value) = content.frontside {
-                    card_data.frontside = value
+                if let Some(value) = content.card_type {
+                    card_data.card_type = value
                 }
 
-                if let Some(value) = content.backside {
-                    card_data.backside = value
+                if let Some(value) = content.directions {
+                    if ! scheduler::is_valid_card_directions(&value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    card_data.directions = value
                 }
 
-                // Validate new data
-                if card_data.frontside.len() > 255 || card_data.backside.len() > 255 {
-                    return Err(ResponseError::InvalidData)
+                // Cards of other note types are rendered from their fields, frontside and backside can't be set directly
+                let note_type_id = content.note_type_id.unwrap_or(card_data.note_type_id.clone());
+                if note_type_id == notes::BASIC_NOTE_TYPE_ID {
+                    if content.fields.is_some() {
+                        return Err(ResponseError::InvalidData)
+                    }
+
+                    let frontside = content.frontside.unwrap_or(card_data.frontside.clone());
+                    let backside = content.backside.unwrap_or(card_data.backside.clone());
+                    set_basic_sides(&mut card_data, frontside, backside)?;
+                } else {
+                    if content.frontside.is_some() || content.backside.is_some() {
+                        return Err(ResponseError::InvalidData)
+                    }
+
+                    let fields = content.fields.unwrap_or(card_data.field_values());
+                    set_note_fields(&mut conn, &mut card_data, &note_type_id, fields)?;
                 }
 
                 // Send the update call
@@ -415,18 +479,10 @@ pub async fn cards_handler<'a>(
                     _ => return Err(ResponseError::InvalidData),
                 };
 
-                let stack_data = match db::get_stack(&mut conn, &stack_id) {
-                    Ok(value) => value,
-                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
-                    _ => return Err(ResponseError::InternalError),
-                };
-
-                if stack_data.owner_id != user_id {
-                    return Err(ResponseError::Unauthorized)
-                }
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Manage)?;
 
-                // Send the delete call
-                if db::delete_stack(&mut conn, &stack_id).is_err() {
+                // Forks lower the forks count of their upstream stack in the same transaction
+                if conn.transaction(|conn| db::delete_stack(conn, &stack_id)).is_err() {
                     return Err(ResponseError::InternalError);
                 }
 
@@ -452,15 +508,7 @@ pub async fn cards_handler<'a>(
                     _ => return Err(ResponseError::InternalError),
                 };
 
-                let owner_id = match db::get_stack(&mut conn, &stack_id) {
-                    Ok(value) => value.owner_id,
-                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
-                    _ => return Err(ResponseError::InternalError),
-                };
-
-                if owner_id != user_id {
-                    return Err(ResponseError::Unauthorized)
-                }
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Edit)?;
 
                 // Send the delete call
                 if db::delete_card(&mut conn, &card_id).is_err() {
@@ -469,29 +517,1164 @@ pub async fn cards_handler<'a>(
 
                 Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
             }
-        }
-    };
 
-    match execute() {
-        Ok(resp) => resp,
-        Err(code) => generate_err_response(code)
-    }
-}
 
+            api_models::CardsRequestType::GetDueCards => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
 
+                impl_try_from!( ParsedStacksData {
+                    stack_id: String,
+                });
 
-fn db_stacks_to_resp_stacks(db_stacks: Vec<models::StackFull>) -> Vec<api_models::StackData> {
-    let mut output: Vec<api_models::StackData> = Vec::with_capacity(db_stacks.len());
-    for item in db_stacks {
-        output.push(item.into());
-    }
-    output
-}
+                let stack_id = match ParsedStacksData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
 
-fn db_cards_to_resp_cards(db_cards: Vec<models::Card>) -> Vec<api_models::CardData> {
-    let mut output: Vec<api_models::CardData> = Vec::with_capacity(db_cards.len());
-    for item in db_cards {
-        output.push(item.into());
-    }
-    output
+                // User can study stacks he can read
+                let stack = wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+
+                let note_types = match db::get_note_types_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+                let layout = models::CardLayout::new(&stack, &note_types);
+
+                // Every cloze, template and direction of a card is due on its own
+                let cards: Vec<models::SubCard> = match db::get_cards_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value.iter().flat_map(|card| card.sub_cards(&layout)).collect(),
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let states = match db::get_card_states_by_stack(&mut conn, &user_id, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let user = match db::get_user(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let now = utils::get_unix_timestamp() as i64;
+                let due = scheduler::due_cards(scheduler::for_stack(&user, &stack).as_ref(), cards, &states, now);
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_cards(due.into_iter().map(|card| card.into()).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::SubmitReview => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedReviewData {
+                    unique_id: String,
+                    grade: u8,
+                });
+
+                let review_data = match ParsedReviewData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                if ! scheduler::is_valid_grade(review_data.grade) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Time it took the user to answer in milliseconds
+                let time_taken = content.time_taken.unwrap_or(0);
+                if time_taken < 0 {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Every cloze and direction of a card is reviewed separately
+                let sub_card = get_accessible_sub_card(&mut conn, &review_data.unique_id, &content, &user_id)?;
+                let state = wrapped::review_card(&mut conn, &user_id, &sub_card, review_data.grade, time_taken)?;
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_card_states(vec![state.into()]);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetReviewHistory => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                // Only the reviews of the authenticated user are returned, by card or by stack
+                let logs = match (&content.unique_id, &content.stack_id) {
+                    (Some(card_id), None) => db::get_review_logs_by_card(&mut conn, &user_id, card_id),
+                    (None, Some(stack_id)) => db::get_review_logs_by_stack(&mut conn, &user_id, stack_id),
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let reviews = match logs {
+                    Ok(value) => value.into_iter().map(|log| log.into()).collect(),
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_reviews(reviews);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::ExportReviewHistory => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                let logs = match db::get_review_logs(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                match content.format.as_deref() {
+                    Some("csv") => {
+                        Ok(HttpResponse::Ok().content_type("text/csv").body(transfer::review_logs_to_csv(&logs)))
+                    }
+                    Some("json") | None => {
+                        let mut response_struct = api_models::Response::new();
+                        response_struct.set_reviews(logs.into_iter().map(|log| log.into()).collect());
+                        Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+                    }
+                    _ => Err(ResponseError::InvalidData)
+                }
+            }
+
+
+            api_models::CardsRequestType::GetLeeches => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedStacksData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStacksData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+
+                let states = match db::get_leech_states_by_stack(&mut conn, &user_id, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let mut cards = match db::get_cards_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+                cards.retain(|card| states.iter().any(|state| state.card_id == card.unique_id));
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_cards(db_cards_to_resp_cards(cards));
+                response_struct.set_card_states(states.into_iter().map(|state| state.into()).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::SuspendCard
+            | api_models::CardsRequestType::UnsuspendCard
+            | api_models::CardsRequestType::BuryCard => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedCardData {
+                    unique_id: String,
+                });
+
+                let card_id = match ParsedCardData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let sub_card = get_accessible_sub_card(&mut conn, &card_id, &content, &user_id)?;
+                let mut state = get_sub_card_state(&mut conn, &sub_card, &user_id)?;
+                let now = utils::get_unix_timestamp() as i64;
+
+                match request_data.request_type {
+                    api_models::CardsRequestType::SuspendCard => state.suspended = true,
+                    // Unsuspending also brings back buried cards, the leech flag stays
+                    api_models::CardsRequestType::UnsuspendCard => {
+                        state.suspended = false;
+                        state.buried_until = 0;
+                    }
+                    // Buried until the next midnight (UTC)
+                    _ => state.buried_until = now - now % scheduler::SECONDS_IN_DAY + scheduler::SECONDS_IN_DAY,
+                }
+
+                if db::save_card_state(&mut conn, state.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_card_states(vec![state.into()]);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::CheckAnswer => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedAnswerData {
+                    unique_id: String,
+                    answer: String,
+                });
+
+                let answer_data = match ParsedAnswerData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                if answers::MAX_ANSWER_LENGTH < answer_data.answer.chars().count() {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let sub_card = get_accessible_sub_card(&mut conn, &answer_data.unique_id, &content, &user_id)?;
+
+                // Only checks the answer, the client submits the review with the grade it settles on
+                let check = answers::check_answer(&sub_card.answer, &answer_data.answer);
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_answer_check(api_models::AnswerCheckData::new(sub_card, check));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetNoteTypes => {
+                impl_try_from!( ParsedNoteTypeData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedNoteTypeData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Private stacks get a response as if they had no note types, not even the built-in one
+                let no_note_types_found = || -> Result<HttpResponse, ResponseError> {
+                    let mut response_struct = api_models::Response::new();
+                    response_struct.set_note_types(vec![]);
+                    Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+                };
+
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret).ok();
+                match wrapped::get_permitted_stack(&mut conn, &stack_id, user_id.as_deref(), Permission::Read) {
+                    Ok(_) => {}
+                    Err(ResponseError::Unauthorized) => return no_note_types_found(),
+                    Err(err) => return Err(err),
+                }
+
+                let mut note_types = vec![api_models::NoteTypeData::basic()];
+                match db::get_note_types_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => note_types.extend(value.into_iter().map(api_models::NoteTypeData::from)),
+                    _ => return Err(ResponseError::InternalError)
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_note_types(note_types);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::CreateNoteType => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedNoteTypeData {
+                    stack_id: String,
+                    name: String,
+                    field_names: Vec<String>,
+                    templates: Vec<notes::Template>,
+                });
+
+                let note_type_data = match ParsedNoteTypeData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Validate user
+                wrapped::get_permitted_stack(&mut conn, &note_type_data.stack_id, Some(&user_id), Permission::Edit)?;
+
+                // Validate input data
+                if ! notes::is_valid_name(&note_type_data.name) ||
+                    ! notes::is_valid_field_names(&note_type_data.field_names) ||
+                    ! notes::is_valid_templates(&note_type_data.templates, &note_type_data.field_names) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let note_type_id = match db::generate_note_type_id(&mut conn) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let new_note_type_data = models::NoteType {
+                    unique_id: note_type_id.clone(),
+                    stack_id: note_type_data.stack_id,
+                    name: note_type_data.name.trim().to_owned(),
+                    fields: serde_json::to_string(&note_type_data.field_names).unwrap_or_default(),
+                    templates: serde_json::to_string(&note_type_data.templates).unwrap_or_default(),
+                };
+
+                if db::add_note_type(&mut conn, new_note_type_data).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().set_unique_id(&note_type_id).to_string()))
+            }
+
+
+            api_models::CardsRequestType::UpdateNoteType => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedNoteTypeData {
+                    unique_id: String,
+                });
+
+                let note_type_id = match ParsedNoteTypeData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut note_type_data = get_editable_note_type(&mut conn, &note_type_id, &user_id)?;
+
+                // Udate values
+                if let Some(value) = content.name {
+                    if ! notes::is_valid_name(&value) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                    note_type_data.name = value.trim().to_owned()
+                }
+
+                let field_names = content.field_names.unwrap_or(note_type_data.field_names());
+                let templates = content.templates.unwrap_or(note_type_data.template_list());
+                if ! notes::is_valid_field_names(&field_names) || ! notes::is_valid_templates(&templates, &field_names) {
+                    return Err(ResponseError::InvalidData)
+                }
+                note_type_data.fields = serde_json::to_string(&field_names).unwrap_or_default();
+                note_type_data.templates = serde_json::to_string(&templates).unwrap_or_default();
+
+                // Cards keep the first rendered card for clients that don't know note types,
+                // the update is refused if a card would be left without one
+                let mut cards = match db::get_cards_by_note_type(&mut conn, &note_type_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError)
+                };
+                for card in &mut cards {
+                    let mut fields = card.field_values();
+                    fields.retain(|name, _| field_names.contains(name));
+                    if ! card.set_fields(&note_type_data, fields) {
+                        return Err(ResponseError::InvalidData)
+                    }
+                }
+
+                // The note type and its cards change together or not at all
+                let result = conn.transaction(|conn| -> Result<(), diesel::result::Error> {
+                    db::update_note_type(conn, note_type_data)?;
+                    for card in cards {
+                        db::update_card(conn, card)?;
+                    }
+                    Ok(())
+                });
+                if result.is_err() {
+                    return Err(ResponseError::InternalError)
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::DeleteNoteType => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedNoteTypeData {
+                    unique_id: String,
+                });
+
+                let note_type_id = match ParsedNoteTypeData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                get_editable_note_type(&mut conn, &note_type_id, &user_id)?;
+
+                // Note types can only be deleted once no card uses them
+                match db::get_cards_by_note_type(&mut conn, &note_type_id) {
+                    Ok(value) => {
+                        if ! value.is_empty() {
+                            return Err(ResponseError::InvalidData)
+                        }
+                    }
+                    _ => return Err(ResponseError::InternalError)
+                }
+
+                // Send the delete call
+                if db::delete_note_type(&mut conn, &note_type_id).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::ForkStack => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedStacksData {
+                    unique_id: String,
+                });
+
+                let unique_id = match ParsedStacksData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Public stacks can be forked by everyone, private ones by their members and with a share token
+                let upstream = wrapped::get_shared_stack(&mut conn, &unique_id, Some(&user_id), content.token.as_deref())?;
+
+                let stack = match conn.transaction(|conn| db::fork_stack(conn, &user_id, &upstream)).and_then(|stack_id| db::get_stack(&mut conn, &stack_id)) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_stacks(db_stacks_to_resp_stacks(vec![stack]));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetUpstreamChanges => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedStacksData {
+                    unique_id: String,
+                });
+
+                let unique_id = match ParsedStacksData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let fork = get_fork(&mut conn, &unique_id, &user_id)?;
+                let changes = upstream::changes(&fork.lineage, (&fork.cards, &fork.note_types), (&fork.upstream_cards, &fork.upstream_note_types));
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_upstream_changes(changes.into_iter().map(api_models::UpstreamChangeData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::PullUpstreamChanges => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedPullData {
+                    unique_id: String,
+                    card_ids: Vec<String>,
+                });
+
+                let pull_data = match ParsedPullData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let fork = get_fork(&mut conn, &pull_data.unique_id, &user_id)?;
+                let changes = upstream::changes(&fork.lineage, (&fork.cards, &fork.note_types), (&fork.upstream_cards, &fork.upstream_note_types));
+
+                // Changes that can't be stored are skipped with a warning, the other ones are still pulled
+                let mut note_types = fork.note_types.clone();
+                let mut warnings: Vec<api_models::WarningData> = Vec::new();
+                let result = conn.transaction(|conn| -> Result<(), diesel::result::Error> {
+                    for change in changes.into_iter().filter(|change| pull_data.card_ids.contains(&change.upstream_card_id)) {
+                        let merged = match (change.merged, &change.card_id) {
+                            (Some(value), _) => value,
+                            (None, Some(card_id)) => {
+                                // Cards edited in the fork keep the edits and their review state, they just stop following upstream
+                                if change.kind != upstream::CHANGE_CONFLICT {
+                                    db::delete_card(conn, card_id)?;
+                                }
+                                db::delete_card_lineage(conn, &pull_data.unique_id, &change.upstream_card_id)?;
+                                continue;
+                            }
+                            (None, None) => continue,
+                        };
+
+                        // Note types of added cards are copied from upstream when the fork has none with that name
+                        if ! merged.note_type.is_empty() && ! note_types.iter().any(|note_type| note_type.name == merged.note_type) {
+                            if let Some(note_type) = fork.upstream_note_types.iter().find(|note_type| note_type.name == merged.note_type) {
+                                let new_note_type = models::NoteType {
+                                    unique_id: db::generate_note_type_id(conn)?,
+                                    stack_id: pull_data.unique_id.clone(),
+                                    ..note_type.clone()
+                                };
+                                db::add_note_type(conn, new_note_type.clone())?;
+                                note_types.push(new_note_type);
+                            }
+                        }
+
+                        let mut card = match fork.cards.iter().find(|card| Some(&card.unique_id) == change.card_id.as_ref()) {
+                            Some(value) => value.clone(),
+                            None => models::Card {
+                                unique_id: String::new(),
+                                stack_id: pull_data.unique_id.clone(),
+                                frontside: String::new(),
+                                backside: String::new(),
+                                card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+                                directions: String::new(),
+                                note_type_id: String::new(),
+                                fields: String::new(),
+                            },
+                        };
+                        if let Err(message) = upstream::apply(&mut card, merged, &note_types) {
+                            warnings.push(api_models::WarningData { item: format!("card {}", change.upstream_card_id), message: message.to_owned() });
+                            continue;
+                        }
+
+                        // The upstream card becomes the base of the next pull
+                        let mut lineage = models::CardLineage {
+                            stack_id: pull_data.unique_id.clone(),
+                            upstream_card_id: change.upstream_card_id,
+                            card_id: None,
+                            base: serde_json::to_string(&change.upstream).unwrap_or_default(),
+                        };
+                        if card.unique_id.is_empty() {
+                            card.unique_id = db::generate_card_id(conn)?;
+                            lineage.card_id = Some(card.unique_id.clone());
+                            db::add_card(conn, card)?;
+                            db::add_card_lineage(conn, lineage)?;
+                        } else {
+                            lineage.card_id = Some(card.unique_id.clone());
+                            db::update_card(conn, card)?;
+                            db::update_card_lineage(conn, lineage)?;
+                        }
+                    }
+                    Ok(())
+                });
+                if result.is_err() {
+                    return Err(ResponseError::InternalError)
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_warnings(warnings);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::CreateSuggestion => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedSuggestionData {
+                    stack_id: String,
+                    kind: String,
+                });
+
+                let suggestion_data = match ParsedSuggestionData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+                if ! suggestions::is_valid_kind(&suggestion_data.kind) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Suggestions are for public stacks of other users, owners and editors edit them directly
+                let stack = wrapped::get_permitted_stack(&mut conn, &suggestion_data.stack_id, Some(&user_id), Permission::Read)?;
+                if ! suggestions::can_suggest(&stack, wrapped::get_stack_role(&mut conn, &stack, Some(&user_id))?, &user_id) {
+                    return Err(ResponseError::Unauthorized)
+                }
+
+                let current = match &content.card_id {
+                    Some(card_id) => {
+                        match db::get_card(&mut conn, card_id) {
+                            Ok(value) if value.stack_id == suggestion_data.stack_id => Some(value),
+                            Ok(_) | Err(diesel::result::Error::NotFound) => return Err(ResponseError::InvalidData),
+                            _ => return Err(ResponseError::InternalError),
+                        }
+                    }
+                    None => None,
+                };
+
+                let card = match suggestions::suggested_card(
+                    &suggestion_data.kind,
+                    &suggestion_data.stack_id,
+                    current.as_ref(),
+                    content.frontside,
+                    content.backside,
+                    content.card_type,
+                ) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let unique_id = match db::generate_suggestion_id(&mut conn) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let new_suggestion = models::Suggestion {
+                    unique_id: unique_id.clone(),
+                    stack_id: suggestion_data.stack_id,
+                    author_id: user_id,
+                    kind: suggestion_data.kind,
+                    card_id: current.map(|value| value.unique_id),
+                    frontside: card.frontside,
+                    backside: card.backside,
+                    card_type: card.card_type,
+                    status: suggestions::STATUS_PENDING.to_owned(),
+                    created_at: utils::get_unix_timestamp() as i64,
+                    resolved_at: None,
+                };
+
+                if db::add_suggestion(&mut conn, new_suggestion).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_unique_id(&unique_id);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetSuggestions => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedStacksData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStacksData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let stack = wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+                let can_edit = members::allows(&stack, wrapped::get_stack_role(&mut conn, &stack, Some(&user_id))?, Permission::Edit);
+
+                let (mut stack_suggestions, cards) = match (db::get_suggestions_by_stack(&mut conn, &stack_id), db::get_cards_by_stack(&mut conn, &stack_id)) {
+                    (Ok(stack_suggestions), Ok(cards)) => (stack_suggestions, cards),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                // The owner and editors review the pending suggestions, other users follow their own ones
+                if can_edit {
+                    stack_suggestions.retain(|suggestion| suggestion.status == suggestions::STATUS_PENDING);
+                } else {
+                    stack_suggestions.retain(|suggestion| suggestion.author_id == user_id);
+                }
+
+                let output = stack_suggestions.into_iter().map(|suggestion| {
+                    let current = cards.iter().find(|card| Some(&card.unique_id) == suggestion.card_id.as_ref()).cloned();
+                    api_models::SuggestionData::new(suggestion, current)
+                }).collect();
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_suggestions(output);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::AcceptSuggestion => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedSuggestionData {
+                    unique_id: String,
+                });
+
+                let unique_id = match ParsedSuggestionData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut suggestion = get_pending_suggestion(&mut conn, &unique_id, &user_id)?;
+
+                // The card of an edit or deletion may have been deleted since, its id is cleared then
+                let current = match &suggestion.card_id {
+                    Some(card_id) => {
+                        match db::get_card(&mut conn, card_id) {
+                            Ok(value) => Some(value),
+                            Err(diesel::result::Error::NotFound) => None,
+                            _ => return Err(ResponseError::InternalError),
+                        }
+                    }
+                    None => None,
+                };
+
+                // Accepted suggestions go through the same checks as create_card and update_card
+                let card = match suggestions::accepted_card(&suggestion, current) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                suggestions::resolve(&mut suggestion, suggestions::STATUS_ACCEPTED, utils::get_unix_timestamp() as i64);
+                let result = conn.transaction(|conn| -> Result<(), diesel::result::Error> {
+                    match card {
+                        Some(card) if card.unique_id.is_empty() => suggestion.card_id = Some(add_new_card(conn, card)?),
+                        Some(card) => {
+                            db::update_card(conn, card)?;
+                        }
+                        None => {
+                            if let Some(card_id) = suggestion.card_id.take() {
+                                db::delete_card(conn, &card_id)?;
+                            }
+                        }
+                    }
+                    db::update_suggestion(conn, suggestion)?;
+                    Ok(())
+                });
+                if result.is_err() {
+                    return Err(ResponseError::InternalError)
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::RejectSuggestion => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedSuggestionData {
+                    unique_id: String,
+                });
+
+                let unique_id = match ParsedSuggestionData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut suggestion = get_pending_suggestion(&mut conn, &unique_id, &user_id)?;
+                suggestions::resolve(&mut suggestion, suggestions::STATUS_REJECTED, utils::get_unix_timestamp() as i64);
+
+                if db::update_suggestion(&mut conn, suggestion).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::InviteMember => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedMemberData {
+                    stack_id: String,
+                    username: String,
+                    role: String,
+                });
+
+                let member_data = match ParsedMemberData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+                if ! members::is_valid_member_role(&member_data.role) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                wrapped::get_permitted_stack(&mut conn, &member_data.stack_id, Some(&user_id), Permission::Manage)?;
+
+                // The owner can't be a member of his own stack
+                let invited_id = match db::get_user_by_username(&mut conn, &member_data.username) {
+                    Ok(value) if value.unique_id == user_id => return Err(ResponseError::InvalidData),
+                    Ok(value) => value.unique_id,
+                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::InvalidData),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                // Inviting a member again changes the role, an accepted invite stays accepted
+                let result = match db::get_stack_member(&mut conn, &member_data.stack_id, &invited_id) {
+                    Ok(member) => db::update_stack_member(&mut conn, models::StackMember {
+                        role: member_data.role,
+                        ..member
+                    }),
+                    Err(diesel::result::Error::NotFound) => db::add_stack_member(&mut conn, models::StackMember {
+                        stack_id: member_data.stack_id,
+                        user_id: invited_id,
+                        role: member_data.role,
+                        accepted: false,
+                        invited_at: utils::get_unix_timestamp() as i64,
+                    }),
+                    Err(err) => Err(err),
+                };
+                if result.is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::AcceptInvite => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedMemberData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedMemberData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let member = match db::get_stack_member(&mut conn, &stack_id, &user_id) {
+                    Ok(value) => value,
+                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                if db::update_stack_member(&mut conn, models::StackMember { accepted: true, ..member }).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::RemoveMember => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedMemberData {
+                    stack_id: String,
+                    user_id: String,
+                });
+
+                let member_data = match ParsedMemberData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Members can leave a stack or decline an invite, everyone else is removed by the owner
+                if member_data.user_id != user_id {
+                    wrapped::get_permitted_stack(&mut conn, &member_data.stack_id, Some(&user_id), Permission::Manage)?;
+                }
+
+                match db::delete_stack_member(&mut conn, &member_data.stack_id, &member_data.user_id) {
+                    Ok(0) => return Err(ResponseError::InvalidData),
+                    Ok(_) => {}
+                    _ => return Err(ResponseError::InternalError),
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetMembers => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedMemberData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedMemberData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Only the owner and members see who else works on a stack
+                let stack = match db::get_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+                    _ => return Err(ResponseError::InternalError),
+                };
+                if wrapped::get_stack_role(&mut conn, &stack, Some(&user_id))?.is_none() {
+                    return Err(ResponseError::Unauthorized)
+                }
+
+                let stack_members = match db::get_stack_members_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut output: Vec<api_models::MemberData> = Vec::with_capacity(stack_members.len() + 1);
+                match db::get_user(&mut conn, &stack.owner_id) {
+                    Ok(owner) => output.push(api_models::MemberData::owner(&stack, owner.username)),
+                    _ => return Err(ResponseError::InternalError),
+                }
+                for member in stack_members {
+                    match db::get_user(&mut conn, &member.user_id) {
+                        Ok(user) => output.push(api_models::MemberData::new(member, user.username)),
+                        _ => return Err(ResponseError::InternalError),
+                    }
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_members(output);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetMemberships => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                let (memberships, user) = match (db::get_stack_members_by_user(&mut conn, &user_id), db::get_user(&mut conn, &user_id)) {
+                    (Ok(memberships), Ok(user)) => (memberships, user),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                // Stacks come along so invites can be shown before they are accepted
+                let mut stacks: Vec<models::StackFull> = Vec::with_capacity(memberships.len());
+                for member in &memberships {
+                    match db::get_stack(&mut conn, &member.stack_id) {
+                        Ok(value) => stacks.push(value),
+                        _ => return Err(ResponseError::InternalError),
+                    }
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_stacks(db_stacks_to_resp_stacks(stacks));
+                response_struct.set_members(memberships.into_iter().map(|member| api_models::MemberData::new(member, user.username.clone())).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::CreateShareToken => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedTokenData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedTokenData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Manage)?;
+
+                // Tokens without an expiry stay valid until they are revoked
+                let now = utils::get_unix_timestamp() as i64;
+                if content.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let new_token = models::ShareToken {
+                    token: utils::random_token(),
+                    stack_id,
+                    created_at: now,
+                    expires_at: content.expires_at,
+                };
+
+                if db::add_share_token(&mut conn, new_token.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_share_tokens(vec![new_token.into()]);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::GetShareTokens => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedTokenData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedTokenData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Manage)?;
+
+                let tokens = match db::get_share_tokens_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value.into_iter().map(api_models::ShareTokenData::from).collect(),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_share_tokens(tokens);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::CardsRequestType::RevokeShareToken => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                impl_try_from!( ParsedTokenData {
+                    token: String,
+                });
+
+                let token = match ParsedTokenData::try_from_optional(&content) {
+                    Ok(value) => value.token,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let stack_id = match db::get_share_token(&mut conn, &token) {
+                    Ok(value) => value.stack_id,
+                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Manage)?;
+
+                if db::delete_share_token(&mut conn, &token).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()))
+            }
+        }
+    };
+
+    match execute() {
+        Ok(resp) => resp,
+        Err(code) => generate_err_response(code)
+    }
+}
+
+
+
+fn db_stacks_to_resp_stacks(db_stacks: Vec<models::StackFull>) -> Vec<api_models::StackData> {
+    let mut output: Vec<api_models::StackData> = Vec::with_capacity(db_stacks.len());
+    for item in db_stacks {
+        output.push(item.into());
+    }
+    output
+}
+
+fn db_cards_to_resp_cards(db_cards: Vec<models::Card>) -> Vec<api_models::CardData> {
+    let mut output: Vec<api_models::CardData> = Vec::with_capacity(db_cards.len());
+    for item in db_cards {
+        output.push(item.into());
+    }
+    output
+}
+
+/// Turns a card into one of the built-in Basic note type with the given sides, checked like create_card and update_card check them
+fn set_basic_sides(card: &mut models::Card, frontside: String, backside: String) -> Result<(), ResponseError> {
+    card.frontside = frontside;
+    card.backside = backside;
+    card.note_type_id = String::new();
+    card.fields = String::new();
+
+    match card.check_basic() {
+        Ok(()) => Ok(()),
+        _ => Err(ResponseError::InvalidData),
+    }
+}
+
+/// Stores a new card under a free unique id
+fn add_new_card(conn: &mut db::Conn, mut card: models::Card) -> Result<String, diesel::result::Error> {
+    card.unique_id = db::generate_card_id(conn)?;
+    let card_id = card.unique_id.clone();
+    db::add_card(conn, card)?;
+    Ok(card_id)
+}
+
+/// Loads a note type of a stack the user can edit
+fn get_editable_note_type(conn: &mut db::Conn, note_type_id: &str, user_id: &str) -> Result<models::NoteType, ResponseError> {
+    let note_type = match db::get_note_type(conn, note_type_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    wrapped::get_permitted_stack(conn, &note_type.stack_id, Some(user_id), Permission::Edit)?;
+    Ok(note_type)
+}
+
+/// Loads a pending suggestion for a stack the user can edit
+fn get_pending_suggestion(conn: &mut db::Conn, suggestion_id: &str, user_id: &str) -> Result<models::Suggestion, ResponseError> {
+    let suggestion = match db::get_suggestion(conn, suggestion_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    wrapped::get_permitted_stack(conn, &suggestion.stack_id, Some(user_id), Permission::Edit)?;
+
+    if suggestion.status != suggestions::STATUS_PENDING {
+        return Err(ResponseError::InvalidData)
+    }
+    Ok(suggestion)
+}
+
+/// Fork of the user with everything needed to compare it with its upstream stack
+struct Fork {
+    lineage: Vec<models::CardLineage>,
+    cards: Vec<models::Card>,
+    note_types: Vec<models::NoteType>,
+    upstream_cards: Vec<models::Card>,
+    upstream_note_types: Vec<models::NoteType>,
+}
+
+/// Loads a fork the user can edit, the user has to be able to read the upstream stack as well
+fn get_fork(conn: &mut db::Conn, stack_id: &str, user_id: &str) -> Result<Fork, ResponseError> {
+    let fork = wrapped::get_permitted_stack(conn, stack_id, Some(user_id), Permission::Edit)?;
+    let upstream_id = fork.upstream_id.ok_or(ResponseError::InvalidData)?;
+    wrapped::get_permitted_stack(conn, &upstream_id, Some(user_id), Permission::Read)?;
+
+    let result = (
+        db::get_card_lineage_by_stack(conn, stack_id),
+        db::get_cards_by_stack(conn, stack_id),
+        db::get_note_types_by_stack(conn, stack_id),
+        db::get_cards_by_stack(conn, &upstream_id),
+        db::get_note_types_by_stack(conn, &upstream_id),
+    );
+    match result {
+        (Ok(lineage), Ok(cards), Ok(note_types), Ok(upstream_cards), Ok(upstream_note_types)) => Ok(Fork {
+            lineage,
+            cards,
+            note_types,
+            upstream_cards,
+            upstream_note_types,
+        }),
+        _ => Err(ResponseError::InternalError),
+    }
+}
+
+/// Renders a card of a note type from its fields, the note type has to belong to the stack of the card
+fn set_note_fields(
+    conn: &mut db::Conn,
+    card: &mut models::Card,
+    note_type_id: &str,
+    fields: notes::Fields,
+) -> Result<(), ResponseError> {
+    let note_type = match db::get_note_type(conn, note_type_id) {
+        Ok(value) if value.stack_id == card.stack_id => value,
+        Ok(_) | Err(diesel::result::Error::NotFound) => return Err(ResponseError::InvalidData),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    match card.set_checked_fields(&note_type, fields) {
+        Ok(()) => Ok(()),
+        _ => Err(ResponseError::InvalidData),
+    }
+}
+
+/// Loads a sub-card of a card the user can study, picked by the ordinal (default 0) and direction (default forward) of the request
+fn get_accessible_sub_card(
+    conn: &mut db::Conn,
+    card_id: &str,
+    content: &api_models::CardsRequestContent,
+    user_id: &str,
+) -> Result<models::SubCard, ResponseError> {
+    let card = match db::get_card(conn, card_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    let stack = wrapped::get_permitted_stack(conn, &card.stack_id, Some(user_id), Permission::Read)?;
+
+    let note_types = match db::get_note_types_by_stack(conn, &card.stack_id) {
+        Ok(value) => value,
+        _ => return Err(ResponseError::InternalError)
+    };
+
+    let ordinal = content.ordinal.unwrap_or(0);
+    let direction = content.direction.as_deref().unwrap_or(scheduler::DIRECTION_FORWARD);
+    match card.sub_card(&models::CardLayout::new(&stack, &note_types), ordinal, direction) {
+        Some(value) => Ok(value),
+        None => Err(ResponseError::InvalidData),
+    }
+}
+
+/// Loads the state of a sub-card, sub-cards that were never reviewed get a fresh one
+fn get_sub_card_state(conn: &mut db::Conn, sub_card: &models::SubCard, user_id: &str) -> Result<models::CardState, ResponseError> {
+    match db::get_card_state(conn, user_id, &sub_card.card_id, sub_card.ordinal, &sub_card.direction) {
+        Ok(value) => Ok(value),
+        Err(diesel::result::Error::NotFound) => Ok(models::CardState {
+            ordinal: sub_card.ordinal,
+            direction: sub_card.direction.clone(),
+            ..models::CardState::new(user_id, &sub_card.card_id, utils::get_unix_timestamp() as i64)
+        }),
+        _ => Err(ResponseError::InternalError),
+    }
 }
\ No newline at end of file
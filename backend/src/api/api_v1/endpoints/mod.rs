@@ -10,3 +10,12 @@ pub use users::users_handler;
 
 mod cards;
 pub use cards::cards_handler;
+
+mod study;
+pub use study::study_handler;
+
+mod quiz;
+pub use quiz::quiz_handler;
+
+mod transfer;
+pub use transfer::transfer_handler;
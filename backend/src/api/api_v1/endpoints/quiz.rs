@@ -0,0 +1,312 @@
+use super::{generate_err_response, api_models::{self, ResponseError}, wrapped, api_models::ConvertQuizFromOptional};
+use crate::{utils, db, models, quiz, members::{self, Permission}};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+
+macro_rules! impl_try_from {
+    ($struct_name:ident { $($field:ident : $field_type:ty,)* }) => {
+        #[derive(Debug)]
+        struct $struct_name {
+            $($field: $field_type),*
+        }
+
+        impl api_models::ConvertQuizFromOptional for $struct_name {
+            fn try_from_optional(optional: &api_models::QuizRequestContent) -> Result<Self, &'static str> {
+                $(
+                    let $field = optional.$field.clone().ok_or(concat!(stringify!($field), " is required"))?;
+                )*
+
+                Ok($struct_name {
+                    $($field),*
+                })
+            }
+        }
+    };
+}
+
+#[allow(clippy::needless_lifetimes)] // False positive
+pub async fn quiz_handler<'a>(
+    app_data: web::Data<models::AppData<'a>>,
+    req: HttpRequest,
+    payload: String,
+) -> HttpResponse {
+    let execute = move || -> Result<HttpResponse, ResponseError> {
+        // Validate encoding
+        wrapped::is_utf8(&payload)?;
+        // Parse JSON
+        let request_data = wrapped::parse_json::<api_models::QuizRequest>(&payload)?;
+        // Get db connection
+        let mut conn: db::Conn = wrapped::get_db_conn(&app_data.pool)?;
+        // Quizzes are always taken by a signed in user
+        let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+
+        let content = match request_data.content {
+            Some(data) => data,
+            None => api_models::QuizRequestContent::empty(),
+        };
+
+        match request_data.request_type {
+            api_models::QuizRequestType::GenerateQuiz => {
+                impl_try_from!( ParsedQuizData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedQuizData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let count = content.count.unwrap_or(quiz::DEFAULT_QUESTION_COUNT);
+                if ! quiz::is_valid_question_count(count) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let cards = get_stack_cards(&mut conn, &stack_id, &user_id)?;
+
+                // Same seed gives the same quiz as long as the stack doesn't change
+                let seed = content.seed.unwrap_or(utils::random_seed() as u32);
+                let questions = quiz::generate_questions(&cards, count as usize, seed as u64);
+
+                // Stack needs at least two different answers
+                if questions.is_empty() {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let quiz_id = match db::generate_quiz_id(&mut conn) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let attempt = models::QuizAttempt {
+                    unique_id: quiz_id,
+                    user_id,
+                    stack_id,
+                    seed,
+                    questions: serde_json::to_string(&questions).map_err(|_| ResponseError::InternalError)?,
+                    answers: None,
+                    score: None,
+                    question_count: questions.len() as i32,
+                    created_at: utils::get_unix_timestamp() as i64,
+                    submitted_at: None,
+                    kind: quiz::KIND_MULTIPLE_CHOICE.to_owned(),
+                    max_score: questions.len() as i32,
+                };
+
+                if db::add_quiz_attempt(&mut conn, attempt.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_quiz(api_models::QuizData::new(attempt, questions));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::QuizRequestType::SubmitQuiz => {
+                impl_try_from!( ParsedSubmitData {
+                    unique_id: String,
+                    answers: Vec<i32>,
+                });
+
+                let submit_data = match ParsedSubmitData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut attempt = get_open_attempt(&mut conn, &submit_data.unique_id, &user_id, quiz::KIND_MULTIPLE_CHOICE)?;
+
+                // At most one answer per question
+                if submit_data.answers.len() > attempt.question_count as usize {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let questions = match serde_json::from_str::<Vec<quiz::Question>>(&attempt.questions) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let results = quiz::grade(&questions, &submit_data.answers);
+                attempt.score = Some(results.iter().filter(|correct| **correct).count() as i32);
+                attempt.answers = Some(quiz::selected_to_string(&submit_data.answers));
+                attempt.submitted_at = Some(utils::get_unix_timestamp() as i64);
+
+                if db::update_quiz_attempt(&mut conn, attempt.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_quiz(api_models::QuizData::new(attempt, questions));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::QuizRequestType::GenerateTest => {
+                impl_try_from!( ParsedTestData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedTestData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let count = content.count.unwrap_or(quiz::DEFAULT_QUESTION_COUNT);
+                if ! quiz::is_valid_question_count(count) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let cards = get_stack_cards(&mut conn, &stack_id, &user_id)?;
+
+                let seed = content.seed.unwrap_or(utils::random_seed() as u32);
+                let questions = quiz::generate_test(&cards, count as usize, seed as u64);
+
+                if questions.is_empty() {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let test_id = match db::generate_quiz_id(&mut conn) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let attempt = models::QuizAttempt {
+                    unique_id: test_id,
+                    user_id,
+                    stack_id,
+                    seed,
+                    questions: serde_json::to_string(&questions).map_err(|_| ResponseError::InternalError)?,
+                    answers: None,
+                    score: None,
+                    question_count: questions.len() as i32,
+                    created_at: utils::get_unix_timestamp() as i64,
+                    submitted_at: None,
+                    kind: quiz::KIND_TEST.to_owned(),
+                    max_score: questions.iter().map(|question| question.max_points()).sum(),
+                };
+
+                if db::add_quiz_attempt(&mut conn, attempt.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_test(api_models::TestData::new(attempt, questions));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::QuizRequestType::SubmitTest => {
+                impl_try_from!( ParsedSubmitData {
+                    unique_id: String,
+                    test_answers: Vec<Option<quiz::TestAnswer>>,
+                });
+
+                let submit_data = match ParsedSubmitData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut attempt = get_open_attempt(&mut conn, &submit_data.unique_id, &user_id, quiz::KIND_TEST)?;
+
+                if submit_data.test_answers.len() > attempt.question_count as usize {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let questions = match serde_json::from_str::<Vec<quiz::TestQuestion>>(&attempt.questions) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let feedback = quiz::grade_test(&questions, &submit_data.test_answers);
+                attempt.score = Some(feedback.iter().map(|question| question.points).sum());
+                attempt.answers = Some(serde_json::to_string(&submit_data.test_answers).map_err(|_| ResponseError::InternalError)?);
+                attempt.submitted_at = Some(utils::get_unix_timestamp() as i64);
+
+                if db::update_quiz_attempt(&mut conn, attempt.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                // Feedback for every question comes with the graded test
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_test(api_models::TestData::new(attempt, questions));
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::QuizRequestType::GetTestAttempts => {
+                impl_try_from!( ParsedStackData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStackData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let stack = match db::get_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                // Owner of the stack sees attempts of every learner, everyone else only their own
+                let attempts = if members::allows(&stack, wrapped::get_stack_role(&mut conn, &stack, Some(&user_id))?, Permission::Manage) {
+                    db::get_submitted_quiz_attempts_by_stack(&mut conn, &stack_id)
+                } else {
+                    db::get_submitted_quiz_attempts_by_user(&mut conn, &user_id, &stack_id)
+                };
+
+                let attempts = match attempts {
+                    Ok(value) => value.into_iter().map(|attempt| attempt.into()).collect(),
+                    _ => return Err(ResponseError::InternalError)
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_attempts(attempts);
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+        }
+    };
+
+    match execute() {
+        Ok(resp) => resp,
+        Err(code) => generate_err_response(code)
+    }
+}
+
+
+
+/// Cards of a stack the user can study, cloze cards give one card for every cloze,
+/// note type cards one for every template and cards studied in both directions one for each direction
+fn get_stack_cards(conn: &mut db::Conn, stack_id: &str, user_id: &str) -> Result<Vec<models::SubCard>, ResponseError> {
+    let stack = wrapped::get_permitted_stack(conn, stack_id, Some(user_id), Permission::Read)?;
+
+    let note_types = match db::get_note_types_by_stack(conn, &stack.unique_id) {
+        Ok(value) => value,
+        _ => return Err(ResponseError::InternalError)
+    };
+
+    match db::get_cards_by_stack(conn, stack_id) {
+        Ok(value) => Ok(value.iter().flat_map(|card| card.sub_cards(&models::CardLayout::new(&stack, &note_types))).collect()),
+        _ => Err(ResponseError::InternalError)
+    }
+}
+
+/// Attempts can only be submitted by the user that generated them and only once
+fn get_open_attempt(conn: &mut db::Conn, attempt_id: &str, user_id: &str, kind: &str) -> Result<models::QuizAttempt, ResponseError> {
+    let attempt = match db::get_quiz_attempt(conn, attempt_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    if attempt.user_id != user_id {
+        return Err(ResponseError::Unauthorized);
+    }
+
+    if attempt.submitted_at.is_some() || attempt.kind != kind {
+        return Err(ResponseError::InvalidData);
+    }
+
+    Ok(attempt)
+}
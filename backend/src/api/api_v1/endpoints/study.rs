@@ -0,0 +1,380 @@
+use super::{generate_err_response, api_models::{self, ResponseError}, wrapped, api_models::ConvertStudyFromOptional};
+use crate::{utils, db, models, scheduler, members::Permission};
+
+use std::collections::HashMap;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+macro_rules! impl_try_from {
+    ($struct_name:ident { $($field:ident : $field_type:ty,)* }) => {
+        #[derive(Debug)]
+        struct $struct_name {
+            $($field: $field_type),*
+        }
+
+        impl api_models::ConvertStudyFromOptional for $struct_name {
+            fn try_from_optional(optional: &api_models::StudyRequestContent) -> Result<Self, &'static str> {
+                $(
+                    let $field = optional.$field.clone().ok_or(concat!(stringify!($field), " is required"))?;
+                )*
+
+                Ok($struct_name {
+                    $($field),*
+                })
+            }
+        }
+    };
+}
+
+#[allow(clippy::needless_lifetimes)] // False positive
+pub async fn study_handler<'a>(
+    app_data: web::Data<models::AppData<'a>>,
+    req: HttpRequest,
+    payload: String,
+) -> HttpResponse {
+    let execute = move || -> Result<HttpResponse, ResponseError> {
+        // Validate encoding
+        wrapped::is_utf8(&payload)?;
+        // Parse JSON
+        let request_data = wrapped::parse_json::<api_models::StudyRequest>(&payload)?;
+        // Get db connection
+        let mut conn: db::Conn = wrapped::get_db_conn(&app_data.pool)?;
+        // Every study request is made on behalf of a user
+        let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+
+        let content = match request_data.content {
+            Some(data) => data,
+            None => api_models::StudyRequestContent::empty(),
+        };
+
+        match request_data.request_type {
+            api_models::StudyRequestType::OpenSession => {
+                // Without a stack_id the session goes over all stacks of the user
+                let stacks = match &content.stack_id {
+                    Some(stack_id) => vec![wrapped::get_permitted_stack(&mut conn, stack_id, Some(&user_id), Permission::Read)?],
+                    None => {
+                        match db::get_stacks_by_owner(&mut conn, &user_id) {
+                            Ok(value) => value,
+                            _ => return Err(ResponseError::InternalError)
+                        }
+                    }
+                };
+
+                // Only stacks with at least one of the requested tags
+                let stacks: Vec<models::StackFull> = match &content.tags {
+                    Some(tags) => {
+                        let tags = utils::parse_tags(tags);
+                        let wanted: Vec<&str> = tags.split(',').filter(|tag| ! tag.is_empty()).collect();
+                        stacks.into_iter().filter(|stack| stack.tags.split(',').any(|tag| wanted.contains(&tag))).collect()
+                    }
+                    None => stacks,
+                };
+
+                // Cloze cards are queued once for every cloze, note type cards once for every template
+                // and cards studied in both directions once for each
+                let mut cards: Vec<models::SubCard> = Vec::new();
+                let mut states: Vec<models::CardState> = Vec::new();
+                for stack in &stacks {
+                    let note_types = match db::get_note_types_by_stack(&mut conn, &stack.unique_id) {
+                        Ok(value) => value,
+                        _ => return Err(ResponseError::InternalError)
+                    };
+                    let layout = models::CardLayout::new(stack, &note_types);
+                    match db::get_cards_by_stack(&mut conn, &stack.unique_id) {
+                        Ok(value) => cards.extend(value.iter().flat_map(|card| card.sub_cards(&layout))),
+                        _ => return Err(ResponseError::InternalError)
+                    }
+                    match db::get_card_states_by_stack(&mut conn, &user_id, &stack.unique_id) {
+                        Ok(value) => states.extend(value),
+                        _ => return Err(ResponseError::InternalError)
+                    }
+                }
+
+                let now = utils::get_unix_timestamp() as i64;
+                let mode = content.mode.as_deref().unwrap_or(scheduler::SESSION_REVIEW);
+
+                let (queue, new_count) = match mode {
+                    scheduler::SESSION_REVIEW => {
+                        let user = match db::get_user(&mut conn, &user_id) {
+                            Ok(value) => value,
+                            _ => return Err(ResponseError::InternalError)
+                        };
+
+                        // Daily limits count everything reviewed since midnight (UTC)
+                        let (new_today, reviews_today) = match db::count_reviews_since(&mut conn, &user_id, now - now % scheduler::SECONDS_IN_DAY) {
+                            Ok(value) => value,
+                            _ => return Err(ResponseError::InternalError)
+                        };
+                        let new_limit = (user.new_cards_per_day as i64 - new_today).max(0) as usize;
+                        let review_limit = (user.reviews_per_day as i64 - reviews_today).max(0) as usize;
+
+                        // Every card is due by the scheduler of its stack, the same one answer_card reviews it with
+                        let schedulers: HashMap<&str, scheduler::steps::LearningSteps> = stacks.iter()
+                            .map(|stack| (stack.unique_id.as_str(), scheduler::steps::LearningSteps::for_stack(scheduler::for_stack(&user, stack), stack, &user_id)))
+                            .collect();
+                        let user_scheduler = scheduler::for_user(&user);
+                        let scheduler_of = |card: &models::SubCard| -> &dyn scheduler::Scheduler {
+                            match schedulers.get(card.stack_id.as_str()) {
+                                Some(value) => value,
+                                None => user_scheduler.as_ref(),
+                            }
+                        };
+                        let (reviews, new_cards) = scheduler::split_due_cards_by(scheduler_of, cards, &states, now);
+                        let new_count = new_cards.len().min(new_limit) as i32;
+                        (scheduler::build_queue(reviews, new_cards, review_limit, new_limit), new_count)
+                    }
+
+                    // Cram ignores due dates and daily limits
+                    scheduler::SESSION_CRAM => {
+                        let order = content.order.as_deref().unwrap_or(scheduler::cram::ORDER_RANDOM);
+                        if ! scheduler::cram::is_valid_order(order) {
+                            return Err(ResponseError::InvalidData)
+                        }
+
+                        let filter = scheduler::cram::CramFilter {
+                            min_failures: content.min_failures,
+                            reviewed_within_days: content.reviewed_within_days,
+                        };
+                        if filter.min_failures.is_some_and(|value| value < 0) || filter.reviewed_within_days.is_some_and(|value| value < 1) {
+                            return Err(ResponseError::InvalidData)
+                        }
+
+                        let mut logs: Vec<models::ReviewLogFull> = Vec::new();
+                        for stack in &stacks {
+                            match db::get_review_logs_by_stack(&mut conn, &user_id, &stack.unique_id) {
+                                Ok(value) => logs.extend(value),
+                                _ => return Err(ResponseError::InternalError)
+                            }
+                        }
+
+                        (scheduler::cram::build_cram_queue(cards, &states, &logs, &filter, order, now, utils::random_seed()), 0)
+                    }
+
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let session_id = match db::generate_session_id(&mut conn) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let session = models::StudySession {
+                    unique_id: session_id,
+                    user_id,
+                    stack_id: content.stack_id,
+                    started_at: now,
+                    finished_at: None,
+                    queue: queue.iter().map(models::StudySession::queue_item).collect::<Vec<String>>().join(","),
+                    new_count,
+                    position: 0,
+                    answered_count: 0,
+                    correct_count: 0,
+                    mode: mode.to_owned(),
+                };
+
+                if db::add_study_session(&mut conn, session.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_session(session.into());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::StudyRequestType::NextCard => {
+                impl_try_from!( ParsedSessionData {
+                    unique_id: String,
+                });
+
+                let session_id = match ParsedSessionData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut session = get_open_session(&mut conn, &session_id, &user_id)?;
+                let cards = match next_queued_card(&mut conn, &mut session, &user_id)? {
+                    Some(card) => vec![card.into()],
+                    // Queue is done, client should close the session
+                    None => Vec::new(),
+                };
+
+                if db::update_study_session(&mut conn, session.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_cards(cards);
+                response_struct.set_session(session.into());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::StudyRequestType::AnswerCard => {
+                impl_try_from!( ParsedAnswerData {
+                    unique_id: String,
+                    grade: u8,
+                    card_id: String,
+                    ordinal: i32,
+                    direction: String,
+                });
+
+                let answer_data = match ParsedAnswerData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                if ! scheduler::is_valid_grade(answer_data.grade) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Time it took the user to answer in milliseconds
+                let time_taken = content.time_taken.unwrap_or(0);
+                if time_taken < 0 {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let mut session = get_open_session(&mut conn, &answer_data.unique_id, &user_id)?;
+                let card = match next_queued_card(&mut conn, &mut session, &user_id)? {
+                    Some(card) => card,
+                    None => return Err(ResponseError::InvalidData),
+                };
+
+                // The answer has to be for the card next_card handed out, not for one that is already gone or still to come
+                if ! card.is_sub_card(&answer_data.card_id, answer_data.ordinal, &answer_data.direction) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                session.position += 1;
+                session.answered_count += 1;
+                if answer_data.grade >= 3 {
+                    session.correct_count += 1;
+                }
+
+                let mut response_struct = api_models::Response::new();
+
+                if session.mode == scheduler::SESSION_CRAM {
+                    // Cram answers are not reviews, failed cards just come back at the end of the queue
+                    if answer_data.grade < 3 {
+                        session.push_queue_item(&card);
+                    }
+                } else {
+                    let state = wrapped::review_card(&mut conn, &user_id, &card, answer_data.grade, time_taken)?;
+
+                    // Cards in short learning steps come back at the end of the queue
+                    if scheduler::steps::is_in_short_step(&state, utils::get_unix_timestamp() as i64) {
+                        session.push_queue_item(&card);
+                    }
+                    response_struct.set_card_states(vec![state.into()]);
+                }
+
+                if db::update_study_session(&mut conn, session.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                response_struct.set_session(session.into());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::StudyRequestType::CloseSession => {
+                impl_try_from!( ParsedSessionData {
+                    unique_id: String,
+                });
+
+                let session_id = match ParsedSessionData::try_from_optional(&content) {
+                    Ok(value) => value.unique_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut session = get_open_session(&mut conn, &session_id, &user_id)?;
+                session.finished_at = Some(utils::get_unix_timestamp() as i64);
+
+                if db::update_study_session(&mut conn, session.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                // Summary of the whole session
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_session(session.into());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+        }
+    };
+
+    match execute() {
+        Ok(resp) => resp,
+        Err(code) => generate_err_response(code)
+    }
+}
+
+
+
+/// Sessions can only be used by the user that opened them and only until they are closed
+fn get_open_session(conn: &mut db::Conn, session_id: &str, user_id: &str) -> Result<models::StudySession, ResponseError> {
+    let session = match db::get_study_session(conn, session_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    if session.user_id != user_id {
+        return Err(ResponseError::Unauthorized);
+    }
+
+    if session.finished_at.is_some() {
+        return Err(ResponseError::InvalidData);
+    }
+
+    Ok(session)
+}
+
+/// Finds the sub-card at the session position, skipping cards that were deleted, suspended, buried,
+/// lost the cloze, template or direction since the session was opened or whose stack stopped being accessible
+fn next_queued_card(conn: &mut db::Conn, session: &mut models::StudySession, user_id: &str) -> Result<Option<models::SubCard>, ResponseError> {
+    let queue: Vec<(String, i32, String)> = session.queue_items().into_iter()
+        .map(|(id, ordinal, direction)| (id.to_owned(), ordinal, direction.to_owned()))
+        .collect();
+
+    while (session.position as usize) < queue.len() {
+        let (card_id, ordinal, direction) = &queue[session.position as usize];
+        let card = match db::get_card(conn, card_id) {
+            Ok(value) => value,
+            Err(diesel::result::Error::NotFound) => {
+                session.position += 1;
+                continue;
+            }
+            _ => return Err(ResponseError::InternalError),
+        };
+
+        let stack = match wrapped::get_permitted_stack(conn, &card.stack_id, Some(user_id), Permission::Read) {
+            Ok(stack) => stack,
+            Err(ResponseError::Unauthorized) => {
+                session.position += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let note_types = match db::get_note_types_by_stack(conn, &stack.unique_id) {
+            Ok(value) => value,
+            _ => return Err(ResponseError::InternalError),
+        };
+
+        let sub_card = match card.sub_card(&models::CardLayout::new(&stack, &note_types), *ordinal, direction) {
+            Some(value) => value,
+            None => {
+                session.position += 1;
+                continue;
+            }
+        };
+
+        match db::get_card_state(conn, user_id, card_id, *ordinal, direction) {
+            Ok(state) if state.suspended || state.buried_until > utils::get_unix_timestamp() as i64 => session.position += 1,
+            Ok(_) | Err(diesel::result::Error::NotFound) => return Ok(Some(sub_card)),
+            _ => return Err(ResponseError::InternalError),
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,595 @@
+use super::{generate_err_response, api_models::{self, ResponseError}, wrapped, api_models::ConvertTransferFromOptional};
+use crate::{db, models, notes, scheduler, transfer::{self, account, delimited}, utils, members::Permission};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::Engine;
+use diesel::Connection;
+
+macro_rules! impl_try_from {
+    ($struct_name:ident { $($field:ident : $field_type:ty,)* }) => {
+        #[derive(Debug)]
+        struct $struct_name {
+            $($field: $field_type),*
+        }
+
+        impl api_models::ConvertTransferFromOptional for $struct_name {
+            fn try_from_optional(optional: &api_models::TransferRequestContent) -> Result<Self, &'static str> {
+                $(
+                    let $field = optional.$field.clone().ok_or(concat!(stringify!($field), " is required"))?;
+                )*
+
+                Ok($struct_name {
+                    $($field),*
+                })
+            }
+        }
+    };
+}
+
+#[allow(clippy::needless_lifetimes)] // False positive
+pub async fn transfer_handler<'a>(
+    app_data: web::Data<models::AppData<'a>>,
+    req: HttpRequest,
+    payload: String,
+) -> HttpResponse {
+    let execute = move || -> Result<HttpResponse, ResponseError> {
+        // Validate encoding
+        wrapped::is_utf8(&payload)?;
+        // Parse JSON
+        let request_data = wrapped::parse_json::<api_models::TransferRequest>(&payload)?;
+        // Get db connection
+        let mut conn: db::Conn = wrapped::get_db_conn(&app_data.pool)?;
+        // Imports always go to the account of the signed in user, exports need it to check access
+        let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+
+        let content = match request_data.content {
+            Some(data) => data,
+            None => api_models::TransferRequestContent::empty(),
+        };
+
+        match request_data.request_type {
+            api_models::TransferRequestType::ImportApkg => {
+                impl_try_from!( ParsedFileData {
+                    file: String,
+                });
+
+                let file = match ParsedFileData::try_from_optional(&content) {
+                    Ok(value) => value.file,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let file = match base64::engine::general_purpose::STANDARD.decode(file) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let (stacks, warnings) = match transfer::anki::parse(&file, content.include_history.unwrap_or(false)) {
+                    Some(value) => value,
+                    None => return Err(ResponseError::InvalidData),
+                };
+
+                // Nothing is stored if any of it fails
+                let stack_ids = match conn.transaction(|conn| db::add_imported_stacks(conn, &user_id, stacks)) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_stacks(get_stacks(&mut conn, &stack_ids)?);
+                response_struct.set_warnings(warnings.into_iter().map(api_models::WarningData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::TransferRequestType::ExportApkg => {
+                impl_try_from!( ParsedStackData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStackData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Users can export the stacks they can read
+                let stack = wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+
+                let note_types = match db::get_note_types_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+                let cards = match db::get_cards_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+                // Review history is personal, it is only exported for the owner
+                let reviews = if stack.owner_id == user_id {
+                    match db::get_review_logs_by_stack(&mut conn, &user_id, &stack_id) {
+                        Ok(value) => value,
+                        _ => return Err(ResponseError::InternalError),
+                    }
+                } else {
+                    Vec::new()
+                };
+
+                let file = match transfer::anki::write(&stack, &note_types, &cards, &reviews, utils::get_unix_timestamp() as i64) {
+                    Some(value) => value,
+                    None => return Err(ResponseError::InternalError),
+                };
+
+                Ok(HttpResponse::Ok()
+                    .content_type("application/apkg")
+                    .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.apkg\"", file_name(&stack.name))))
+                    .body(file))
+            }
+
+
+            api_models::TransferRequestType::ImportCards => {
+                impl_try_from!( ParsedCardsData {
+                    stack_id: String,
+                    text: String,
+                });
+
+                let cards_data = match ParsedCardsData::try_from_optional(&content) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+                let format = get_format(&content)?;
+
+                // Cards can only be added to stacks the user can edit
+                wrapped::get_permitted_stack(&mut conn, &cards_data.stack_id, Some(&user_id), Permission::Edit)?;
+
+                let note_type = get_note_type(&mut conn, content.note_type_id.as_deref(), &cards_data.stack_id)?;
+                let field_names = note_type.as_ref().map(|note_type| note_type.field_names());
+
+                let mut rows = match delimited::read(&cards_data.text, &format) {
+                    Ok(value) => value,
+                    Err(line) => return Ok(row_errors_response(vec![api_models::RowErrorData { line, message: "quoted value is never closed".to_owned() }])),
+                };
+                let header = if format.header && ! rows.is_empty() { Some(rows.remove(0)) } else { None };
+
+                let columns = match (content.columns, header) {
+                    (Some(columns), _) => columns,
+                    (None, Some(header)) => header.values.iter().map(|value| value.trim().to_owned()).collect(),
+                    (None, None) => delimited::default_columns(field_names.as_deref()),
+                };
+                if ! delimited::is_valid_columns(&columns, field_names.as_deref()) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                // Every row is checked before anything is stored
+                let mut cards: Vec<models::Card> = Vec::with_capacity(rows.len());
+                let mut row_errors: Vec<api_models::RowErrorData> = Vec::new();
+                for row in &rows {
+                    match delimited::row_to_card(row, &columns, note_type.is_some()).and_then(|card| new_card(card, &cards_data.stack_id, note_type.as_ref())) {
+                        Ok(card) => cards.push(card),
+                        Err(message) => row_errors.push(api_models::RowErrorData { line: row.line, message: message.to_owned() }),
+                    }
+                }
+                if ! row_errors.is_empty() {
+                    return Ok(row_errors_response(row_errors))
+                }
+
+                let cards = match conn.transaction(|conn| store_cards(conn, cards)) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_cards(cards.into_iter().map(api_models::CardData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::TransferRequestType::ExportCards => {
+                impl_try_from!( ParsedStackData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStackData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+                let format = get_format(&content)?;
+
+                // Users can export the stacks they can read
+                let stack = wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+
+                // Only cards of one note type fit into the columns of a file
+                let note_type = get_note_type(&mut conn, content.note_type_id.as_deref(), &stack_id)?;
+                let note_type_id = note_type.as_ref().map_or(notes::BASIC_NOTE_TYPE_ID, |note_type| note_type.unique_id.as_str());
+                let field_names = note_type.as_ref().map(|note_type| note_type.field_names());
+
+                let columns = content.columns.unwrap_or(delimited::export_columns(field_names.as_deref()));
+                if ! delimited::is_valid_columns(&columns, field_names.as_deref()) {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let cards = match db::get_cards_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut rows: Vec<Vec<String>> = Vec::with_capacity(cards.len() + 1);
+                if format.header {
+                    rows.push(columns.clone());
+                }
+                rows.extend(cards.iter().filter(|card| card.note_type_id == note_type_id).map(|card| delimited::card_to_row(card, &columns)));
+
+                let (content_type, extension) = if format.delimiter == '\t' { ("text/tab-separated-values", "tsv") } else { ("text/csv", "csv") };
+                Ok(HttpResponse::Ok()
+                    .content_type(content_type)
+                    .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.{}\"", file_name(&stack.name), extension)))
+                    .body(delimited::write(&rows, &format)))
+            }
+
+
+            api_models::TransferRequestType::ImportText => {
+                impl_try_from!( ParsedTextData {
+                    text: String,
+                });
+
+                let text = match ParsedTextData::try_from_optional(&content) {
+                    Ok(value) => value.text,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut separators = transfer::text::Separators::default();
+                if let Some(value) = content.term_separator {
+                    separators.term = value;
+                }
+                if let Some(value) = content.card_separator {
+                    separators.card = value;
+                }
+                if ! separators.is_valid() {
+                    return Err(ResponseError::InvalidData)
+                }
+
+                let preview = content.preview.unwrap_or(false);
+                // A preview isn't stored, so it doesn't need a stack
+                let stack_id = match (content.stack_id, preview) {
+                    (Some(stack_id), _) => {
+                        wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Edit)?;
+                        stack_id
+                    }
+                    (None, true) => String::new(),
+                    (None, false) => return Err(ResponseError::InvalidData),
+                };
+
+                let mut cards: Vec<models::Card> = Vec::new();
+                let mut row_errors: Vec<api_models::RowErrorData> = Vec::new();
+                for term in transfer::text::parse(&text, &separators) {
+                    match term.card.and_then(|card| new_card(card, &stack_id, None)) {
+                        Ok(card) => cards.push(card),
+                        Err(message) => row_errors.push(api_models::RowErrorData { line: term.line, message: message.to_owned() }),
+                    }
+                }
+
+                if preview {
+                    let mut response_struct = api_models::Response::new();
+                    response_struct.set_cards(cards.into_iter().map(api_models::CardData::from).collect());
+                    response_struct.set_row_errors(row_errors);
+                    return Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+                }
+                if ! row_errors.is_empty() {
+                    return Ok(row_errors_response(row_errors))
+                }
+
+                let cards = match conn.transaction(|conn| store_cards(conn, cards)) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_cards(cards.into_iter().map(api_models::CardData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::TransferRequestType::ImportMarkdown => {
+                impl_try_from!( ParsedTextData {
+                    text: String,
+                });
+
+                let text = match ParsedTextData::try_from_optional(&content) {
+                    Ok(value) => value.text,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let (stack, warnings) = transfer::markdown::parse(&text);
+                let stack_ids = match conn.transaction(|conn| db::add_imported_stacks(conn, &user_id, vec![stack])) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_stacks(get_stacks(&mut conn, &stack_ids)?);
+                response_struct.set_warnings(warnings.into_iter().map(api_models::WarningData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+
+
+            api_models::TransferRequestType::ExportMarkdown => {
+                impl_try_from!( ParsedStackData {
+                    stack_id: String,
+                });
+
+                let stack_id = match ParsedStackData::try_from_optional(&content) {
+                    Ok(value) => value.stack_id,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                // Users can export the stacks they can read
+                let stack = wrapped::get_permitted_stack(&mut conn, &stack_id, Some(&user_id), Permission::Read)?;
+
+                let note_types = match db::get_note_types_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+                let cards = match db::get_cards_by_stack(&mut conn, &stack_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                Ok(HttpResponse::Ok()
+                    .content_type("text/markdown")
+                    .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.md\"", file_name(&stack.name))))
+                    .body(transfer::markdown::write(&stack, &note_types, &cards)))
+            }
+
+
+            api_models::TransferRequestType::ExportAccount => {
+                let user = match db::get_user(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+                let stacks = match db::get_stacks_by_owner(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut archived_stacks: Vec<account::ArchivedStack> = Vec::with_capacity(stacks.len());
+                for stack in &stacks {
+                    let note_types = match db::get_note_types_by_stack(&mut conn, &stack.unique_id) {
+                        Ok(value) => value,
+                        _ => return Err(ResponseError::InternalError),
+                    };
+                    let cards = match db::get_cards_by_stack(&mut conn, &stack.unique_id) {
+                        Ok(value) => value,
+                        _ => return Err(ResponseError::InternalError),
+                    };
+                    archived_stacks.push(account::ArchivedStack::new(stack, &note_types, &cards));
+                }
+
+                let history = (
+                    db::get_card_states(&mut conn, &user_id),
+                    db::get_review_logs(&mut conn, &user_id),
+                    db::get_study_sessions_by_user(&mut conn, &user_id),
+                    db::get_quiz_attempts_by_user(&mut conn, &user_id),
+                );
+                let (card_states, reviews, study_sessions, quiz_attempts) = match history {
+                    (Ok(card_states), Ok(reviews), Ok(study_sessions), Ok(quiz_attempts)) => (card_states, reviews, study_sessions, quiz_attempts),
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let archive = account::Archive {
+                    format: account::FORMAT.to_owned(),
+                    version: account::VERSION,
+                    exported_at: utils::get_unix_timestamp() as i64,
+                    profile: account::ArchivedProfile::from(&user),
+                    stacks: archived_stacks,
+                    card_states: card_states.into_iter().map(account::ArchivedCardState::from).collect(),
+                    reviews: reviews.into_iter().map(account::ArchivedReview::from).collect(),
+                    study_sessions: study_sessions.into_iter().map(account::ArchivedStudySession::from).collect(),
+                    quiz_attempts: quiz_attempts.into_iter().map(account::ArchivedQuizAttempt::from).collect(),
+                };
+                let body = match serde_json::to_string(&archive) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                Ok(HttpResponse::Ok()
+                    .content_type("application/json")
+                    .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.json\"", file_name(&user.username))))
+                    .body(body))
+            }
+
+
+            api_models::TransferRequestType::ImportAccount => {
+                impl_try_from!( ParsedTextData {
+                    text: String,
+                });
+
+                let text = match ParsedTextData::try_from_optional(&content) {
+                    Ok(value) => value.text,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+                let archive = match serde_json::from_str::<account::Archive>(&text) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InvalidData),
+                };
+
+                let mut user = match db::get_user(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+                let mut restored = match account::restore(archive, &mut user) {
+                    Some(value) => value,
+                    None => return Err(ResponseError::InvalidData),
+                };
+
+                // History of cards in stacks of other users is only kept while the user can still study them
+                let mut accessible: std::collections::HashMap<String, Option<String>> = std::collections::HashMap::new();
+                let card_ids = restored.other_states.iter().map(|state| &state.card_id).chain(restored.other_reviews.iter().map(|review| &review.card_id));
+                for card_id in card_ids {
+                    if accessible.contains_key(card_id) {
+                        continue;
+                    }
+                    let stack_id = match db::get_card(&mut conn, card_id) {
+                        Ok(card) => match wrapped::get_permitted_stack(&mut conn, &card.stack_id, Some(&user_id), Permission::Read) {
+                            Ok(stack) => Some(stack.unique_id),
+                            Err(ResponseError::Unauthorized) => None,
+                            Err(err) => return Err(err),
+                        },
+                        Err(diesel::result::Error::NotFound) => None,
+                        _ => return Err(ResponseError::InternalError),
+                    };
+                    if stack_id.is_none() {
+                        restored.warnings.push(transfer::Warning::new(&format!("card {}", card_id), "card doesn't exist anymore or is private, its history is left out"));
+                    }
+                    accessible.insert(card_id.clone(), stack_id);
+                }
+
+                let stacks = restored.stacks;
+                let other_states = restored.other_states;
+                let other_reviews = restored.other_reviews;
+                let result = conn.transaction(|conn| -> Result<Vec<String>, diesel::result::Error> {
+                    db::update_user(conn, user)?;
+                    let stack_ids = db::add_imported_stacks(conn, &user_id, stacks)?;
+
+                    // Newer progress of the account and reviews it already has are kept as they are
+                    let (other_states, other_reviews) = account::new_history(
+                        other_states,
+                        other_reviews,
+                        &db::get_card_states(conn, &user_id)?,
+                        &db::get_review_logs(conn, &user_id)?,
+                    );
+                    for state in other_states {
+                        if let Some(Some(_)) = accessible.get(&state.card_id) {
+                            db::save_card_state(conn, models::CardState { user_id: user_id.clone(), ..state })?;
+                        }
+                    }
+                    for review in other_reviews {
+                        if let Some(Some(stack_id)) = accessible.get(&review.card_id) {
+                            db::add_review_log(conn, models::ReviewLog {
+                                user_id: user_id.clone(),
+                                card_id: review.card_id,
+                                stack_id: stack_id.clone(),
+                                reviewed_at: review.reviewed_at,
+                                grade: review.grade,
+                                previous_interval: review.previous_interval,
+                                next_interval: review.next_interval,
+                                time_taken: review.time_taken,
+                                phase: review.phase,
+                                ordinal: review.ordinal,
+                                direction: review.direction,
+                            })?;
+                        }
+                    }
+                    Ok(stack_ids)
+                });
+                let stack_ids = match result {
+                    Ok(value) => value,
+                    _ => return Err(ResponseError::InternalError),
+                };
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_stacks(get_stacks(&mut conn, &stack_ids)?);
+                response_struct.set_warnings(restored.warnings.into_iter().map(api_models::WarningData::from).collect());
+                Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()))
+            }
+        }
+    };
+
+    match execute() {
+        Ok(resp) => resp,
+        Err(code) => generate_err_response(code)
+    }
+}
+
+
+
+/// Delimiter (default `,`), quote char (default `"`, empty for none) and header row of a CSV or TSV request
+fn get_format(content: &api_models::TransferRequestContent) -> Result<delimited::Format, ResponseError> {
+    let single = |value: &str| -> Option<char> {
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    let mut format = delimited::Format::default();
+    if let Some(value) = &content.delimiter {
+        format.delimiter = single(value).ok_or(ResponseError::InvalidData)?;
+    }
+    if let Some(value) = &content.quote {
+        format.quote = if value.is_empty() { None } else { Some(single(value).ok_or(ResponseError::InvalidData)?) };
+    }
+    format.header = content.header.unwrap_or(false);
+
+    if ! format.is_valid() {
+        return Err(ResponseError::InvalidData)
+    }
+    Ok(format)
+}
+
+/// Note type of a stack picked by a request, None for the built-in Basic note type
+fn get_note_type(conn: &mut db::Conn, note_type_id: Option<&str>, stack_id: &str) -> Result<Option<models::NoteType>, ResponseError> {
+    match note_type_id {
+        None | Some(notes::BASIC_NOTE_TYPE_ID) => Ok(None),
+        Some(id) => match db::get_note_type(conn, id) {
+            Ok(value) if value.stack_id == stack_id => Ok(Some(value)),
+            Ok(_) | Err(diesel::result::Error::NotFound) => Err(ResponseError::InvalidData),
+            _ => Err(ResponseError::InternalError),
+        },
+    }
+}
+
+/// Checks an imported card with the same rules as create_card
+fn new_card(imported: transfer::ImportedCard, stack_id: &str, note_type: Option<&models::NoteType>) -> Result<models::Card, &'static str> {
+    // Empty directions follow the stack
+    if ! scheduler::is_valid_card_directions(&imported.directions) {
+        return Err("invalid directions")
+    }
+
+    let mut card = models::Card {
+        unique_id: String::new(),
+        stack_id: stack_id.to_owned(),
+        frontside: imported.frontside,
+        backside: imported.backside,
+        card_type: imported.card_type,
+        directions: imported.directions,
+        note_type_id: String::new(),
+        fields: String::new(),
+    };
+    match note_type {
+        Some(note_type) => card.set_checked_fields(note_type, imported.fields)?,
+        None => card.check_basic()?,
+    }
+    Ok(card)
+}
+
+fn store_cards(conn: &mut db::Conn, cards: Vec<models::Card>) -> Result<Vec<models::Card>, diesel::result::Error> {
+    let mut stored: Vec<models::Card> = Vec::with_capacity(cards.len());
+    for mut card in cards {
+        card.unique_id = db::generate_card_id(conn)?;
+        db::add_card(conn, card.clone())?;
+        stored.push(card);
+    }
+    Ok(stored)
+}
+
+/// Nothing of the file is stored when a row can't become a card
+fn row_errors_response(row_errors: Vec<api_models::RowErrorData>) -> HttpResponse {
+    let mut response_struct = api_models::Response::new_err(vec![ResponseError::InvalidData.parse()]);
+    response_struct.set_row_errors(row_errors);
+    HttpResponse::BadRequest().content_type("application/json").body(response_struct.to_string())
+}
+
+/// Stack name without characters that don't belong in a file name
+fn file_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+fn get_stacks(conn: &mut db::Conn, stack_ids: &[String]) -> Result<Vec<api_models::StackData>, ResponseError> {
+    let mut output: Vec<api_models::StackData> = Vec::with_capacity(stack_ids.len());
+    for stack_id in stack_ids {
+        match db::get_stack(conn, stack_id) {
+            Ok(value) => output.push(value.into()),
+            _ => return Err(ResponseError::InternalError),
+        }
+    }
+    Ok(output)
+}
@@ -1,7 +1,8 @@
 use super::{generate_err_response, api_models::{self, ResponseError}, wrapped, api_models::ConvertUserFromOptional};
-use crate::{utils, db, auth, models};
+use crate::{utils, db, auth, models, scheduler};
 
 use diesel::result::DatabaseErrorKind;
+use diesel::Connection;
 use actix_web::{web, HttpRequest, HttpResponse};
 
 macro_rules! impl_try_from {
@@ -147,7 +148,11 @@ pub async fn users_handler<'a>(
                     email: user_data.email,
                     password_hash: auth::hash_password(&app_data.argon2, &user_data.password),
                     date_of_registration: utils::get_unix_timestamp() as i64,
-                    country: user_data.country
+                    country: user_data.country,
+                    scheduler: scheduler::SCHEDULER_SM2.to_owned(),
+                    fsrs_parameters: String::new(),
+                    new_cards_per_day: 20,
+                    reviews_per_day: 200,
                 };
 
                 match db::add_user(&mut conn, user) {
@@ -216,6 +221,37 @@ pub async fn users_handler<'a>(
                     user_data.country = country;
                 }
 
+                // Validate scheduler
+                if let Some(scheduler_name) = content.scheduler {
+                    if ! scheduler::is_valid_scheduler_name(&scheduler_name) {
+                        return Err(ResponseError::InvalidData);
+                    }
+                    user_data.scheduler = scheduler_name;
+                }
+
+                // Validate FSRS parameters
+                if let Some(parameters) = content.fsrs_parameters {
+                    if ! scheduler::fsrs::is_valid_parameters(&parameters) {
+                        return Err(ResponseError::InvalidData);
+                    }
+                    user_data.fsrs_parameters = scheduler::fsrs::parameters_to_string(&parameters);
+                }
+
+                // Validate daily limits
+                if let Some(limit) = content.new_cards_per_day {
+                    if ! utils::is_valid_daily_limit(limit) {
+                        return Err(ResponseError::InvalidData);
+                    }
+                    user_data.new_cards_per_day = limit;
+                }
+
+                if let Some(limit) = content.reviews_per_day {
+                    if ! utils::is_valid_daily_limit(limit) {
+                        return Err(ResponseError::InvalidData);
+                    }
+                    user_data.reviews_per_day = limit;
+                }
+
                 match db::update_user(&mut conn, user_data) {
                     Ok(_) => {}
                     Err(diesel::result::Error::DatabaseError(kind, _)) => {
@@ -260,7 +296,7 @@ pub async fn users_handler<'a>(
                     return Err(ResponseError::CouldntAuthenticate);
                 }
 
-                match db::delete_user(&mut conn, &user_id) {
+                match conn.transaction(|conn| db::delete_user(conn, &user_id)) {
                     Ok(_) => {}
                     Err(_) => {
                         return Err(ResponseError::InternalError);
@@ -269,6 +305,44 @@ pub async fn users_handler<'a>(
 
                 return Ok(HttpResponse::Ok().content_type("application/json").body(api_models::Response::empty_ok().to_string()));
             }
+
+
+
+            api_models::UsersRequestType::OptimizeScheduler => {
+                let user_id = wrapped::authenticate(&req, &app_data.jwt_secret)?;
+
+                let mut user_data = match db::get_user(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => {
+                        return Err(ResponseError::InternalError);
+                    }
+                };
+
+                let logs = match db::get_review_logs(&mut conn, &user_id) {
+                    Ok(value) => value,
+                    _ => {
+                        return Err(ResponseError::InternalError);
+                    }
+                };
+
+                // Start from the current parameters so repeated calls refine them
+                let current = scheduler::fsrs::Fsrs::from_parameters_str(&user_data.fsrs_parameters).parameters;
+                let sequences = scheduler::fsrs::sequences_from_logs(&logs);
+                let optimized = match scheduler::fsrs::optimize(&current, &sequences) {
+                    Some(value) => value,
+                    // Not enough reviews yet
+                    None => return Err(ResponseError::InvalidData),
+                };
+
+                user_data.fsrs_parameters = scheduler::fsrs::parameters_to_string(&optimized);
+                if db::update_user(&mut conn, user_data.clone()).is_err() {
+                    return Err(ResponseError::InternalError);
+                }
+
+                let mut response_struct = api_models::Response::new();
+                response_struct.set_user(api_models::UserData::from(user_data, true));
+                return Ok(HttpResponse::Ok().content_type("application/json").body(response_struct.to_string()));
+            }
         }
 
         Err(ResponseError::InvalidData)
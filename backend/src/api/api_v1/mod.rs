@@ -4,6 +4,9 @@ mod api_models;
 mod wrapped;
 mod endpoints;
 
+/// Files are uploaded base64 encoded, other endpoints keep the default limit of 256 KiB
+const MAX_TRANSFER_PAYLOAD: usize = 64 * 1024 * 1024;
+
 pub fn generate_err_response(err_code: api_models::ResponseError) -> HttpResponse {
     let response_struct = api_models::Response::new_err( vec![err_code.parse()] );
     HttpResponse::BadRequest().content_type("application/json").body(response_struct.to_string())
@@ -19,5 +22,15 @@ pub fn api_v1(cfg: &mut web::ServiceConfig) {
         .route("users/", web::post().to(endpoints::users_handler))
 
         .route("cards", web::post().to(endpoints::cards_handler))
-        .route("cards/", web::post().to(endpoints::cards_handler));
+        .route("cards/", web::post().to(endpoints::cards_handler))
+
+        .route("study", web::post().to(endpoints::study_handler))
+        .route("study/", web::post().to(endpoints::study_handler))
+
+        .route("quiz", web::post().to(endpoints::quiz_handler))
+        .route("quiz/", web::post().to(endpoints::quiz_handler))
+
+        .service(web::resource(["transfer", "transfer/"])
+            .app_data(web::PayloadConfig::new(MAX_TRANSFER_PAYLOAD))
+            .route(web::post().to(endpoints::transfer_handler)));
 }
\ No newline at end of file
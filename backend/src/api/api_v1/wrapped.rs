@@ -1,8 +1,9 @@
 //! ## A collection of functions wrapped to return api_models::ResponseError
 
 use crate::api::api_v1::api_models::ResponseError;
-use crate::{utils, db, auth,};
+use crate::{utils, db, auth, models, members, scheduler::{self, Scheduler}};
 use actix_web::HttpRequest;
+use diesel::Connection;
 
 pub fn is_ascii(content: &str) -> Result<(), ResponseError> {
     if ! utils::is_ascii(content) {
@@ -34,4 +35,115 @@ pub fn authenticate(req: &HttpRequest, jwt_secret: &str) -> Result<String, Respo
     
     auth::authorize_jwt::<auth::Claims>(jwt_secret, &jwt)
     .map_err(|_| ResponseError::CouldntAuthenticate)
+}
+
+/// Loads the role of a user in a stack, None for anonymous users and users who are neither the owner nor a member
+pub fn get_stack_role(conn: &mut db::Conn, stack: &models::StackFull, user_id: Option<&str>) -> Result<Option<&'static str>, ResponseError> {
+    let user_id = match user_id {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if stack.owner_id == user_id {
+        return Ok(Some(members::ROLE_OWNER))
+    }
+
+    match db::get_stack_member(conn, &stack.unique_id, user_id) {
+        Ok(member) => Ok(members::role(stack, Some(&member), user_id)),
+        Err(diesel::result::Error::NotFound) => Ok(None),
+        _ => Err(ResponseError::InternalError),
+    }
+}
+
+/// Loads a stack the user has a permission for, stacks without it are reported as Unauthorized like missing ones
+pub fn get_permitted_stack(
+    conn: &mut db::Conn,
+    stack_id: &str,
+    user_id: Option<&str>,
+    permission: members::Permission,
+) -> Result<models::StackFull, ResponseError> {
+    let stack = match db::get_stack(conn, stack_id) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    let role = get_stack_role(conn, &stack, user_id)?;
+    if ! members::allows(&stack, role, permission) {
+        return Err(ResponseError::Unauthorized)
+    }
+    Ok(stack)
+}
+
+/// Loads a stack the user can read, a valid share token of the stack gives access as well
+pub fn get_shared_stack(
+    conn: &mut db::Conn,
+    stack_id: &str,
+    user_id: Option<&str>,
+    token: Option<&str>,
+) -> Result<models::StackFull, ResponseError> {
+    let token = match (get_permitted_stack(conn, stack_id, user_id, members::Permission::Read), token) {
+        (Err(ResponseError::Unauthorized), Some(token)) => token,
+        (result, _) => return result,
+    };
+
+    match db::get_share_token(conn, token) {
+        Ok(value) if members::is_valid_token(&value, stack_id, utils::get_unix_timestamp() as i64) => {}
+        Ok(_) | Err(diesel::result::Error::NotFound) => return Err(ResponseError::Unauthorized),
+        _ => return Err(ResponseError::InternalError),
+    }
+    db::get_stack(conn, stack_id).map_err(|_| ResponseError::InternalError)
+}
+
+/// Applies a review to the state of a sub-card and appends it to the review log.
+/// Callers have to check if the user can access the card.
+pub fn review_card(
+    conn: &mut db::Conn,
+    user_id: &str,
+    card: &models::SubCard,
+    grade: u8,
+    time_taken: i32,
+) -> Result<models::CardState, ResponseError> {
+    let user = db::get_user(conn, user_id).map_err(|_| ResponseError::InternalError)?;
+    let stack = db::get_stack(conn, &card.stack_id).map_err(|_| ResponseError::InternalError)?;
+    let card_scheduler = scheduler::steps::LearningSteps::for_stack(scheduler::for_stack(&user, &stack), &stack, user_id);
+
+    let now = utils::get_unix_timestamp() as i64;
+    let mut state = match db::get_card_state(conn, user_id, &card.card_id, card.ordinal, &card.direction) {
+        Ok(value) => value,
+        Err(diesel::result::Error::NotFound) => models::CardState {
+            ordinal: card.ordinal,
+            direction: card.direction.clone(),
+            ..models::CardState::new(user_id, &card.card_id, now)
+        },
+        _ => return Err(ResponseError::InternalError),
+    };
+
+    let previous_interval = state.interval_days;
+    let previous_phase = state.phase.clone();
+    card_scheduler.review(&mut state, grade, now);
+
+    let log = models::ReviewLog {
+        user_id: user_id.to_owned(),
+        card_id: card.card_id.to_owned(),
+        stack_id: card.stack_id.to_owned(),
+        reviewed_at: now,
+        grade: grade as i32,
+        previous_interval,
+        next_interval: state.interval_days,
+        time_taken,
+        phase: previous_phase,
+        ordinal: card.ordinal,
+        direction: card.direction.clone(),
+    };
+
+    // The state only moves on together with its history, optimize_scheduler relies on the log being complete
+    let result = conn.transaction(|conn| -> Result<(), diesel::result::Error> {
+        db::save_card_state(conn, state.clone())?;
+        db::add_review_log(conn, log)?;
+        Ok(())
+    });
+    if result.is_err() {
+        return Err(ResponseError::InternalError)
+    }
+    Ok(state)
 }
\ No newline at end of file
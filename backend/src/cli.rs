@@ -0,0 +1,63 @@
+//! ## Commands
+//!
+//! Run against the database of the `.env` file instead of starting the server.
+//!
+//! - `backend import-markdown <user id> <file>` adds the stack of a Markdown file to an account
+//! - `backend export-markdown <stack id> [file]` writes a stack as Markdown, to stdout without a file
+
+use diesel::Connection;
+use crate::{db, transfer};
+
+pub const USAGE: &str = "Usage:
+    backend                                     start the server
+    backend import-markdown <user id> <file>    add the stack of a Markdown file to an account
+    backend export-markdown <stack id> [file]   write a stack as Markdown, to stdout without a file";
+
+/// ## Runs a command.
+///
+/// ### Arguments
+///
+/// * `pool` - &db::Pool
+/// * `args` - &[String], without the name of the program
+///
+/// ### Returns
+/// What went wrong
+pub fn run(pool: &db::Pool, args: &[String]) -> Result<(), String> {
+    let mut conn = pool.get().map_err(|err| format!("Couldn't connect to the database.: {}", err))?;
+
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        ["import-markdown", user_id, path] => {
+            if db::get_user(&mut conn, user_id).is_err() {
+                return Err(format!("User {} not found.", user_id))
+            }
+            let text = std::fs::read_to_string(path).map_err(|err| format!("Couldn't read {}.: {}", path, err))?;
+
+            let (stack, warnings) = transfer::markdown::parse(&text);
+            for warning in warnings {
+                eprintln!("{}: {}", warning.item, warning.message);
+            }
+
+            let stack_ids = conn.transaction(|conn| db::add_imported_stacks(conn, user_id, vec![stack]))
+                .map_err(|err| format!("Couldn't store the stack.: {}", err))?;
+            println!("{}", stack_ids.join("\n"));
+            Ok(())
+        }
+
+        ["export-markdown", stack_id, rest @ ..] if rest.len() <= 1 => {
+            let stack = db::get_stack(&mut conn, stack_id).map_err(|_| format!("Stack {} not found.", stack_id))?;
+            let note_types = db::get_note_types_by_stack(&mut conn, stack_id).map_err(|err| format!("Couldn't load the note types.: {}", err))?;
+            let cards = db::get_cards_by_stack(&mut conn, stack_id).map_err(|err| format!("Couldn't load the cards.: {}", err))?;
+
+            let text = transfer::markdown::write(&stack, &note_types, &cards);
+            match rest.first() {
+                Some(path) => std::fs::write(path, text).map_err(|err| format!("Couldn't write {}.: {}", path, err)),
+                None => {
+                    print!("{}", text);
+                    Ok(())
+                }
+            }
+        }
+
+        _ => Err(USAGE.to_owned()),
+    }
+}
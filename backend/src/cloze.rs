@@ -0,0 +1,103 @@
+//! ## Cloze deletions
+//!
+//! Cloze cards hide parts of a text behind markers like `{{c1::Paris}}` or `{{c1::Paris::capital}}`,
+//! the optional last part is a hint shown in place of the hidden text. Every cloze number becomes
+//! its own sub-card, markers with the same number are hidden together.
+
+use regex::{Captures, Regex};
+
+pub const CARD_TYPE_BASIC: &str = "basic";
+pub const CARD_TYPE_CLOZE: &str = "cloze";
+
+pub const MAX_CLOZE_INDEX: i32 = 50;
+
+/// Shown in place of a hidden cloze without a hint
+const HIDDEN: &str = "[...]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cloze {
+    pub index: i32,
+    pub answer: String,
+    pub hint: Option<String>,
+}
+
+/// Basic cards can hold any text, cloze cards need at least one valid marker on the frontside
+pub fn is_valid_card(card_type: &str, frontside: &str) -> bool {
+    match card_type {
+        CARD_TYPE_BASIC => true,
+        CARD_TYPE_CLOZE => parse(frontside).is_some(),
+        _ => false,
+    }
+}
+
+fn marker_regex() -> Regex {
+    Regex::new(r"\{\{c(\d+)::(.*?)(?:::(.*?))?\}\}").unwrap()
+}
+
+/// ## Parses cloze markers.
+///
+/// ### Arguments
+///
+/// * `text` - &str, ex. `{{c1::Paris}} is the capital of {{c2::France::country}}`
+///
+/// ### Returns
+/// Markers in the order they appear or None if there are none, a number is out of the 1 - 50 range,
+/// an answer is empty or a marker is not closed
+pub fn parse(text: &str) -> Option<Vec<Cloze>> {
+    let re = marker_regex();
+
+    let clozes = re.captures_iter(text).map(|captures| {
+        let index = captures[1].parse::<i32>().ok().filter(|index| (1..=MAX_CLOZE_INDEX).contains(index))?;
+        let answer = captures[2].trim();
+        if answer.is_empty() {
+            return None;
+        }
+
+        Some(Cloze {
+            index,
+            answer: answer.to_owned(),
+            hint: captures.get(3).map(|hint| hint.as_str().trim().to_owned()).filter(|hint| ! hint.is_empty()),
+        })
+    }).collect::<Option<Vec<Cloze>>>()?;
+
+    // Leftovers of broken markers, ex. `{{c1::Paris}`
+    if clozes.is_empty() || re.replace_all(text, "").contains("{{c") {
+        return None;
+    }
+    Some(clozes)
+}
+
+/// Distinct cloze numbers in ascending order, empty if the text is not a valid cloze
+pub fn indexes(text: &str) -> Vec<i32> {
+    let mut indexes: Vec<i32> = parse(text).unwrap_or_default().into_iter().map(|cloze| cloze.index).collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    indexes
+}
+
+/// Text with the clozes of the given number hidden and all other ones revealed
+pub fn render_question(text: &str, index: i32) -> String {
+    marker_regex().replace_all(text, |captures: &Captures| {
+        if captures[1].parse::<i32>() != Ok(index) {
+            return captures[2].trim().to_owned();
+        }
+        match captures.get(3).map(|hint| hint.as_str().trim()).filter(|hint| ! hint.is_empty()) {
+            Some(hint) => format!("[{}]", hint),
+            None => HIDDEN.to_owned(),
+        }
+    }).into_owned()
+}
+
+/// Text with every cloze revealed
+pub fn render_answer(text: &str) -> String {
+    marker_regex().replace_all(text, |captures: &Captures| captures[2].trim().to_owned()).into_owned()
+}
+
+/// Hidden answers of the given number, several markers with the same number are joined with commas
+pub fn answer(text: &str, index: i32) -> String {
+    parse(text).unwrap_or_default().into_iter()
+        .filter(|cloze| cloze.index == index)
+        .map(|cloze| cloze.answer)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use diesel::dsl::exists;
 use diesel::r2d2::{self, ConnectionManager};
-use crate::{models, schema};
+use crate::{models, schema, scheduler, transfer, upstream};
 
 /// ## Alias for connection pool type
 pub type Pool = r2d2::Pool<ConnectionManager<MysqlConnection>>;
@@ -162,6 +162,9 @@ pub fn get_user_by_username(
 
 /// ## deletes a user record.
 /// 
+/// Stacks of the user are deleted with it, the forks among them no longer count for their upstream stacks.
+/// Should run in a transaction.
+/// 
 /// ### Arguments
 ///
 /// * `conn` - &mut MysqlConnection
@@ -173,6 +176,18 @@ pub fn delete_user(
     conn: &mut MysqlConnection,
     id: &str,
 ) -> Result<usize, diesel::result::Error> {
+    let forked: Vec<Option<String>> = {
+        use schema::stacks::dsl::*;
+        stacks
+            .filter(owner_id.eq(id))
+            .filter(upstream_id.is_not_null())
+            .select(upstream_id)
+            .load::<Option<String>>(conn)?
+    };
+    for upstream in forked.into_iter().flatten() {
+        lower_forks_count(conn, &upstream)?;
+    }
+
     use schema::users::dsl::*;
     diesel::delete(users.find(id))
         .execute(conn)
@@ -278,6 +293,8 @@ pub fn get_stacks_by_owner(
 
 /// ## deletes a stack record.
 /// 
+/// A deleted fork no longer counts for its upstream stack. Should run in a transaction.
+/// 
 /// ### Arguments
 ///
 /// * `conn` - &mut MysqlConnection
@@ -290,10 +307,31 @@ pub fn delete_stack(
     id: &str,
 ) -> Result<usize, diesel::result::Error> {
     use schema::stacks::dsl::*;
+    let upstream = stacks
+        .find(id)
+        .select(upstream_id)
+        .first::<Option<String>>(conn)
+        .optional()?
+        .flatten();
+    if let Some(upstream) = upstream {
+        lower_forks_count(conn, &upstream)?;
+    }
+
     diesel::delete(stacks.find(id))
         .execute(conn)
 }
 
+/// Counts one fork less for a stack, never below zero
+fn lower_forks_count(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<usize, diesel::result::Error> {
+    use schema::stacks::dsl::*;
+    diesel::update(stacks.find(id).filter(forks_count.gt(0)))
+        .set(forks_count.eq(forks_count - 1))
+        .execute(conn)
+}
+
 // --- managing cards
 
 /// ## Generates a free unique id.
@@ -409,3 +447,1144 @@ pub fn delete_card(
     diesel::delete(cards.find(id))
         .execute(conn)
 }
+
+// --- managing note types
+
+/// ## Generates a free unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+///
+/// ### Returns
+/// Result containing unique id **String** or **diesel::result::Error**
+pub fn generate_note_type_id(conn: &mut MysqlConnection) -> Result<String, diesel::result::Error> {
+    let result = diesel::sql_query("SELECT generate_note_type_id() as unique_id")
+        .get_result::<models::UniqueId>(conn);
+
+    match result {
+        Ok(unique_id) => Ok(unique_id.unique_id),
+        Err(err) => Err(err)
+    }
+}
+
+/// ## Inserts a new note type record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `note_type_to_insert` - models::NoteType
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_note_type(
+    conn: &mut MysqlConnection,
+    note_type_to_insert: models::NoteType,
+) -> Result<usize, diesel::result::Error> {
+    use schema::note_types::dsl::*;
+    diesel::insert_into(note_types)
+        .values(note_type_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a note type record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `note_type_to_update` - models::NoteType
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_note_type(
+    conn: &mut MysqlConnection,
+    note_type_to_update: models::NoteType,
+) -> Result<usize, diesel::result::Error> {
+    use schema::note_types::dsl::*;
+    diesel::update(note_types.find(&note_type_to_update.unique_id))
+        .set(&note_type_to_update)
+        .execute(conn)
+}
+
+/// ## Selects a note type record by it's unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `id` - &str
+///
+/// ### Returns
+/// Result containing **models::NoteType** or **diesel::result::Error**
+pub fn get_note_type(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<models::NoteType, diesel::result::Error> {
+    use schema::note_types::dsl::*;
+    note_types
+        .find(id)
+        .first::<models::NoteType>(conn)
+}
+
+/// ## Selects note type records of a stack.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::NoteType** or **diesel::result::Error**
+pub fn get_note_types_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::NoteType>, diesel::result::Error> {
+    use schema::note_types::dsl::*;
+    note_types
+        .filter(stack_id.eq(s_id))
+        .load::<models::NoteType>(conn)
+}
+
+/// ## Selects card records of a note type.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `n_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::Card** or **diesel::result::Error**
+pub fn get_cards_by_note_type(
+    conn: &mut MysqlConnection,
+    n_id: &str,
+) -> Result<Vec<models::Card>, diesel::result::Error> {
+    use schema::cards::dsl::*;
+    cards
+        .filter(note_type_id.eq(n_id))
+        .load::<models::Card>(conn)
+}
+
+/// ## deletes a note type record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `id` - &str
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn delete_note_type(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<usize, diesel::result::Error> {
+    use schema::note_types::dsl::*;
+    diesel::delete(note_types.find(id))
+        .execute(conn)
+}
+
+// --- managing card states
+
+/// ## Selects a card state record by user id, card id, ordinal and direction.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `c_id` - &str
+/// * `ord` - i32, 0 for basic cards, cloze number for sub-cards of cloze cards
+/// * `dir` - &str, `forward` or `backward`
+///
+/// ### Returns
+/// Result containing **models::CardState** or **diesel::result::Error**
+pub fn get_card_state(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    c_id: &str,
+    ord: i32,
+    dir: &str,
+) -> Result<models::CardState, diesel::result::Error> {
+    use schema::card_states::dsl::*;
+    card_states
+        .find((u_id, c_id, ord, dir))
+        .first::<models::CardState>(conn)
+}
+
+/// ## Selects all card state records of a user.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::CardState** or **diesel::result::Error**
+pub fn get_card_states(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+) -> Result<Vec<models::CardState>, diesel::result::Error> {
+    use schema::card_states::dsl::*;
+    card_states
+        .filter(user_id.eq(u_id))
+        .load::<models::CardState>(conn)
+}
+
+/// ## Selects all card state records of a user in a given stack.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::CardState** or **diesel::result::Error**
+pub fn get_card_states_by_stack(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    s_id: &str,
+) -> Result<Vec<models::CardState>, diesel::result::Error> {
+    use schema::card_states::dsl::*;
+    let stack_cards = schema::cards::table
+        .filter(schema::cards::stack_id.eq(s_id))
+        .select(schema::cards::unique_id);
+
+    card_states
+        .filter(user_id.eq(u_id))
+        .filter(card_id.eq_any(stack_cards))
+        .load::<models::CardState>(conn)
+}
+
+/// ## Selects card state records of a user in a given stack that are flagged as leeches.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::CardState** or **diesel::result::Error**
+pub fn get_leech_states_by_stack(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    s_id: &str,
+) -> Result<Vec<models::CardState>, diesel::result::Error> {
+    use schema::card_states::dsl::*;
+    let stack_cards = schema::cards::table
+        .filter(schema::cards::stack_id.eq(s_id))
+        .select(schema::cards::unique_id);
+
+    card_states
+        .filter(user_id.eq(u_id))
+        .filter(leech.eq(true))
+        .filter(card_id.eq_any(stack_cards))
+        .order(lapses.desc())
+        .load::<models::CardState>(conn)
+}
+
+/// ## Inserts or replaces a card state record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `state_to_save` - models::CardState
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn save_card_state(
+    conn: &mut MysqlConnection,
+    state_to_save: models::CardState,
+) -> Result<usize, diesel::result::Error> {
+    use schema::card_states::dsl::*;
+    diesel::replace_into(card_states)
+        .values(state_to_save)
+        .execute(conn)
+}
+
+// --- managing review log
+
+/// ## Appends a review log record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `log_to_insert` - models::ReviewLog
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_review_log(
+    conn: &mut MysqlConnection,
+    log_to_insert: models::ReviewLog,
+) -> Result<usize, diesel::result::Error> {
+    use schema::review_log::dsl::*;
+    diesel::insert_into(review_log)
+        .values(log_to_insert)
+        .execute(conn)
+}
+
+/// ## Selects all review log records of a user, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::ReviewLogFull** or **diesel::result::Error**
+pub fn get_review_logs(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+) -> Result<Vec<models::ReviewLogFull>, diesel::result::Error> {
+    use schema::review_log::dsl::*;
+    review_log
+        .filter(user_id.eq(u_id))
+        .order((reviewed_at.asc(), unique_id.asc()))
+        .load::<models::ReviewLogFull>(conn)
+}
+
+/// ## Selects review log records of a user for a single card, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `c_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::ReviewLogFull** or **diesel::result::Error**
+pub fn get_review_logs_by_card(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    c_id: &str,
+) -> Result<Vec<models::ReviewLogFull>, diesel::result::Error> {
+    use schema::review_log::dsl::*;
+    review_log
+        .filter(user_id.eq(u_id))
+        .filter(card_id.eq(c_id))
+        .order((reviewed_at.asc(), unique_id.asc()))
+        .load::<models::ReviewLogFull>(conn)
+}
+
+/// ## Selects review log records of a user for a whole stack, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::ReviewLogFull** or **diesel::result::Error**
+pub fn get_review_logs_by_stack(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    s_id: &str,
+) -> Result<Vec<models::ReviewLogFull>, diesel::result::Error> {
+    use schema::review_log::dsl::*;
+    review_log
+        .filter(user_id.eq(u_id))
+        .filter(stack_id.eq(s_id))
+        .order((reviewed_at.asc(), unique_id.asc()))
+        .load::<models::ReviewLogFull>(conn)
+}
+
+/// ## Counts reviews of a user made since a given time.
+/// 
+/// Only the first review of a card counts as a new card and only reviews of cards in review phase count as reviews,
+/// learning and relearning steps are not limited.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `since` - i64, unix timestamp
+///
+/// ### Returns
+/// Result containing **(new cards, reviews)** or **diesel::result::Error**
+pub fn count_reviews_since(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    since: i64,
+) -> Result<(i64, i64), diesel::result::Error> {
+    use schema::review_log::dsl::*;
+    let new_cards = review_log
+        .filter(user_id.eq(u_id))
+        .filter(reviewed_at.ge(since))
+        .filter(phase.eq(scheduler::steps::PHASE_NEW))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    let reviews = review_log
+        .filter(user_id.eq(u_id))
+        .filter(reviewed_at.ge(since))
+        .filter(phase.eq(scheduler::steps::PHASE_REVIEW))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok((new_cards, reviews))
+}
+
+// --- managing study sessions
+
+/// ## Generates a free unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+///
+/// ### Returns
+/// Result containing unique id **String** or **diesel::result::Error**
+pub fn generate_session_id(conn: &mut MysqlConnection) -> Result<String, diesel::result::Error> {
+    let result = diesel::sql_query("SELECT generate_session_id() as unique_id")
+        .get_result::<models::UniqueId>(conn);
+
+    match result {
+        Ok(unique_id) => Ok(unique_id.unique_id),
+        Err(err) => Err(err)
+    }
+}
+
+/// ## Inserts a new study session record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `session_to_insert` - models::StudySession
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_study_session(
+    conn: &mut MysqlConnection,
+    session_to_insert: models::StudySession,
+) -> Result<usize, diesel::result::Error> {
+    use schema::study_sessions::dsl::*;
+    diesel::insert_into(study_sessions)
+        .values(session_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a study session record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `session_to_update` - models::StudySession
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_study_session(
+    conn: &mut MysqlConnection,
+    session_to_update: models::StudySession,
+) -> Result<usize, diesel::result::Error> {
+    use schema::study_sessions::dsl::*;
+    diesel::update(study_sessions.find(&session_to_update.unique_id))
+        .set(&session_to_update)
+        .execute(conn)
+}
+
+/// ## Selects a study session record by it's unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `id` - &str
+///
+/// ### Returns
+/// Result containing **models::StudySession** or **diesel::result::Error**
+pub fn get_study_session(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<models::StudySession, diesel::result::Error> {
+    use schema::study_sessions::dsl::*;
+    study_sessions
+        .find(id)
+        .first::<models::StudySession>(conn)
+}
+
+/// ## Selects all study session records of a user, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::StudySession** or **diesel::result::Error**
+pub fn get_study_sessions_by_user(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+) -> Result<Vec<models::StudySession>, diesel::result::Error> {
+    use schema::study_sessions::dsl::*;
+    study_sessions
+        .filter(user_id.eq(u_id))
+        .order(started_at.asc())
+        .load::<models::StudySession>(conn)
+}
+
+// --- managing quiz attempts
+
+/// ## Generates a free unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+///
+/// ### Returns
+/// Result containing unique id **String** or **diesel::result::Error**
+pub fn generate_quiz_id(conn: &mut MysqlConnection) -> Result<String, diesel::result::Error> {
+    let result = diesel::sql_query("SELECT generate_quiz_id() as unique_id")
+        .get_result::<models::UniqueId>(conn);
+
+    match result {
+        Ok(unique_id) => Ok(unique_id.unique_id),
+        Err(err) => Err(err)
+    }
+}
+
+/// ## Inserts a new quiz attempt record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `attempt_to_insert` - models::QuizAttempt
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_quiz_attempt(
+    conn: &mut MysqlConnection,
+    attempt_to_insert: models::QuizAttempt,
+) -> Result<usize, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    diesel::insert_into(quiz_attempts)
+        .values(attempt_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a quiz attempt record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `attempt_to_update` - models::QuizAttempt
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_quiz_attempt(
+    conn: &mut MysqlConnection,
+    attempt_to_update: models::QuizAttempt,
+) -> Result<usize, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    diesel::update(quiz_attempts.find(&attempt_to_update.unique_id))
+        .set(&attempt_to_update)
+        .execute(conn)
+}
+
+/// ## Selects a quiz attempt record by it's unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `id` - &str
+///
+/// ### Returns
+/// Result containing **models::QuizAttempt** or **diesel::result::Error**
+pub fn get_quiz_attempt(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<models::QuizAttempt, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    quiz_attempts
+        .find(id)
+        .first::<models::QuizAttempt>(conn)
+}
+
+/// ## Selects all quiz attempts of a user, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::QuizAttempt** or **diesel::result::Error**
+pub fn get_quiz_attempts_by_user(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+) -> Result<Vec<models::QuizAttempt>, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    quiz_attempts
+        .filter(user_id.eq(u_id))
+        .order(created_at.asc())
+        .load::<models::QuizAttempt>(conn)
+}
+
+/// ## Selects submitted quiz attempts of all users in a given stack.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::QuizAttempt**, oldest first, or **diesel::result::Error**
+pub fn get_submitted_quiz_attempts_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::QuizAttempt>, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    quiz_attempts
+        .filter(stack_id.eq(s_id))
+        .filter(submitted_at.is_not_null())
+        .order(submitted_at.asc())
+        .load::<models::QuizAttempt>(conn)
+}
+
+/// ## Selects submitted quiz attempts of a user in a given stack.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::QuizAttempt**, oldest first, or **diesel::result::Error**
+pub fn get_submitted_quiz_attempts_by_user(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+    s_id: &str,
+) -> Result<Vec<models::QuizAttempt>, diesel::result::Error> {
+    use schema::quiz_attempts::dsl::*;
+    quiz_attempts
+        .filter(user_id.eq(u_id))
+        .filter(stack_id.eq(s_id))
+        .filter(submitted_at.is_not_null())
+        .order(submitted_at.asc())
+        .load::<models::QuizAttempt>(conn)
+}
+
+// --- importing stacks
+
+/// ## Inserts imported stacks with their note types and cards into the account of a user.
+///
+/// Reviews go to the review log of the user and card states are saved for the user. Cards have to be checked with `transfer::check_card` first.
+/// Should run in a transaction, so nothing is left behind when one of the inserts fails.
+///
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `user_id` - &str
+/// * `stacks` - Vec<transfer::ImportedStack>
+///
+/// ### Returns
+/// Result containing a Vec of the new stack ids or **diesel::result::Error**
+pub fn add_imported_stacks(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    stacks: Vec<transfer::ImportedStack>,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut stack_ids: Vec<String> = Vec::with_capacity(stacks.len());
+    for stack in stacks {
+        let stack_id = generate_stack_id(conn)?;
+        add_stack(conn, models::Stack {
+            unique_id: stack_id.clone(),
+            owner_id: user_id.to_owned(),
+            name: stack.name,
+            visibility: stack.visibility,
+            tags: stack.tags,
+            learning_steps: stack.settings.learning_steps,
+            relearning_steps: stack.settings.relearning_steps,
+            leech_threshold: stack.settings.leech_threshold,
+            leech_suspend: stack.settings.leech_suspend,
+            scheduler: stack.settings.scheduler,
+            leitner_intervals: stack.settings.leitner_intervals,
+            directions: stack.settings.directions,
+            upstream_id: None,
+        })?;
+
+        let mut note_types: Vec<models::NoteType> = Vec::with_capacity(stack.note_types.len());
+        for note_type in stack.note_types {
+            let new_note_type = models::NoteType {
+                unique_id: generate_note_type_id(conn)?,
+                stack_id: stack_id.clone(),
+                name: note_type.name,
+                fields: serde_json::to_string(&note_type.field_names).unwrap_or_default(),
+                templates: serde_json::to_string(&note_type.templates).unwrap_or_default(),
+            };
+            add_note_type(conn, new_note_type.clone())?;
+            note_types.push(new_note_type);
+        }
+
+        for card in stack.cards {
+            let mut new_card = models::Card {
+                unique_id: generate_card_id(conn)?,
+                stack_id: stack_id.clone(),
+                frontside: card.frontside,
+                backside: card.backside,
+                card_type: card.card_type,
+                directions: card.directions,
+                note_type_id: String::new(),
+                fields: String::new(),
+            };
+            if let Some(note_type) = card.note_type.and_then(|index| note_types.get(index)) {
+                new_card.set_fields(note_type, card.fields);
+            }
+            let card_id = new_card.unique_id.clone();
+            add_card(conn, new_card)?;
+
+            for review in card.reviews {
+                add_review_log(conn, models::ReviewLog {
+                    user_id: user_id.to_owned(),
+                    card_id: card_id.clone(),
+                    stack_id: stack_id.clone(),
+                    reviewed_at: review.reviewed_at,
+                    grade: review.grade,
+                    previous_interval: review.previous_interval,
+                    next_interval: review.next_interval,
+                    time_taken: review.time_taken,
+                    phase: review.phase,
+                    ordinal: review.ordinal,
+                    direction: review.direction,
+                })?;
+            }
+            for state in card.states {
+                save_card_state(conn, models::CardState {
+                    user_id: user_id.to_owned(),
+                    card_id: card_id.clone(),
+                    ..state
+                })?;
+            }
+        }
+        stack_ids.push(stack_id);
+    }
+    Ok(stack_ids)
+}
+
+// --- forking stacks
+
+/// ## Copies a stack with its note types and cards into the account of a user.
+///
+/// The copy is private, records the stack it was copied from and raises the forks count of that stack.
+/// Every card gets a lineage record to sync it with its upstream card later.
+/// Card states and reviews stay with the original cards. Should run in a transaction.
+///
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `user_id` - &str
+/// * `upstream` - &models::StackFull, stack that is forked
+///
+/// ### Returns
+/// Result containing the id of the new stack or **diesel::result::Error**
+pub fn fork_stack(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    upstream: &models::StackFull,
+) -> Result<String, diesel::result::Error> {
+    let stack_id = generate_stack_id(conn)?;
+
+    // Upstream note types and cards with the ids of their copies
+    let mut note_types: Vec<(models::NoteType, String)> = Vec::new();
+    for note_type in get_note_types_by_stack(conn, &upstream.unique_id)? {
+        note_types.push((note_type, generate_note_type_id(conn)?));
+    }
+    let mut cards: Vec<(models::Card, String)> = Vec::new();
+    for card in get_cards_by_stack(conn, &upstream.unique_id)? {
+        cards.push((card, generate_card_id(conn)?));
+    }
+
+    let fork = upstream::fork(user_id, upstream, &stack_id, &note_types, &cards);
+    add_stack(conn, fork.stack)?;
+    for note_type in fork.note_types {
+        add_note_type(conn, note_type)?;
+    }
+    for card in fork.cards {
+        add_card(conn, card)?;
+    }
+    for lineage in fork.lineage {
+        add_card_lineage(conn, lineage)?;
+    }
+
+    {
+        use schema::stacks::dsl::*;
+        diesel::update(stacks.find(&upstream.unique_id))
+            .set(forks_count.eq(forks_count + 1))
+            .execute(conn)?;
+    }
+    Ok(stack_id)
+}
+
+/// ## Inserts a card lineage record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `lineage_to_insert` - models::CardLineage
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_card_lineage(
+    conn: &mut MysqlConnection,
+    lineage_to_insert: models::CardLineage,
+) -> Result<usize, diesel::result::Error> {
+    use schema::card_lineage::dsl::*;
+    diesel::insert_into(card_lineage)
+        .values(lineage_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a card lineage record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `lineage_to_update` - models::CardLineage
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_card_lineage(
+    conn: &mut MysqlConnection,
+    lineage_to_update: models::CardLineage,
+) -> Result<usize, diesel::result::Error> {
+    use schema::card_lineage::dsl::*;
+    diesel::update(card_lineage.find((&lineage_to_update.stack_id, &lineage_to_update.upstream_card_id)))
+        .set(&lineage_to_update)
+        .execute(conn)
+}
+
+/// ## Selects the card lineage records of a fork.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::CardLineage** or **diesel::result::Error**
+pub fn get_card_lineage_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::CardLineage>, diesel::result::Error> {
+    use schema::card_lineage::dsl::*;
+    card_lineage
+        .filter(stack_id.eq(s_id))
+        .load::<models::CardLineage>(conn)
+}
+
+/// ## Deletes a card lineage record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+/// * `u_id` - &str, id of the upstream card
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn delete_card_lineage(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+    u_id: &str,
+) -> Result<usize, diesel::result::Error> {
+    use schema::card_lineage::dsl::*;
+    diesel::delete(card_lineage.find((s_id, u_id)))
+        .execute(conn)
+}
+
+
+// --- managing suggestions
+
+/// ## Generates a free unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+///
+/// ### Returns
+/// Result containing unique id **String** or **diesel::result::Error**
+pub fn generate_suggestion_id(conn: &mut MysqlConnection) -> Result<String, diesel::result::Error> {
+    let result = diesel::sql_query("SELECT generate_suggestion_id() as unique_id")
+        .get_result::<models::UniqueId>(conn);
+
+    match result {
+        Ok(unique_id) => Ok(unique_id.unique_id),
+        Err(err) => Err(err)
+    }
+}
+
+/// ## Inserts a new suggestion record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `suggestion_to_insert` - models::Suggestion
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_suggestion(
+    conn: &mut MysqlConnection,
+    suggestion_to_insert: models::Suggestion,
+) -> Result<usize, diesel::result::Error> {
+    use schema::suggestions::dsl::*;
+    diesel::insert_into(suggestions)
+        .values(suggestion_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a suggestion record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `suggestion_to_update` - models::Suggestion
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_suggestion(
+    conn: &mut MysqlConnection,
+    suggestion_to_update: models::Suggestion,
+) -> Result<usize, diesel::result::Error> {
+    use schema::suggestions::dsl::*;
+    diesel::update(suggestions.find(&suggestion_to_update.unique_id))
+        .set(&suggestion_to_update)
+        .execute(conn)
+}
+
+/// ## Selects a suggestion record by it's unique id.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `id` - &str
+///
+/// ### Returns
+/// Result containing **models::Suggestion** or **diesel::result::Error**
+pub fn get_suggestion(
+    conn: &mut MysqlConnection,
+    id: &str,
+) -> Result<models::Suggestion, diesel::result::Error> {
+    use schema::suggestions::dsl::*;
+    suggestions
+        .find(id)
+        .first::<models::Suggestion>(conn)
+}
+
+/// ## Selects all suggestions of a stack, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::Suggestion** or **diesel::result::Error**
+pub fn get_suggestions_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::Suggestion>, diesel::result::Error> {
+    use schema::suggestions::dsl::*;
+    suggestions
+        .filter(stack_id.eq(s_id))
+        .order(created_at.asc())
+        .load::<models::Suggestion>(conn)
+}
+
+
+// --- managing stack members
+
+/// ## Inserts a new stack member record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `member_to_insert` - models::StackMember
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_stack_member(
+    conn: &mut MysqlConnection,
+    member_to_insert: models::StackMember,
+) -> Result<usize, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    diesel::insert_into(stack_members)
+        .values(member_to_insert)
+        .execute(conn)
+}
+
+/// ## Updates a stack member record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `member_to_update` - models::StackMember
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn update_stack_member(
+    conn: &mut MysqlConnection,
+    member_to_update: models::StackMember,
+) -> Result<usize, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    diesel::update(stack_members.find((&member_to_update.stack_id, &member_to_update.user_id)))
+        .set(&member_to_update)
+        .execute(conn)
+}
+
+/// ## Selects the membership of a user in a stack.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing **models::StackMember** or **diesel::result::Error**
+pub fn get_stack_member(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+    u_id: &str,
+) -> Result<models::StackMember, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    stack_members
+        .find((s_id, u_id))
+        .first::<models::StackMember>(conn)
+}
+
+/// ## Selects the members of a stack, oldest invite first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::StackMember** or **diesel::result::Error**
+pub fn get_stack_members_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::StackMember>, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    stack_members
+        .filter(stack_id.eq(s_id))
+        .order(invited_at.asc())
+        .load::<models::StackMember>(conn)
+}
+
+/// ## Selects the memberships of a user, oldest invite first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::StackMember** or **diesel::result::Error**
+pub fn get_stack_members_by_user(
+    conn: &mut MysqlConnection,
+    u_id: &str,
+) -> Result<Vec<models::StackMember>, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    stack_members
+        .filter(user_id.eq(u_id))
+        .order(invited_at.asc())
+        .load::<models::StackMember>(conn)
+}
+
+/// ## Deletes a stack member record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+/// * `u_id` - &str
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn delete_stack_member(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+    u_id: &str,
+) -> Result<usize, diesel::result::Error> {
+    use schema::stack_members::dsl::*;
+    diesel::delete(stack_members.find((s_id, u_id)))
+        .execute(conn)
+}
+
+
+// --- managing share tokens
+
+/// ## Inserts a new share token record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `token_to_insert` - models::ShareToken
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn add_share_token(
+    conn: &mut MysqlConnection,
+    token_to_insert: models::ShareToken,
+) -> Result<usize, diesel::result::Error> {
+    use schema::share_tokens::dsl::*;
+    diesel::insert_into(share_tokens)
+        .values(token_to_insert)
+        .execute(conn)
+}
+
+/// ## Selects a share token record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `value` - &str
+///
+/// ### Returns
+/// Result containing **models::ShareToken** or **diesel::result::Error**
+pub fn get_share_token(
+    conn: &mut MysqlConnection,
+    value: &str,
+) -> Result<models::ShareToken, diesel::result::Error> {
+    use schema::share_tokens::dsl::*;
+    share_tokens
+        .find(value)
+        .first::<models::ShareToken>(conn)
+}
+
+/// ## Selects the share tokens of a stack, oldest first.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `s_id` - &str
+///
+/// ### Returns
+/// Result containing a Vec of **models::ShareToken** or **diesel::result::Error**
+pub fn get_share_tokens_by_stack(
+    conn: &mut MysqlConnection,
+    s_id: &str,
+) -> Result<Vec<models::ShareToken>, diesel::result::Error> {
+    use schema::share_tokens::dsl::*;
+    share_tokens
+        .filter(stack_id.eq(s_id))
+        .order(created_at.asc())
+        .load::<models::ShareToken>(conn)
+}
+
+/// ## Deletes a share token record.
+/// 
+/// ### Arguments
+///
+/// * `conn` - &mut MysqlConnection
+/// * `value` - &str
+///
+/// ### Returns
+/// Result containing number of affected rows or **diesel::result::Error**
+pub fn delete_share_token(
+    conn: &mut MysqlConnection,
+    value: &str,
+) -> Result<usize, diesel::result::Error> {
+    use schema::share_tokens::dsl::*;
+    diesel::delete(share_tokens.find(value))
+        .execute(conn)
+}
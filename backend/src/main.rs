@@ -16,6 +16,16 @@ mod routes;
 mod tests;
 mod utils;
 mod api;
+mod scheduler;
+mod answers;
+mod quiz;
+mod cloze;
+mod notes;
+mod transfer;
+mod upstream;
+mod suggestions;
+mod members;
+mod cli;
 
 #[cfg(feature = "demo")]
 async fn demo() -> Result<actix_web::HttpResponse, std::io::Error> {
@@ -42,6 +52,16 @@ async fn main() -> std::io::Result<()> {
         db::establish_connection(url)
     };
 
+    // Commands don't start the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if ! args.is_empty() {
+        if let Err(err) = cli::run(&pool, &args) {
+            error!("{}", err);
+            exit(1);
+        }
+        exit(0);
+    }
+
     let jwt_secret = dotenv::var("JWT_SECRET").unwrap_or_else(|err| {
         error!("JWT_SECRET not set in .env file.: {}", err);
         exit(1);
@@ -0,0 +1,83 @@
+//! ## Sharing stacks with other users
+//!
+//! The owner of a stack invites other users as editors or viewers, an invite counts once the user accepted it.
+//! Editors add and edit cards and note types, viewers can read and study private stacks. Settings of the stack,
+//! deleting it and managing its members stay with the owner.
+//!
+//! Share tokens let anyone with the link view and fork a private stack without becoming a member.
+
+use crate::models;
+
+/// The user the stack belongs to, never stored as a member
+pub const ROLE_OWNER: &str = "owner";
+pub const ROLE_EDITOR: &str = "editor";
+pub const ROLE_VIEWER: &str = "viewer";
+
+/// What a request does with a stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Reading and studying the stack
+    Read,
+    /// Changing cards and note types
+    Edit,
+    /// Changing settings, deleting the stack and managing its members
+    Manage,
+}
+
+/// Roles users can be invited with
+pub fn is_valid_member_role(role: &str) -> bool {
+    matches!(role, ROLE_EDITOR | ROLE_VIEWER)
+}
+
+/// ## Role of a user in a stack.
+///
+/// ### Arguments
+///
+/// * `stack` - &models::StackFull
+/// * `member` - Option<&models::StackMember>, membership of the user in the stack if there is one
+/// * `user_id` - &str
+///
+/// ### Returns
+/// One of the `ROLE_` roles or None for users who aren't the owner or a member that accepted the invite
+pub fn role(stack: &models::StackFull, member: Option<&models::StackMember>, user_id: &str) -> Option<&'static str> {
+    if stack.owner_id == user_id {
+        return Some(ROLE_OWNER)
+    }
+
+    match member {
+        Some(member) if member.accepted && member.stack_id == stack.unique_id && member.user_id == user_id => {
+            match member.role.as_str() {
+                ROLE_EDITOR => Some(ROLE_EDITOR),
+                ROLE_VIEWER => Some(ROLE_VIEWER),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// ## Tells if a role allows something, public stacks can be read by everyone.
+///
+/// ### Arguments
+///
+/// * `stack` - &models::StackFull
+/// * `role` - Option<&str>, see `role`
+/// * `permission` - Permission
+pub fn allows(stack: &models::StackFull, role: Option<&str>, permission: Permission) -> bool {
+    match permission {
+        Permission::Read => stack.visibility || role.is_some(),
+        Permission::Edit => matches!(role, Some(ROLE_OWNER | ROLE_EDITOR)),
+        Permission::Manage => role == Some(ROLE_OWNER),
+    }
+}
+
+/// ## Tells if a share token gives access to a stack.
+///
+/// ### Arguments
+///
+/// * `token` - &models::ShareToken
+/// * `stack_id` - &str
+/// * `now` - i64, unix timestamp
+pub fn is_valid_token(token: &models::ShareToken, stack_id: &str, now: i64) -> bool {
+    token.stack_id == stack_id && token.expires_at.map_or(true, |expires_at| expires_at > now)
+}
@@ -1,4 +1,4 @@
-use crate::{schema::*, db};
+use crate::{schema::*, db, scheduler, cloze, notes, upstream};
 use argon2::Argon2;
 
 use diesel::prelude::*;
@@ -32,6 +32,10 @@ pub struct User {
     pub password_hash: String,
     pub date_of_registration: i64,
     pub country: String,
+    pub scheduler: String,
+    pub fsrs_parameters: String,
+    pub new_cards_per_day: i32,
+    pub reviews_per_day: i32,
 }
 
 
@@ -43,6 +47,15 @@ pub struct Stack {
     pub name: String,
     pub visibility: bool,
     pub tags: String,
+    pub learning_steps: String,
+    pub relearning_steps: String,
+    pub leech_threshold: i32,
+    pub leech_suspend: bool,
+    pub scheduler: String,
+    pub leitner_intervals: String,
+    pub directions: String,
+    /// Stack this one was forked from
+    pub upstream_id: Option<String>,
 }
 
 impl From<StackFull> for Stack {
@@ -53,6 +66,14 @@ impl From<StackFull> for Stack {
             name: stack_full.name,
             visibility: stack_full.visibility,
             tags: stack_full.tags,
+            learning_steps: stack_full.learning_steps,
+            relearning_steps: stack_full.relearning_steps,
+            leech_threshold: stack_full.leech_threshold,
+            leech_suspend: stack_full.leech_suspend,
+            scheduler: stack_full.scheduler,
+            leitner_intervals: stack_full.leitner_intervals,
+            directions: stack_full.directions,
+            upstream_id: stack_full.upstream_id,
         }
     }
 }
@@ -65,6 +86,15 @@ pub struct StackFull {
     pub visibility: bool,
     pub cards_count: i32,
     pub tags: String,
+    pub learning_steps: String,
+    pub relearning_steps: String,
+    pub leech_threshold: i32,
+    pub leech_suspend: bool,
+    pub scheduler: String,
+    pub leitner_intervals: String,
+    pub directions: String,
+    pub upstream_id: Option<String>,
+    pub forks_count: i32,
 }
 
 
@@ -75,4 +105,431 @@ pub struct Card {
     pub stack_id: String,
     pub frontside: String,
     pub backside: String,
-}
\ No newline at end of file
+    pub card_type: String,
+    /// Empty when the stack decides
+    pub directions: String,
+    /// Empty for the built-in Basic note type
+    pub note_type_id: String,
+    /// JSON object of field values, empty for the built-in Basic note type which uses frontside and backside
+    pub fields: String,
+}
+
+/// What a card needs from its stack to be split into sub-cards
+#[derive(Debug, Clone, Copy)]
+pub struct CardLayout<'a> {
+    /// Directions of the stack, used when the card doesn't set its own
+    pub directions: &'a str,
+    pub note_types: &'a [NoteType],
+}
+
+impl<'a> CardLayout<'a> {
+    pub fn new(stack: &'a StackFull, note_types: &'a [NoteType]) -> Self {
+        CardLayout {
+            directions: &stack.directions,
+            note_types,
+        }
+    }
+}
+
+impl Card {
+    /// Field values by field name, the built-in Basic note type keeps them in frontside and backside
+    pub fn field_values(&self) -> notes::Fields {
+        if self.note_type_id == notes::BASIC_NOTE_TYPE_ID {
+            return notes::Fields::from([
+                (notes::BASIC_FRONT_FIELD.to_owned(), self.frontside.clone()),
+                (notes::BASIC_BACK_FIELD.to_owned(), self.backside.clone()),
+            ]);
+        }
+        serde_json::from_str(&self.fields).unwrap_or_default()
+    }
+
+    /// ## Stores the field values of a card of a note type.
+    ///
+    /// The first rendered card is kept in frontside and backside for clients that don't know note types.
+    ///
+    /// ### Returns
+    /// false if none of the templates renders a front
+    pub fn set_fields(&mut self, note_type: &NoteType, fields: notes::Fields) -> bool {
+        let first = match notes::render_cards(&note_type.template_list(), &fields).into_iter().next() {
+            Some(value) => value,
+            None => return false,
+        };
+
+        self.note_type_id = note_type.unique_id.clone();
+        self.fields = serde_json::to_string(&fields).unwrap_or_default();
+        self.frontside = first.front;
+        self.backside = first.back;
+        true
+    }
+
+    /// ## Stores the field values of a card of a note type after checking them the way create_card does.
+    ///
+    /// ### Returns
+    /// Why the fields can't be used
+    pub fn set_checked_fields(&mut self, note_type: &NoteType, fields: notes::Fields) -> Result<(), &'static str> {
+        // Every template already makes its own sub-card, so they can't be cloze cards
+        if self.card_type != cloze::CARD_TYPE_BASIC {
+            return Err("cards of a note type can't be cloze cards")
+        }
+        if ! notes::is_valid_fields(&fields, &note_type.field_names()) {
+            return Err("fields are empty or longer than 255 bytes")
+        }
+        if ! self.set_fields(note_type, fields) {
+            return Err("no card template renders a front")
+        }
+        Ok(())
+    }
+
+    /// ## Checks the frontside and backside of a card of the built-in Basic note type the way create_card does.
+    ///
+    /// ### Returns
+    /// Why the card can't be stored
+    pub fn check_basic(&self) -> Result<(), &'static str> {
+        if self.frontside.len() > 255 || self.backside.len() > 255 {
+            return Err("front or back is longer than 255 bytes")
+        }
+        // Cloze cards need valid markers on the frontside
+        if ! cloze::is_valid_card(&self.card_type, &self.frontside) {
+            return Err("invalid card type or cloze markers")
+        }
+        Ok(())
+    }
+
+    /// ## Splits the card into the parts that are reviewed separately.
+    ///
+    /// Basic cards have a single sub-card with ordinal 0, plus a backward one when they are studied in both directions.
+    /// Cloze cards have one for every cloze number and are only studied forward.
+    /// The backside of a cloze sub-card is the whole text revealed, followed by the extra notes of the card.
+    /// Cards of other note types have one for every template that renders a front, the ordinal is the index of the template.
+    ///
+    /// ### Arguments
+    ///
+    /// * `layout` - &CardLayout, of the stack the card belongs to
+    pub fn sub_cards(&self, layout: &CardLayout) -> Vec<SubCard> {
+        if self.note_type_id != notes::BASIC_NOTE_TYPE_ID {
+            let note_type = match layout.note_types.iter().find(|note_type| note_type.unique_id == self.note_type_id) {
+                Some(value) => value,
+                None => return Vec::new(),
+            };
+
+            return notes::render_cards(&note_type.template_list(), &self.field_values()).into_iter().map(|rendered| SubCard {
+                card_id: self.unique_id.clone(),
+                stack_id: self.stack_id.clone(),
+                card_type: self.card_type.clone(),
+                ordinal: rendered.template as i32,
+                direction: scheduler::DIRECTION_FORWARD.to_owned(),
+                frontside: rendered.front,
+                backside: rendered.back,
+                answer: rendered.answer,
+            }).collect();
+        }
+
+        if self.card_type != cloze::CARD_TYPE_CLOZE {
+            let forward = SubCard {
+                card_id: self.unique_id.clone(),
+                stack_id: self.stack_id.clone(),
+                card_type: self.card_type.clone(),
+                ordinal: 0,
+                direction: scheduler::DIRECTION_FORWARD.to_owned(),
+                frontside: self.frontside.clone(),
+                backside: self.backside.clone(),
+                answer: self.backside.clone(),
+            };
+
+            let directions = if self.directions.is_empty() { layout.directions } else { &self.directions };
+            if directions != scheduler::DIRECTIONS_BOTH {
+                return vec![forward];
+            }
+
+            let backward = SubCard {
+                direction: scheduler::DIRECTION_BACKWARD.to_owned(),
+                frontside: self.backside.clone(),
+                backside: self.frontside.clone(),
+                answer: self.frontside.clone(),
+                ..forward.clone()
+            };
+            return vec![forward, backward];
+        }
+
+        let mut backside = cloze::render_answer(&self.frontside);
+        if ! self.backside.trim().is_empty() {
+            backside = format!("{}\n\n{}", backside, self.backside);
+        }
+
+        cloze::indexes(&self.frontside).into_iter().map(|index| SubCard {
+            card_id: self.unique_id.clone(),
+            stack_id: self.stack_id.clone(),
+            card_type: self.card_type.clone(),
+            ordinal: index,
+            direction: scheduler::DIRECTION_FORWARD.to_owned(),
+            frontside: cloze::render_question(&self.frontside, index),
+            backside: backside.clone(),
+            answer: cloze::answer(&self.frontside, index),
+        }).collect()
+    }
+
+    pub fn sub_card(&self, layout: &CardLayout, ordinal: i32, direction: &str) -> Option<SubCard> {
+        self.sub_cards(layout).into_iter().find(|sub_card| sub_card.ordinal == ordinal && sub_card.direction == direction)
+    }
+}
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = note_types)]
+pub struct NoteType {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub name: String,
+    /// JSON list of field names
+    pub fields: String,
+    /// JSON list of notes::Template
+    pub templates: String,
+}
+
+impl NoteType {
+    pub fn field_names(&self) -> Vec<String> {
+        serde_json::from_str(&self.fields).unwrap_or_default()
+    }
+
+    pub fn template_list(&self) -> Vec<notes::Template> {
+        serde_json::from_str(&self.templates).unwrap_or_default()
+    }
+}
+
+/// Part of a card with its own review state, not stored in the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubCard {
+    pub card_id: String,
+    pub stack_id: String,
+    pub card_type: String,
+    pub ordinal: i32,
+    pub direction: String,
+    pub frontside: String,
+    pub backside: String,
+    /// What the user has to recall, the backside (frontside when backward) of basic cards or the hidden text of a cloze
+    pub answer: String,
+}
+
+impl SubCard {
+    pub fn is_sub_card(&self, card_id: &str, ordinal: i32, direction: &str) -> bool {
+        self.card_id == card_id && self.ordinal == ordinal && self.direction == direction
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = card_states)]
+pub struct CardState {
+    pub user_id: String,
+    pub card_id: String,
+    pub ease_factor: f64,
+    pub interval_days: i32,
+    pub repetitions: i32,
+    pub due: i64,
+    pub stability: f64,
+    pub difficulty: f64,
+    pub last_review: i64,
+    pub phase: String,
+    pub step: i32,
+    pub lapses: i32,
+    pub leech: bool,
+    pub suspended: bool,
+    pub buried_until: i64,
+    pub ordinal: i32,
+    pub direction: String,
+}
+
+impl CardState {
+    /// State of a card that the user has never reviewed
+    pub fn new(user_id: &str, card_id: &str, now: i64) -> Self {
+        CardState {
+            user_id: user_id.to_owned(),
+            card_id: card_id.to_owned(),
+            ease_factor: scheduler::sm2::DEFAULT_EASE_FACTOR,
+            interval_days: 0,
+            repetitions: 0,
+            due: now,
+            stability: 0.0,
+            difficulty: 0.0,
+            last_review: 0,
+            phase: scheduler::steps::PHASE_NEW.to_owned(),
+            step: 0,
+            lapses: 0,
+            leech: false,
+            suspended: false,
+            buried_until: 0,
+            ordinal: 0,
+            direction: scheduler::DIRECTION_FORWARD.to_owned(),
+        }
+    }
+}
+
+
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = card_lineage)]
+#[diesel(treat_none_as_null = true)]
+pub struct CardLineage {
+    /// Fork the card belongs to
+    pub stack_id: String,
+    pub upstream_card_id: String,
+    /// None once the card was deleted in the fork
+    pub card_id: Option<String>,
+    /// JSON of upstream::CardContent when the card was forked or last pulled
+    pub base: String,
+}
+
+impl CardLineage {
+    pub fn base_content(&self) -> upstream::CardContent {
+        serde_json::from_str(&self.base).unwrap_or_default()
+    }
+}
+
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = stack_members)]
+pub struct StackMember {
+    pub stack_id: String,
+    pub user_id: String,
+    /// One of the `members::ROLE_` roles except the owner
+    pub role: String,
+    /// False while the invite is pending
+    pub accepted: bool,
+    pub invited_at: i64,
+}
+
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[diesel(table_name = share_tokens)]
+pub struct ShareToken {
+    pub token: String,
+    pub stack_id: String,
+    pub created_at: i64,
+    /// None for tokens that stay valid until they are revoked
+    pub expires_at: Option<i64>,
+}
+
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = suggestions)]
+#[diesel(treat_none_as_null = true)]
+pub struct Suggestion {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub author_id: String,
+    /// One of the `suggestions::KIND_` kinds
+    pub kind: String,
+    /// Card an edit or deletion is for, None for new cards or once the card was deleted
+    pub card_id: Option<String>,
+    /// Suggested content, empty for deletions
+    pub frontside: String,
+    pub backside: String,
+    pub card_type: String,
+    /// One of the `suggestions::STATUS_` values
+    pub status: String,
+    pub created_at: i64,
+    pub resolved_at: Option<i64>,
+}
+
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = review_log)]
+pub struct ReviewLog {
+    pub user_id: String,
+    pub card_id: String,
+    pub stack_id: String,
+    pub reviewed_at: i64,
+    pub grade: i32,
+    pub previous_interval: i32,
+    pub next_interval: i32,
+    pub time_taken: i32,
+    pub phase: String,
+    pub ordinal: i32,
+    pub direction: String,
+}
+
+#[derive(Debug, Clone, Queryable)]
+pub struct ReviewLogFull {
+    pub unique_id: i64,
+    #[allow(unused)]
+    pub user_id: String,
+    pub card_id: String,
+    pub stack_id: String,
+    pub reviewed_at: i64,
+    pub grade: i32,
+    pub previous_interval: i32,
+    pub next_interval: i32,
+    pub time_taken: i32,
+    pub phase: String,
+    pub ordinal: i32,
+    pub direction: String,
+}
+
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = study_sessions)]
+#[diesel(treat_none_as_null = true)]
+pub struct StudySession {
+    pub unique_id: String,
+    pub user_id: String,
+    pub stack_id: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub queue: String,
+    pub new_count: i32,
+    pub position: i32,
+    pub answered_count: i32,
+    pub correct_count: i32,
+    pub mode: String,
+}
+
+impl StudySession {
+    /// Card ids, ordinals and directions in the order they will be served
+    pub fn queue_items(&self) -> Vec<(&str, i32, &str)> {
+        self.queue.split(',')
+            .filter(|item| ! item.is_empty())
+            .map(|item| {
+                let mut parts = item.split(':');
+                let id = parts.next().unwrap_or_default();
+                let ordinal = parts.next().and_then(|ordinal| ordinal.parse::<i32>().ok()).unwrap_or(0);
+                let direction = parts.next().unwrap_or(scheduler::DIRECTION_FORWARD);
+                (id, ordinal, direction)
+            })
+            .collect()
+    }
+
+    /// Queue entry of a sub-card, `card_id:ordinal:direction` with the defaults (0 and forward) left out at the end
+    pub fn queue_item(card: &SubCard) -> String {
+        if card.direction != scheduler::DIRECTION_FORWARD {
+            format!("{}:{}:{}", card.card_id, card.ordinal, card.direction)
+        } else if card.ordinal != 0 {
+            format!("{}:{}", card.card_id, card.ordinal)
+        } else {
+            card.card_id.clone()
+        }
+    }
+
+    /// Puts a sub-card at the end of the queue
+    pub fn push_queue_item(&mut self, card: &SubCard) {
+        self.queue = format!("{},{}", self.queue, StudySession::queue_item(card));
+    }
+}
+
+
+#[derive(Debug, Clone, Queryable, AsChangeset, Insertable)]
+#[diesel(table_name = quiz_attempts)]
+#[diesel(treat_none_as_null = true)]
+pub struct QuizAttempt {
+    pub unique_id: String,
+    pub user_id: String,
+    pub stack_id: String,
+    pub seed: u32,
+    /// JSON list of quiz::Question or quiz::TestQuestion, depending on the kind
+    pub questions: String,
+    /// JSON list of selected option indexes or quiz::TestAnswer, None until submitted
+    pub answers: Option<String>,
+    pub score: Option<i32>,
+    pub question_count: i32,
+    pub kind: String,
+    pub max_score: i32,
+    pub created_at: i64,
+    pub submitted_at: Option<i64>,
+}
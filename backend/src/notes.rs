@@ -0,0 +1,217 @@
+//! ## Note types
+//!
+//! A note type names the fields of its cards (ex. Word, Reading, Meaning) and has one or more
+//! templates that render the front and the back of a card out of those fields.
+//! Every template of a note type becomes its own sub-card.
+//!
+//! Templates use a small language:
+//! - `{{Field}}` is replaced with the value of the field
+//! - `{{#Field}}...{{/Field}}` is only kept when the field is not empty, `{{^Field}}...{{/Field}}` only when it is
+//! - `{{FrontSide}}` on the back is the rendered front
+//!
+//! Cards with an empty `note_type_id` use the built-in Basic note type, its fields are the frontside and backside columns.
+
+use std::collections::BTreeMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+pub const BASIC_NOTE_TYPE_ID: &str = "";
+pub const BASIC_NOTE_TYPE_NAME: &str = "Basic";
+pub const BASIC_FRONT_FIELD: &str = "Front";
+pub const BASIC_BACK_FIELD: &str = "Back";
+
+/// Special field of back templates
+pub const FRONT_SIDE: &str = "FrontSide";
+
+pub const MAX_FIELDS: usize = 20;
+pub const MAX_TEMPLATES: usize = 10;
+pub const MAX_NAME_LENGTH: usize = 64;
+pub const MAX_TEMPLATE_LENGTH: usize = 1000;
+/// Same as the frontside and backside of basic cards
+pub const MAX_FIELD_LENGTH: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    pub front: String,
+    pub back: String,
+}
+
+/// Field values of a card by field name
+pub type Fields = BTreeMap<String, String>;
+
+/// Fields and templates of the built-in Basic note type
+pub fn basic_note_type() -> (Vec<String>, Vec<Template>) {
+    (
+        vec![BASIC_FRONT_FIELD.to_owned(), BASIC_BACK_FIELD.to_owned()],
+        vec![Template {
+            name: "Card 1".to_owned(),
+            front: format!("{{{{{}}}}}", BASIC_FRONT_FIELD),
+            back: format!("{{{{{}}}}}", BASIC_BACK_FIELD),
+        }],
+    )
+}
+
+fn tag_regex() -> Regex {
+    Regex::new(r"\{\{([#^/]?)\s*([^{}]*?)\s*\}\}").unwrap()
+}
+
+/// 1 - 64 characters, without braces and not starting with a section sign
+pub fn is_valid_name(name: &str) -> bool {
+    let length = name.trim().chars().count();
+    (1..=MAX_NAME_LENGTH).contains(&length) &&
+        ! name.contains(['{', '}']) &&
+        ! name.trim().starts_with(['#', '^', '/'])
+}
+
+/// 1 - 20 distinct valid names, `FrontSide` is reserved
+pub fn is_valid_field_names(names: &[String]) -> bool {
+    if names.is_empty() || names.len() > MAX_FIELDS {
+        return false;
+    }
+
+    names.iter().enumerate().all(|(i, name)| {
+        is_valid_name(name) &&
+            name.trim() == name &&
+            name != FRONT_SIDE &&
+            ! names[..i].contains(name)
+    })
+}
+
+/// ## Checks a template against the fields of its note type.
+///
+/// Every tag has to name one of the fields and sections have to be closed in order.
+/// Front templates have to use at least one field, `{{FrontSide}}` is only allowed on the back.
+///
+/// ### Arguments
+///
+/// * `template` - &str
+/// * `field_names` - &[String]
+/// * `back` - bool, if it's a back template
+pub fn is_valid_template(template: &str, field_names: &[String], back: bool) -> bool {
+    if template.chars().count() > MAX_TEMPLATE_LENGTH {
+        return false;
+    }
+
+    let mut sections: Vec<&str> = Vec::new();
+    let mut uses_field = false;
+    for captures in tag_regex().captures_iter(template) {
+        let name = captures.get(2).map_or("", |name| name.as_str());
+        let known = field_names.iter().any(|field| field == name);
+
+        match &captures[1] {
+            "/" => {
+                if sections.pop() != Some(name) {
+                    return false;
+                }
+            }
+            sign => {
+                let front_side = back && sign.is_empty() && name == FRONT_SIDE;
+                if ! (known || front_side) {
+                    return false;
+                }
+                if ! sign.is_empty() {
+                    sections.push(name);
+                }
+                uses_field = uses_field || known;
+            }
+        }
+    }
+
+    // Leftovers of broken tags, ex. `{{Word}`
+    let leftover = tag_regex().replace_all(template, "");
+    sections.is_empty() && (back || uses_field) && ! leftover.contains("{{") && ! leftover.contains("}}")
+}
+
+/// All templates valid for the fields, 1 - 10 of them with valid names
+pub fn is_valid_templates(templates: &[Template], field_names: &[String]) -> bool {
+    ! templates.is_empty() &&
+        templates.len() <= MAX_TEMPLATES &&
+        templates.iter().all(|template| {
+            is_valid_name(&template.name) &&
+                is_valid_template(&template.front, field_names, false) &&
+                is_valid_template(&template.back, field_names, true)
+        })
+}
+
+/// Only fields of the note type, each up to 255 bytes, at least one of them not empty
+pub fn is_valid_fields(fields: &Fields, field_names: &[String]) -> bool {
+    fields.iter().all(|(name, value)| field_names.contains(name) && value.len() <= MAX_FIELD_LENGTH) &&
+        fields.values().any(|value| ! value.trim().is_empty())
+}
+
+/// ## Renders a template.
+///
+/// ### Arguments
+///
+/// * `template` - &str
+/// * `fields` - &Fields, missing fields are empty
+/// * `front_side` - &str, rendered front for `{{FrontSide}}`
+pub fn render(template: &str, fields: &Fields, front_side: &str) -> String {
+    let value = |name: &str| -> String {
+        if name == FRONT_SIDE {
+            front_side.to_owned()
+        } else {
+            fields.get(name).cloned().unwrap_or_default()
+        }
+    };
+
+    let mut output = String::with_capacity(template.len());
+    // Visibility of every open section, text is only kept when all of them are visible
+    let mut sections: Vec<bool> = Vec::new();
+    let mut last = 0;
+
+    for captures in tag_regex().captures_iter(template) {
+        let tag = captures.get(0).unwrap();
+        if sections.iter().all(|visible| *visible) {
+            output.push_str(&template[last..tag.start()]);
+        }
+        last = tag.end();
+
+        let name = captures.get(2).map_or("", |name| name.as_str());
+        match &captures[1] {
+            "#" => sections.push(! value(name).trim().is_empty()),
+            "^" => sections.push(value(name).trim().is_empty()),
+            "/" => {
+                sections.pop();
+            }
+            _ => {
+                if sections.iter().all(|visible| *visible) {
+                    output.push_str(&value(name));
+                }
+            }
+        }
+    }
+
+    if sections.iter().all(|visible| *visible) {
+        output.push_str(&template[last..]);
+    }
+    output.trim().to_owned()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedCard {
+    /// Index of the template
+    pub template: usize,
+    pub front: String,
+    pub back: String,
+    /// Back without the front side, what the user has to recall
+    pub answer: String,
+}
+
+/// Renders both sides of a card for every template, templates with an empty front are left out
+pub fn render_cards(templates: &[Template], fields: &Fields) -> Vec<RenderedCard> {
+    templates.iter().enumerate().filter_map(|(i, template)| {
+        let front = render(&template.front, fields, "");
+        if front.is_empty() {
+            return None;
+        }
+
+        Some(RenderedCard {
+            template: i,
+            back: render(&template.back, fields, &front),
+            answer: render(&template.back, fields, ""),
+            front,
+        })
+    }).collect()
+}
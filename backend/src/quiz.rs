@@ -0,0 +1,293 @@
+//! ## Quizzes and tests
+//!
+//! Multiple choice quizzes ask for the answer of a card, the other options are answers
+//! of other cards of the same stack. Tests mix true/false, matching and written questions.
+//! Cards are sub-cards, so every cloze of a cloze card can be asked about on its own.
+//! The same cards and seed always give the same quiz or test.
+
+use serde::{Deserialize, Serialize};
+use crate::{answers, models, utils};
+
+pub const KIND_MULTIPLE_CHOICE: &str = "multiple_choice";
+pub const KIND_TEST: &str = "test";
+
+pub const DEFAULT_QUESTION_COUNT: i32 = 10;
+pub const MAX_QUESTION_COUNT: i32 = 100;
+/// Correct option included
+pub const OPTIONS_COUNT: usize = 4;
+/// Pairs in a matching question
+pub const MATCHING_SIZE: usize = 4;
+/// Written answers graded at least this high by answers::check_answer count as correct
+const WRITTEN_PASSING_GRADE: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Question {
+    pub card_id: String,
+    pub prompt: String,
+    pub options: Vec<String>,
+    /// Index of the correct option
+    pub correct: usize,
+}
+
+/// 1 - 100
+pub fn is_valid_question_count(count: i32) -> bool {
+    (1..=MAX_QUESTION_COUNT).contains(&count)
+}
+
+/// Rough kind of an answer, distractors of the same kind are harder to rule out
+fn answer_kind(text: &str) -> u8 {
+    let text = text.trim();
+    if text.replace(',', ".").parse::<f64>().is_ok() {
+        0
+    } else if ! text.contains(char::is_whitespace) {
+        1
+    } else {
+        2
+    }
+}
+
+/// ## Generates quiz questions.
+///
+/// Cards are picked in random order, cards without any different backside in the stack are skipped.
+/// Distractors are drawn from the backsides closest in kind (number, single word, sentence) and length.
+///
+/// ### Arguments
+///
+/// * `cards` - &[models::SubCard], cards of one stack
+/// * `count` - usize, max number of questions
+/// * `seed` - u64
+pub fn generate_questions(cards: &[models::SubCard], count: usize, seed: u64) -> Vec<Question> {
+    let mut order: Vec<usize> = (0..cards.len()).collect();
+    utils::shuffle(&mut order, seed);
+
+    // Distinct answers, cards with the same backside share it
+    let mut distinct: Vec<(String, &str)> = Vec::new();
+    for card in cards {
+        let normalized = answers::normalize(&card.answer);
+        if ! distinct.iter().any(|(existing, _)| *existing == normalized) {
+            distinct.push((normalized, card.answer.as_str()));
+        }
+    }
+
+    let mut questions: Vec<Question> = Vec::new();
+    for (number, index) in order.into_iter().enumerate() {
+        if questions.len() == count {
+            break;
+        }
+
+        let card = &cards[index];
+        let correct = answers::normalize(&card.answer);
+        let kind = answer_kind(&card.answer);
+        let length = card.answer.chars().count() as i64;
+
+        let mut candidates: Vec<&str> = distinct.iter()
+            .filter(|(normalized, _)| *normalized != correct)
+            .map(|(_, backside)| *backside)
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+
+        // Stable sort keeps the stack order between equally close candidates
+        candidates.sort_by_key(|backside| (answer_kind(backside) != kind, (backside.chars().count() as i64 - length).abs()));
+        candidates.truncate((OPTIONS_COUNT - 1) * 2);
+
+        // Random pick among the closest ones, but answers of the same kind always go first
+        let question_seed = seed.wrapping_add(2 * number as u64 + 1);
+        utils::shuffle(&mut candidates, question_seed);
+        candidates.sort_by_key(|backside| answer_kind(backside) != kind);
+
+        let mut options: Vec<String> = vec![card.answer.clone()];
+        options.extend(candidates.into_iter().take(OPTIONS_COUNT - 1).map(|backside| backside.to_owned()));
+        utils::shuffle(&mut options, question_seed.wrapping_add(1));
+
+        questions.push(Question {
+            card_id: card.card_id.clone(),
+            prompt: card.frontside.clone(),
+            correct: options.iter().position(|option| *option == card.answer).unwrap_or(0),
+            options,
+        });
+    }
+    questions
+}
+
+/// ## Grades submitted answers.
+///
+/// ### Arguments
+///
+/// * `questions` - &[Question]
+/// * `selected` - &[i32], index of the selected option for every question, missing or negative means skipped
+///
+/// ### Returns
+/// **Vec\<bool\>**, if each question was answered correctly
+pub fn grade(questions: &[Question], selected: &[i32]) -> Vec<bool> {
+    questions.iter().enumerate()
+        .map(|(i, question)| selected.get(i).is_some_and(|option| *option >= 0 && *option as usize == question.correct))
+        .collect()
+}
+
+/// Stored answers of a multiple choice quiz, comma separated option indexes
+pub fn selected_to_string(selected: &[i32]) -> String {
+    selected.iter().map(|option| option.to_string()).collect::<Vec<String>>().join(",")
+}
+
+/// Reads stored answers of a multiple choice quiz, see `selected_to_string`
+pub fn parse_selected(answers: &str) -> Vec<i32> {
+    answers.split(',')
+        .filter_map(|option| option.trim().parse::<i32>().ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TestQuestion {
+    /// Is `shown_answer` the backside of the card
+    TrueFalse { card_id: String, prompt: String, shown_answer: String, correct: bool },
+    /// `prompts[i]` goes with `options[correct[i]]`
+    Matching { card_ids: Vec<String>, prompts: Vec<String>, options: Vec<String>, correct: Vec<usize> },
+    Written { card_id: String, prompt: String, expected: String },
+}
+
+/// Answer to a single test question, matching answers are option indexes for every prompt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TestAnswer {
+    TrueFalse(bool),
+    Matching(Vec<i32>),
+    Written(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feedback {
+    pub points: i32,
+    pub max_points: i32,
+    /// Only for written questions
+    pub similarity: Option<f64>,
+}
+
+impl TestQuestion {
+    /// One point per question, matching questions give one point per pair
+    pub fn max_points(&self) -> i32 {
+        match self {
+            TestQuestion::Matching { correct, .. } => correct.len() as i32,
+            _ => 1,
+        }
+    }
+
+    /// Answers of the wrong type and skipped questions get no points
+    pub fn grade(&self, answer: Option<&TestAnswer>) -> Feedback {
+        let max_points = self.max_points();
+        let (points, similarity) = match (self, answer) {
+            (TestQuestion::TrueFalse { correct, .. }, Some(TestAnswer::TrueFalse(value))) => {
+                (i32::from(correct == value), None)
+            }
+            (TestQuestion::Matching { correct, .. }, Some(TestAnswer::Matching(selected))) => {
+                let points = correct.iter().zip(selected)
+                    .filter(|(correct, selected)| **selected >= 0 && **selected as usize == **correct)
+                    .count();
+                (points as i32, None)
+            }
+            (TestQuestion::Written { expected, .. }, Some(TestAnswer::Written(typed))) => {
+                let check = answers::check_answer(expected, typed);
+                (i32::from(check.suggested_grade >= WRITTEN_PASSING_GRADE), Some(check.similarity))
+            }
+            _ => (0, None),
+        };
+
+        Feedback { points, max_points, similarity }
+    }
+}
+
+/// ## Generates a test.
+///
+/// Question types take turns (true/false, written, matching), every card is used at most once.
+/// Matching questions need `MATCHING_SIZE` cards with different backsides, when there are not enough
+/// of them left the turn goes to the next type.
+///
+/// ### Arguments
+///
+/// * `cards` - &[models::SubCard], cards of one stack
+/// * `count` - usize, max number of questions
+/// * `seed` - u64
+pub fn generate_test(cards: &[models::SubCard], count: usize, seed: u64) -> Vec<TestQuestion> {
+    let mut rng = utils::SeededRng::new(seed);
+    let mut remaining: Vec<&models::SubCard> = cards.iter().collect();
+    utils::shuffle(&mut remaining, rng.next_u64());
+    remaining.reverse();
+
+    let mut questions: Vec<TestQuestion> = Vec::new();
+    let mut turn = 0;
+    while questions.len() < count && ! remaining.is_empty() {
+        turn += 1;
+        match turn % 3 {
+            1 => {
+                let Some(card) = remaining.pop() else { break };
+                let different: Vec<&models::SubCard> = cards.iter()
+                    .filter(|other| answers::normalize(&other.answer) != answers::normalize(&card.answer))
+                    .collect();
+
+                // Half of the statements are false, as long as there is a different backside to show
+                let (shown, correct) = if different.is_empty() || rng.below(2) == 0 {
+                    (card, true)
+                } else {
+                    (different[rng.below(different.len())], false)
+                };
+
+                questions.push(TestQuestion::TrueFalse {
+                    card_id: card.card_id.clone(),
+                    prompt: card.frontside.clone(),
+                    shown_answer: shown.answer.clone(),
+                    correct,
+                });
+            }
+
+            2 => {
+                let Some(card) = remaining.pop() else { break };
+                questions.push(TestQuestion::Written {
+                    card_id: card.card_id.clone(),
+                    prompt: card.frontside.clone(),
+                    expected: card.answer.clone(),
+                });
+            }
+
+            _ => {
+                // Picks from the end of the remaining cards, the ones with a repeated backside stay
+                let mut picked: Vec<usize> = Vec::new();
+                let mut backsides: Vec<String> = Vec::new();
+                for (i, card) in remaining.iter().enumerate().rev() {
+                    let normalized = answers::normalize(&card.answer);
+                    if ! backsides.contains(&normalized) {
+                        backsides.push(normalized);
+                        picked.push(i);
+                    }
+                    if picked.len() == MATCHING_SIZE {
+                        break;
+                    }
+                }
+                if picked.len() < MATCHING_SIZE {
+                    continue;
+                }
+
+                // Indexes go down, so removing them one by one keeps the rest valid
+                let group: Vec<&models::SubCard> = picked.into_iter().map(|i| remaining.remove(i)).collect();
+                let mut options: Vec<String> = group.iter().map(|card| card.answer.clone()).collect();
+                utils::shuffle(&mut options, rng.next_u64());
+
+                questions.push(TestQuestion::Matching {
+                    card_ids: group.iter().map(|card| card.card_id.clone()).collect(),
+                    prompts: group.iter().map(|card| card.frontside.clone()).collect(),
+                    correct: group.iter().map(|card| options.iter().position(|option| *option == card.answer).unwrap_or(0)).collect(),
+                    options,
+                });
+            }
+        }
+    }
+    questions
+}
+
+/// Feedback for every question, `answers[i]` answers `questions[i]`
+pub fn grade_test(questions: &[TestQuestion], answers: &[Option<TestAnswer>]) -> Vec<Feedback> {
+    questions.iter().enumerate()
+        .map(|(i, question)| question.grade(answers.get(i).and_then(|answer| answer.as_ref())))
+        .collect()
+}
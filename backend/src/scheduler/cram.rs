@@ -0,0 +1,94 @@
+//! ## Cram sessions
+//!
+//! Cram goes over a selection of cards regardless of their due dates,
+//! answers are never passed to the scheduler so the stored review state stays untouched.
+
+use std::collections::HashMap;
+use crate::{models, utils};
+use super::{Reviewable, SECONDS_IN_DAY};
+
+pub const ORDER_RANDOM: &str = "random";
+pub const ORDER_WEAKEST: &str = "weakest";
+
+pub fn is_valid_order(order: &str) -> bool {
+    matches!(order, ORDER_RANDOM | ORDER_WEAKEST)
+}
+
+/// Optional filters, a card has to pass all of the present ones
+#[derive(Debug, Clone, Default)]
+pub struct CramFilter {
+    /// Minimal count of failed reviews (grade below 3)
+    pub min_failures: Option<i32>,
+    /// Card has to be reviewed at least once in this many last days
+    pub reviewed_within_days: Option<i32>,
+}
+
+/// ## Builds a cram queue.
+///
+/// Suspended and buried cards are left out. Weakest first puts the most failed cards
+/// at the front, ties go to the card with the shortest interval.
+///
+/// ### Arguments
+///
+/// * `cards` - Vec\<T\>, cards or sub-cards
+/// * `states` - &[models::CardState], states of the studying user
+/// * `logs` - &[models::ReviewLogFull], review history of the studying user
+/// * `filter` - &CramFilter
+/// * `order` - &str, `random` or `weakest`
+/// * `now` - i64, unix timestamp
+/// * `seed` - u64, used for the random order
+pub fn build_cram_queue<T: Reviewable>(
+    cards: Vec<T>,
+    states: &[models::CardState],
+    logs: &[models::ReviewLogFull],
+    filter: &CramFilter,
+    order: &str,
+    now: i64,
+    seed: u64,
+) -> Vec<T> {
+    let states: HashMap<(&str, i32, &str), &models::CardState> = states.iter()
+        .map(|state| ((state.card_id.as_str(), state.ordinal, state.direction.as_str()), state))
+        .collect();
+
+    let mut failures: HashMap<(&str, i32, &str), i32> = HashMap::new();
+    let mut last_reviewed: HashMap<(&str, i32, &str), i64> = HashMap::new();
+    for log in logs {
+        let key = (log.card_id.as_str(), log.ordinal, log.direction.as_str());
+        if log.grade < 3 {
+            *failures.entry(key).or_insert(0) += 1;
+        }
+        let last = last_reviewed.entry(key).or_insert(log.reviewed_at);
+        *last = (*last).max(log.reviewed_at);
+    }
+
+    let mut queue: Vec<(i32, i32, T)> = cards.into_iter().filter_map(|card| {
+        let key = (card.card_id(), card.ordinal(), card.direction());
+        let state = states.get(&key);
+        if state.is_some_and(|state| state.suspended || state.buried_until > now) {
+            return None;
+        }
+
+        let card_failures = failures.get(&key).copied().unwrap_or(0);
+        if filter.min_failures.is_some_and(|min| card_failures < min) {
+            return None;
+        }
+
+        if let Some(days) = filter.reviewed_within_days {
+            let since = now - days as i64 * SECONDS_IN_DAY;
+            match last_reviewed.get(&key) {
+                Some(last) if *last >= since => {}
+                _ => return None,
+            }
+        }
+
+        let interval = state.map(|state| state.interval_days).unwrap_or(0);
+        Some((card_failures, interval, card))
+    }).collect();
+
+    match order {
+        ORDER_WEAKEST => queue.sort_by_key(|(card_failures, interval, _)| (-card_failures, *interval)),
+        _ => utils::shuffle(&mut queue, seed),
+    }
+
+    queue.into_iter().map(|(_, _, card)| card).collect()
+}
@@ -0,0 +1,268 @@
+//! ## Free Spaced Repetition Scheduler (FSRS v4.5)
+//!
+//! Memory of a card is described by its stability (days until retrievability drops to 90%)
+//! and difficulty (1 - 10). https://github.com/open-spaced-repetition/fsrs4anki/wiki/The-Algorithm
+
+use std::collections::BTreeMap;
+use crate::models;
+use super::{Scheduler, SECONDS_IN_DAY};
+
+pub const PARAMETERS_COUNT: usize = 17;
+
+pub const DEFAULT_PARAMETERS: [f64; PARAMETERS_COUNT] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474,
+    0.1367, 1.0461, 2.1072, 0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+
+/// Lower and upper bound of every parameter, used for validation and while optimizing
+pub const PARAMETER_BOUNDS: [(f64, f64); PARAMETERS_COUNT] = [
+    (0.1, 100.0), (0.1, 100.0), (0.1, 100.0), (0.1, 100.0), (1.0, 10.0), (0.001, 4.0),
+    (0.001, 4.0), (0.001, 0.75), (0.0, 4.5), (0.0, 0.8), (0.001, 3.5), (0.001, 5.0),
+    (0.001, 0.25), (0.001, 0.9), (0.0, 4.0), (0.0, 1.0), (1.0, 6.0),
+];
+
+pub const DESIRED_RETENTION: f64 = 0.9;
+
+/// Optimizing on less reviews than this would only overfit the parameters
+pub const MIN_REVIEWS_FOR_OPTIMIZATION: usize = 50;
+const MAX_OPTIMIZATION_ROUNDS: usize = 200;
+
+const DECAY: f64 = -0.5;
+const FACTOR: f64 = 19.0 / 81.0;
+
+#[derive(Debug, Clone)]
+pub struct Fsrs {
+    pub parameters: [f64; PARAMETERS_COUNT],
+    pub desired_retention: f64,
+}
+
+impl Default for Fsrs {
+    fn default() -> Self {
+        Fsrs::new(DEFAULT_PARAMETERS)
+    }
+}
+
+/// One review of a card, sequences of those are used for optimizing the parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewRecord {
+    pub timestamp: i64,
+    pub grade: u8,
+}
+
+impl Fsrs {
+    pub fn new(parameters: [f64; PARAMETERS_COUNT]) -> Self {
+        Fsrs { parameters, desired_retention: DESIRED_RETENTION }
+    }
+
+    /// Falls back to the default parameters if the stored ones are empty or invalid
+    pub fn from_parameters_str(parameters: &str) -> Self {
+        Fsrs::new(parse_parameters(parameters).unwrap_or(DEFAULT_PARAMETERS))
+    }
+
+    /// Probability of recalling a card `elapsed_days` after the last review
+    pub fn retrievability(elapsed_days: f64, stability: f64) -> f64 {
+        (1.0 + FACTOR * elapsed_days / stability).powf(DECAY)
+    }
+
+    /// ## Computes the memory state after a review.
+    ///
+    /// ### Arguments
+    ///
+    /// * `memory` - Option<(stability, difficulty)>, None if the card was never reviewed
+    /// * `elapsed_days` - f64, time since the last review
+    /// * `rating` - f64, FSRS rating in 1..=4 range
+    ///
+    /// ### Returns
+    /// **(stability, difficulty)**
+    pub fn next_memory_state(&self, memory: Option<(f64, f64)>, elapsed_days: f64, rating: f64) -> (f64, f64) {
+        let w = &self.parameters;
+
+        let (stability, difficulty) = match memory {
+            Some(value) => value,
+            None => return (w[rating as usize - 1].max(0.1), self.init_difficulty(rating)),
+        };
+
+        let r = Fsrs::retrievability(elapsed_days, stability);
+        let next_stability = if rating == 1.0 {
+            w[11] * difficulty.powf(-w[12]) * ((stability + 1.0).powf(w[13]) - 1.0) * (w[14] * (1.0 - r)).exp()
+        } else {
+            let hard_penalty = if rating == 2.0 { w[15] } else { 1.0 };
+            let easy_bonus = if rating == 4.0 { w[16] } else { 1.0 };
+            stability * (w[8].exp() * (11.0 - difficulty) * stability.powf(-w[9])
+                * ((w[10] * (1.0 - r)).exp() - 1.0) * hard_penalty * easy_bonus + 1.0)
+        };
+
+        let next_difficulty = difficulty - w[6] * (rating - 3.0);
+        let next_difficulty = w[7] * self.init_difficulty(3.0) + (1.0 - w[7]) * next_difficulty;
+
+        (next_stability.max(0.1), next_difficulty.clamp(1.0, 10.0))
+    }
+
+    /// Days until retrievability drops to the desired retention
+    pub fn next_interval(&self, stability: f64) -> i32 {
+        let interval = stability / FACTOR * (self.desired_retention.powf(1.0 / DECAY) - 1.0);
+        (interval.round() as i32).max(1)
+    }
+
+    fn init_difficulty(&self, rating: f64) -> f64 {
+        (self.parameters[4] - (rating - 3.0) * self.parameters[5]).clamp(1.0, 10.0)
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn review(&self, state: &mut models::CardState, grade: u8, now: i64) {
+        // Cards that were only scheduled by other algorithms have no memory state yet
+        let memory = if state.stability > 0.0 {
+            Some((state.stability, state.difficulty))
+        } else {
+            None
+        };
+        let elapsed_days = (now - state.last_review).max(0) as f64 / SECONDS_IN_DAY as f64;
+
+        let (stability, difficulty) = self.next_memory_state(memory, elapsed_days, grade_to_rating(grade));
+        state.stability = stability;
+        state.difficulty = difficulty;
+        state.repetitions = if grade >= 3 { state.repetitions + 1 } else { 0 };
+        state.interval_days = self.next_interval(stability);
+        state.last_review = now;
+        state.due = now + state.interval_days as i64 * SECONDS_IN_DAY;
+    }
+}
+
+/// Maps the 0 - 5 grade onto the again / hard / good / easy rating
+pub fn grade_to_rating(grade: u8) -> f64 {
+    match grade {
+        0..=2 => 1.0,
+        3 => 2.0,
+        4 => 3.0,
+        _ => 4.0,
+    }
+}
+
+/// ## Groups review log records into per card sequences.
+///
+/// Every cloze, template and direction of a card is reviewed on its own, so each gets its own sequence.
+///
+/// ### Arguments
+///
+/// * `logs` - &[models::ReviewLogFull], in any order
+///
+/// ### Returns
+/// Sequences sorted by review time, ordered by card id, ordinal and direction
+pub fn sequences_from_logs(logs: &[models::ReviewLogFull]) -> Vec<Vec<ReviewRecord>> {
+    let mut sequences: BTreeMap<(&str, i32, &str), Vec<ReviewRecord>> = BTreeMap::new();
+    for log in logs {
+        sequences.entry((log.card_id.as_str(), log.ordinal, log.direction.as_str())).or_default().push(ReviewRecord {
+            timestamp: log.reviewed_at,
+            grade: log.grade as u8,
+        });
+    }
+    sequences.into_values().map(|mut sequence| {
+        sequence.sort_by_key(|record| record.timestamp);
+        sequence
+    }).collect()
+}
+
+pub fn is_valid_parameters(parameters: &[f64]) -> bool {
+    parameters.len() == PARAMETERS_COUNT &&
+        parameters.iter().zip(PARAMETER_BOUNDS.iter()).all(|(value, (min, max))| min <= value && value <= max)
+}
+
+/// Parameters are stored as a comma separated list, empty string means defaults
+pub fn parse_parameters(parameters: &str) -> Option<[f64; PARAMETERS_COUNT]> {
+    let parsed = parameters.split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()?;
+
+    if ! is_valid_parameters(&parsed) {
+        return None;
+    }
+    parsed.try_into().ok()
+}
+
+pub fn parameters_to_string(parameters: &[f64]) -> String {
+    parameters.iter().map(|value| format!("{:.4}", value)).collect::<Vec<String>>().join(",")
+}
+
+/// ## Average log loss of predicted retrievability against the actual review outcomes.
+///
+/// ### Returns
+/// **(loss, number of predicted reviews)**, first reviews of cards can't be predicted
+pub fn loss(parameters: &[f64; PARAMETERS_COUNT], sequences: &[Vec<ReviewRecord>]) -> (f64, usize) {
+    let fsrs = Fsrs::new(*parameters);
+    let mut total = 0.0;
+    let mut count = 0;
+
+    for sequence in sequences {
+        let mut memory: Option<(f64, f64)> = None;
+        let mut last_review = 0;
+
+        for record in sequence {
+            let elapsed_days = (record.timestamp - last_review).max(0) as f64 / SECONDS_IN_DAY as f64;
+
+            if let Some((stability, _)) = memory {
+                let r = Fsrs::retrievability(elapsed_days, stability).clamp(0.0001, 0.9999);
+                total -= if record.grade >= 3 { r.ln() } else { (1.0 - r).ln() };
+                count += 1;
+            }
+
+            memory = Some(fsrs.next_memory_state(memory, elapsed_days, grade_to_rating(record.grade)));
+            last_review = record.timestamp;
+        }
+    }
+
+    if count == 0 {
+        return (0.0, 0);
+    }
+    (total / count as f64, count)
+}
+
+/// ## Fits the parameters to the review history of a user.
+///
+/// Uses a coordinate search inside of `PARAMETER_BOUNDS`, so the result never ends up worse than the input.
+///
+/// ### Arguments
+///
+/// * `parameters` - &[f64; PARAMETERS_COUNT], starting point
+/// * `sequences` - &[Vec\<ReviewRecord\>], reviews grouped by card and sorted by timestamp
+///
+/// ### Returns
+/// Optimized parameters or None if there are not enough reviews
+pub fn optimize(parameters: &[f64; PARAMETERS_COUNT], sequences: &[Vec<ReviewRecord>]) -> Option<[f64; PARAMETERS_COUNT]> {
+    let (mut best_loss, count) = loss(parameters, sequences);
+    if count < MIN_REVIEWS_FOR_OPTIMIZATION {
+        return None;
+    }
+
+    let mut best = *parameters;
+    let mut step = 0.1;
+    let mut rounds = 0;
+
+    while step > 0.001 && rounds < MAX_OPTIMIZATION_ROUNDS {
+        let mut improved = false;
+        rounds += 1;
+
+        for i in 0..PARAMETERS_COUNT {
+            let (min, max) = PARAMETER_BOUNDS[i];
+            for direction in [1.0, -1.0] {
+                let mut candidate = best;
+                candidate[i] = (best[i] + direction * step * (max - min)).clamp(min, max);
+
+                let (candidate_loss, _) = loss(&candidate, sequences);
+                if candidate_loss < best_loss {
+                    best = candidate;
+                    best_loss = candidate_loss;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+
+        if ! improved {
+            step /= 2.0;
+        }
+    }
+
+    Some(best)
+}
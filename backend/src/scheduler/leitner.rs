@@ -0,0 +1,81 @@
+//! ## Leitner boxes
+//!
+//! Every card sits in a box, a correct answer moves it one box up and a wrong one sends it back
+//! to the first box. Each box has its own interval in days, ex. `1,2,4,8,16` are five boxes.
+//! The box of a card is kept in `repetitions`.
+
+use crate::models;
+use super::{Scheduler, SECONDS_IN_DAY};
+
+pub const DEFAULT_INTERVALS: &str = "1,2,4,8,16";
+
+const MIN_BOXES: usize = 2;
+const MAX_BOXES: usize = 10;
+const MAX_INTERVAL_DAYS: i32 = 365;
+
+#[derive(Debug, Clone)]
+pub struct Leitner {
+    intervals: Vec<i32>,
+}
+
+impl Default for Leitner {
+    fn default() -> Self {
+        Leitner { intervals: parse_intervals(DEFAULT_INTERVALS).unwrap_or_default() }
+    }
+}
+
+impl Leitner {
+    /// Falls back to the default boxes if the stored intervals are invalid
+    pub fn from_intervals_str(intervals: &str) -> Self {
+        match parse_intervals(intervals) {
+            Some(intervals) => Leitner { intervals },
+            None => Leitner::default(),
+        }
+    }
+}
+
+impl Scheduler for Leitner {
+    fn review(&self, state: &mut models::CardState, grade: u8, now: i64) {
+        let last_box = self.intervals.len() as i32 - 1;
+
+        // Box count can shrink after the intervals are changed
+        state.repetitions = if grade >= 3 {
+            (state.repetitions + 1).clamp(0, last_box)
+        } else {
+            0
+        };
+
+        state.interval_days = self.intervals[state.repetitions as usize];
+        state.last_review = now;
+        state.due = now + state.interval_days as i64 * SECONDS_IN_DAY;
+    }
+}
+
+/// ## Parses box intervals.
+///
+/// ### Arguments
+///
+/// * `intervals` - &str, comma separated days, ex. `1,2,4,8,16`
+///
+/// ### Returns
+/// Interval of every box or None if there are less than 2 or more than 10 boxes
+/// or an interval is out of the 1 - 365 range
+pub fn parse_intervals(intervals: &str) -> Option<Vec<i32>> {
+    let parsed = intervals.split(',')
+        .map(|part| part.trim().parse::<i32>().ok().filter(|days| (1..=MAX_INTERVAL_DAYS).contains(days)))
+        .collect::<Option<Vec<i32>>>()?;
+
+    if ! (MIN_BOXES..=MAX_BOXES).contains(&parsed.len()) {
+        return None;
+    }
+    Some(parsed)
+}
+
+pub fn is_valid_intervals(intervals: &str) -> bool {
+    parse_intervals(intervals).is_some()
+}
+
+/// Formats intervals the same way for storing, ex. ` 1, 3` becomes `1,3`
+pub fn format_intervals(intervals: &str) -> String {
+    intervals.split(',').map(|part| part.trim()).collect::<Vec<&str>>().join(",")
+}
@@ -0,0 +1,242 @@
+//! ## Spaced repetition scheduling
+//!
+//! Review state is kept per user and per card in the `card_states` table,
+//! the functions in here only compute what that state should look like after a review.
+
+use std::collections::HashMap;
+use crate::models;
+
+pub mod sm2;
+pub mod fsrs;
+pub mod steps;
+pub mod cram;
+pub mod leitner;
+
+pub const SECONDS_IN_DAY: i64 = 86400;
+
+pub const SCHEDULER_SM2: &str = "sm2";
+pub const SCHEDULER_FSRS: &str = "fsrs";
+pub const SCHEDULER_LEITNER: &str = "leitner";
+
+/// Review sessions follow the schedule, cram sessions ignore it
+pub const SESSION_REVIEW: &str = "review";
+pub const SESSION_CRAM: &str = "cram";
+
+/// Cards studied in both directions are also asked from the backside, every direction has its own review state
+pub const DIRECTION_FORWARD: &str = "forward";
+pub const DIRECTION_BACKWARD: &str = "backward";
+pub const DIRECTIONS_BOTH: &str = "both";
+
+pub trait Scheduler {
+    /// Updates the card state after the user reviewed it with a grade in 0..=5 range
+    fn review(&self, state: &mut models::CardState, grade: u8, now: i64);
+
+    /// Tells if the card should be shown to the user at the given time
+    fn is_due(&self, state: &models::CardState, now: i64) -> bool {
+        state.due <= now
+    }
+}
+
+/// Something with its own review state, states are kept per card id, ordinal and direction
+pub trait Reviewable {
+    fn card_id(&self) -> &str;
+    fn ordinal(&self) -> i32;
+    fn direction(&self) -> &str;
+}
+
+/// Cards on their own stand for their first forward sub-card, cloze cards and cards studied
+/// in both directions have to be split with `sub_cards` first
+impl Reviewable for models::Card {
+    fn card_id(&self) -> &str {
+        &self.unique_id
+    }
+
+    fn ordinal(&self) -> i32 {
+        0
+    }
+
+    fn direction(&self) -> &str {
+        DIRECTION_FORWARD
+    }
+}
+
+impl Reviewable for models::SubCard {
+    fn card_id(&self) -> &str {
+        &self.card_id
+    }
+
+    fn ordinal(&self) -> i32 {
+        self.ordinal
+    }
+
+    fn direction(&self) -> &str {
+        &self.direction
+    }
+}
+
+/// Stacks are studied `forward` or in `both` directions
+pub fn is_valid_stack_directions(directions: &str) -> bool {
+    matches!(directions, DIRECTION_FORWARD | DIRECTIONS_BOTH)
+}
+
+/// Cards can also leave it to the stack (empty)
+pub fn is_valid_card_directions(directions: &str) -> bool {
+    directions.is_empty() || is_valid_stack_directions(directions)
+}
+
+pub fn is_valid_scheduler_name(name: &str) -> bool {
+    matches!(name, SCHEDULER_SM2 | SCHEDULER_FSRS)
+}
+
+/// ## Picks the scheduler selected by the user.
+///
+/// ### Arguments
+///
+/// * `user` - &models::User
+///
+/// ### Returns
+/// **Box\<dyn Scheduler\>**, SM-2 if the stored name is unknown
+pub fn for_user(user: &models::User) -> Box<dyn Scheduler> {
+    match user.scheduler.as_str() {
+        SCHEDULER_FSRS => Box::new(fsrs::Fsrs::from_parameters_str(&user.fsrs_parameters)),
+        _ => Box::new(sm2::Sm2),
+    }
+}
+
+/// Stacks either follow the scheduler of the user (empty name) or use Leitner boxes
+pub fn is_valid_stack_scheduler_name(name: &str) -> bool {
+    matches!(name, "" | SCHEDULER_LEITNER)
+}
+
+/// ## Picks the scheduler for a card of the given stack.
+///
+/// ### Arguments
+///
+/// * `user` - &models::User, studying user
+/// * `stack` - &models::StackFull
+///
+/// ### Returns
+/// **Box\<dyn Scheduler\>**, Leitner if the stack owner chose it, otherwise the scheduler of the user
+pub fn for_stack(user: &models::User, stack: &models::StackFull) -> Box<dyn Scheduler> {
+    match stack.scheduler.as_str() {
+        SCHEDULER_LEITNER => Box::new(leitner::Leitner::from_intervals_str(&stack.leitner_intervals)),
+        _ => for_user(user),
+    }
+}
+
+/// Grades go from 0 (complete blackout) to 5 (perfect response)
+pub fn is_valid_grade(grade: u8) -> bool {
+    grade <= 5
+}
+
+/// ## Picks cards that should be reviewed now.
+///
+/// Cards without a state were never reviewed by the user, so they are always due.
+/// Overdue cards come first (most overdue at the front), new cards are appended after them.
+/// Suspended cards and cards buried until later are left out.
+///
+/// ### Arguments
+///
+/// * `scheduler` - &dyn Scheduler
+/// * `cards` - Vec\<T\>, cards or sub-cards
+/// * `states` - &[models::CardState], states of the studying user
+/// * `now` - i64, unix timestamp
+pub fn due_cards<T: Reviewable>(
+    scheduler: &dyn Scheduler,
+    cards: Vec<T>,
+    states: &[models::CardState],
+    now: i64,
+) -> Vec<T> {
+    let (reviews, new_cards) = split_due_cards(scheduler, cards, states, now);
+    reviews.into_iter().chain(new_cards).collect()
+}
+
+/// ## Same as `due_cards` but keeps reviews and new cards apart.
+///
+/// ### Returns
+/// **(reviews, new cards)**
+pub fn split_due_cards<T: Reviewable>(
+    scheduler: &dyn Scheduler,
+    cards: Vec<T>,
+    states: &[models::CardState],
+    now: i64,
+) -> (Vec<T>, Vec<T>) {
+    split_due_cards_by(|_| scheduler, cards, states, now)
+}
+
+/// ## Same as `split_due_cards` for cards that don't share a scheduler, ex. cards of several stacks.
+///
+/// ### Arguments
+///
+/// * `scheduler_of` - Fn(&T) -> &dyn Scheduler, scheduler that judges if a card is due
+/// * `cards` - Vec\<T\>, cards or sub-cards
+/// * `states` - &[models::CardState], states of the studying user
+/// * `now` - i64, unix timestamp
+///
+/// ### Returns
+/// **(reviews, new cards)**
+pub fn split_due_cards_by<'a, T: Reviewable>(
+    scheduler_of: impl Fn(&T) -> &'a dyn Scheduler,
+    cards: Vec<T>,
+    states: &[models::CardState],
+    now: i64,
+) -> (Vec<T>, Vec<T>) {
+    let states: HashMap<(&str, i32, &str), &models::CardState> = states.iter()
+        .map(|state| ((state.card_id.as_str(), state.ordinal, state.direction.as_str()), state))
+        .collect();
+
+    let mut reviews: Vec<(i64, T)> = Vec::new();
+    let mut new_cards: Vec<T> = Vec::new();
+
+    for card in cards {
+        match states.get(&(card.card_id(), card.ordinal(), card.direction())) {
+            Some(state) if state.suspended || state.buried_until > now => {}
+            Some(state) if state.phase == steps::PHASE_NEW => new_cards.push(card),
+            Some(state) if scheduler_of(&card).is_due(state, now) => reviews.push((state.due, card)),
+            Some(_) => {}
+            None => new_cards.push(card),
+        }
+    }
+
+    reviews.sort_by_key(|(due, _)| *due);
+    (reviews.into_iter().map(|(_, card)| card).collect(), new_cards)
+}
+
+/// ## Builds a study queue out of due reviews and new cards.
+///
+/// Both lists are cut down to the limits and new cards are spread evenly between the reviews.
+///
+/// ### Arguments
+///
+/// * `reviews` - Vec\<T\>
+/// * `new_cards` - Vec\<T\>
+/// * `review_limit` - usize, reviews left for today
+/// * `new_limit` - usize, new cards left for today
+pub fn build_queue<T>(
+    reviews: Vec<T>,
+    new_cards: Vec<T>,
+    review_limit: usize,
+    new_limit: usize,
+) -> Vec<T> {
+    let reviews: Vec<T> = reviews.into_iter().take(review_limit).collect();
+    let new_cards: Vec<T> = new_cards.into_iter().take(new_limit).collect();
+
+    let new_len = new_cards.len();
+    let total = reviews.len() + new_len;
+    let mut reviews = reviews.into_iter();
+    let mut new_cards = new_cards.into_iter();
+
+    let mut queue = Vec::with_capacity(total);
+    let mut placed_new = 0;
+    for i in 0..total {
+        // Place a new card every time the ideal count of them up to this point goes up
+        let next = if (i + 1) * new_len / total > placed_new {
+            placed_new += 1;
+            new_cards.next()
+        } else {
+            reviews.next()
+        };
+        queue.extend(next);
+    }
+    queue
+}
@@ -0,0 +1,34 @@
+//! ## SuperMemo 2 algorithm
+//!
+//! https://super-memory.com/english/ol/sm2.htm
+
+use crate::models;
+use super::{Scheduler, SECONDS_IN_DAY};
+
+pub const DEFAULT_EASE_FACTOR: f64 = 2.5;
+pub const MIN_EASE_FACTOR: f64 = 1.3;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sm2;
+
+impl Scheduler for Sm2 {
+    fn review(&self, state: &mut models::CardState, grade: u8, now: i64) {
+        if grade >= 3 {
+            state.interval_days = match state.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (state.interval_days as f64 * state.ease_factor).round() as i32,
+            };
+            state.repetitions += 1;
+        } else {
+            // Failed cards start over but keep their ease factor
+            state.repetitions = 0;
+            state.interval_days = 1;
+        }
+
+        let q = 5.0 - grade as f64;
+        state.ease_factor = (state.ease_factor + (0.1 - q * (0.08 + q * 0.02))).max(MIN_EASE_FACTOR);
+        state.last_review = now;
+        state.due = now + state.interval_days as i64 * SECONDS_IN_DAY;
+    }
+}
@@ -0,0 +1,189 @@
+//! ## Learning and relearning steps
+//!
+//! New cards go through short learning steps before the long term scheduler takes over,
+//! forgotten cards go through relearning steps before they come back into reviews.
+//! Steps are written as a comma separated list of durations, ex. `1m,10m,1d`.
+
+use crate::models;
+use super::{Scheduler, sm2, SECONDS_IN_DAY};
+
+pub const PHASE_NEW: &str = "new";
+pub const PHASE_LEARNING: &str = "learning";
+pub const PHASE_REVIEW: &str = "review";
+pub const PHASE_RELEARNING: &str = "relearning";
+
+pub const DEFAULT_LEARNING_STEPS: &str = "1m,10m";
+pub const DEFAULT_RELEARNING_STEPS: &str = "10m";
+
+/// Subtracted from the ease factor every time a card in review is forgotten
+pub const LAPSE_EASE_PENALTY: f64 = 0.2;
+
+/// Number of lapses after which a card becomes a leech
+pub const DEFAULT_LEECH_THRESHOLD: i32 = 8;
+
+const MAX_STEPS: usize = 10;
+const MAX_STEP_SECONDS: i64 = 30 * SECONDS_IN_DAY;
+
+pub struct LearningSteps {
+    inner: Box<dyn Scheduler>,
+    learning: Vec<i64>,
+    relearning: Vec<i64>,
+    leech_threshold: i32,
+    leech_suspend: bool,
+}
+
+impl LearningSteps {
+    pub fn new(inner: Box<dyn Scheduler>, learning: Vec<i64>, relearning: Vec<i64>) -> Self {
+        LearningSteps {
+            inner,
+            learning,
+            relearning,
+            leech_threshold: DEFAULT_LEECH_THRESHOLD,
+            leech_suspend: false,
+        }
+    }
+
+    /// Cards with at least `threshold` lapses get flagged as leeches and optionally suspended
+    pub fn with_leeches(mut self, threshold: i32, suspend: bool) -> Self {
+        self.leech_threshold = threshold;
+        self.leech_suspend = suspend;
+        self
+    }
+
+    /// Steps and leech settings are configured by the owner of the stack, everyone else studies it with the defaults
+    pub fn for_stack(inner: Box<dyn Scheduler>, stack: &models::StackFull, user_id: &str) -> Self {
+        if stack.owner_id != user_id {
+            return LearningSteps::new(
+                inner,
+                parse_steps(DEFAULT_LEARNING_STEPS).unwrap_or_default(),
+                parse_steps(DEFAULT_RELEARNING_STEPS).unwrap_or_default(),
+            );
+        }
+
+        LearningSteps::new(
+            inner,
+            parse_steps(&stack.learning_steps).unwrap_or_default(),
+            parse_steps(&stack.relearning_steps).unwrap_or_default(),
+        ).with_leeches(stack.leech_threshold, stack.leech_suspend)
+    }
+
+    /// Moves the card through the steps, returns true once it's done with them
+    fn advance(state: &mut models::CardState, grade: u8, now: i64, steps: &[i64]) -> bool {
+        if grade == 5 {
+            // Easy skips the remaining steps
+            return true;
+        }
+
+        if grade < 3 {
+            state.step = 0;
+        } else {
+            state.step += 1;
+        }
+
+        match steps.get(state.step as usize) {
+            Some(delay) => {
+                state.due = now + delay;
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl Scheduler for LearningSteps {
+    fn review(&self, state: &mut models::CardState, grade: u8, now: i64) {
+        match state.phase.as_str() {
+            PHASE_REVIEW => {
+                self.inner.review(state, grade, now);
+
+                if grade < 3 {
+                    state.lapses += 1;
+                    state.ease_factor = (state.ease_factor - LAPSE_EASE_PENALTY).max(sm2::MIN_EASE_FACTOR);
+
+                    if state.lapses >= self.leech_threshold {
+                        state.leech = true;
+                        state.suspended = state.suspended || self.leech_suspend;
+                    }
+
+                    // Interval set by the inner scheduler is used once relearning is done
+                    if let Some(delay) = self.relearning.first() {
+                        state.phase = PHASE_RELEARNING.to_owned();
+                        state.step = 0;
+                        state.due = now + delay;
+                    }
+                }
+            }
+
+            PHASE_RELEARNING => {
+                if LearningSteps::advance(state, grade, now, &self.relearning) {
+                    state.phase = PHASE_REVIEW.to_owned();
+                    state.step = 0;
+                    state.due = now + state.interval_days as i64 * SECONDS_IN_DAY;
+                }
+            }
+
+            // New and learning cards
+            _ => {
+                if state.phase == PHASE_NEW {
+                    state.phase = PHASE_LEARNING.to_owned();
+                    state.step = 0;
+                }
+
+                if LearningSteps::advance(state, grade, now, &self.learning) {
+                    // First review that counts for the long term schedule
+                    self.inner.review(state, grade, now);
+                    state.phase = PHASE_REVIEW.to_owned();
+                    state.step = 0;
+                }
+            }
+        }
+
+        state.last_review = now;
+    }
+
+    fn is_due(&self, state: &models::CardState, now: i64) -> bool {
+        self.inner.is_due(state, now)
+    }
+}
+
+/// Tells if the card is waiting for a step that is short enough to be repeated in the same session
+pub fn is_in_short_step(state: &models::CardState, now: i64) -> bool {
+    (state.phase == PHASE_LEARNING || state.phase == PHASE_RELEARNING) && state.due - now < SECONDS_IN_DAY
+}
+
+/// ## Parses a list of steps.
+///
+/// ### Arguments
+///
+/// * `steps` - &str, ex. `1m,10m,1d`, units are s, m, h and d
+///
+/// ### Returns
+/// Step durations in seconds or None if the list is invalid, empty string means no steps
+pub fn parse_steps(steps: &str) -> Option<Vec<i64>> {
+    if steps.trim().is_empty() {
+        return Some(Vec::new());
+    }
+
+    let parsed = steps.split(',').map(|part| {
+        let part = part.trim();
+        let unit = match part.chars().last()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => SECONDS_IN_DAY,
+            _ => return None,
+        };
+        let value = part[..part.len() - 1].parse::<i64>().ok()?;
+        value.checked_mul(unit).filter(|seconds| (1..=MAX_STEP_SECONDS).contains(seconds))
+    }).collect::<Option<Vec<i64>>>()?;
+
+    if parsed.len() > MAX_STEPS {
+        return None;
+    }
+    Some(parsed)
+}
+
+/// Formats steps the same way for storing, ex. ` 1m, 10m` becomes `1m,10m`
+pub fn format_steps(steps: &str) -> String {
+    steps.split(',').map(|part| part.trim()).filter(|part| ! part.is_empty()).collect::<Vec<&str>>().join(",")
+}
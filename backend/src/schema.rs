@@ -6,6 +6,10 @@ table! {
         password_hash -> Varchar,
         date_of_registration -> BigInt,
         country -> Varchar,
+        scheduler -> Varchar,
+        fsrs_parameters -> Varchar,
+        new_cards_per_day -> Integer,
+        reviews_per_day -> Integer,
     }
 }
 
@@ -17,6 +21,15 @@ table! {
         visibility -> Bool,
         cards_count -> Integer,
         tags -> Varchar,
+        learning_steps -> Varchar,
+        relearning_steps -> Varchar,
+        leech_threshold -> Integer,
+        leech_suspend -> Bool,
+        scheduler -> VarChar,
+        leitner_intervals -> VarChar,
+        directions -> VarChar,
+        upstream_id -> Nullable<VarChar>,
+        forks_count -> Integer,
     }
 }
 
@@ -28,7 +41,144 @@ table! {
         stack_id -> Varchar,
         frontside -> Text,
         backside -> Text,
+        card_type -> VarChar,
+        directions -> VarChar,
+        note_type_id -> VarChar,
+        fields -> Text,
     }
 }
 
 joinable!(cards -> stacks (stack_id));
+
+table! {
+    note_types (unique_id) {
+        unique_id -> VarChar,
+        stack_id -> VarChar,
+        name -> VarChar,
+        fields -> Text,
+        templates -> Text,
+    }
+}
+
+joinable!(note_types -> stacks (stack_id));
+
+
+table! {
+    card_states (user_id, card_id, ordinal, direction) {
+        user_id -> VarChar,
+        card_id -> VarChar,
+        ease_factor -> Double,
+        interval_days -> Integer,
+        repetitions -> Integer,
+        due -> BigInt,
+        stability -> Double,
+        difficulty -> Double,
+        last_review -> BigInt,
+        phase -> VarChar,
+        step -> Integer,
+        lapses -> Integer,
+        leech -> Bool,
+        suspended -> Bool,
+        buried_until -> BigInt,
+        ordinal -> Integer,
+        direction -> VarChar,
+    }
+}
+
+joinable!(card_states -> cards (card_id));
+
+table! {
+    review_log (unique_id) {
+        unique_id -> BigInt,
+        user_id -> VarChar,
+        card_id -> VarChar,
+        stack_id -> VarChar,
+        reviewed_at -> BigInt,
+        grade -> Integer,
+        previous_interval -> Integer,
+        next_interval -> Integer,
+        time_taken -> Integer,
+        phase -> VarChar,
+        ordinal -> Integer,
+        direction -> VarChar,
+    }
+}
+
+table! {
+    card_lineage (stack_id, upstream_card_id) {
+        stack_id -> VarChar,
+        upstream_card_id -> VarChar,
+        card_id -> Nullable<VarChar>,
+        base -> Text,
+    }
+}
+
+table! {
+    stack_members (stack_id, user_id) {
+        stack_id -> VarChar,
+        user_id -> VarChar,
+        role -> VarChar,
+        accepted -> Bool,
+        invited_at -> BigInt,
+    }
+}
+
+table! {
+    share_tokens (token) {
+        token -> VarChar,
+        stack_id -> VarChar,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    suggestions (unique_id) {
+        unique_id -> VarChar,
+        stack_id -> VarChar,
+        author_id -> VarChar,
+        kind -> VarChar,
+        card_id -> Nullable<VarChar>,
+        frontside -> Text,
+        backside -> Text,
+        card_type -> VarChar,
+        status -> VarChar,
+        created_at -> BigInt,
+        resolved_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    study_sessions (unique_id) {
+        unique_id -> VarChar,
+        user_id -> VarChar,
+        stack_id -> Nullable<VarChar>,
+        started_at -> BigInt,
+        finished_at -> Nullable<BigInt>,
+        queue -> Text,
+        new_count -> Integer,
+        position -> Integer,
+        answered_count -> Integer,
+        correct_count -> Integer,
+        mode -> VarChar,
+    }
+}
+
+table! {
+    quiz_attempts (unique_id) {
+        unique_id -> VarChar,
+        user_id -> VarChar,
+        stack_id -> VarChar,
+        seed -> Unsigned<Integer>,
+        questions -> Text,
+        answers -> Nullable<Text>,
+        score -> Nullable<Integer>,
+        question_count -> Integer,
+        kind -> VarChar,
+        max_score -> Integer,
+        created_at -> BigInt,
+        submitted_at -> Nullable<BigInt>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(cards, card_states);
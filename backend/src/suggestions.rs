@@ -0,0 +1,213 @@
+//! ## Edit suggestions
+//!
+//! Users can suggest new cards, corrections of the frontside and backside of a card or the deletion of a card
+//! in public stacks of other users. The owner accepts or rejects them, accepted ones change the stack like the owner's own edits would.
+
+use crate::answers::DiffSegment;
+use crate::members::{self, Permission};
+use crate::{cloze, models, notes};
+
+pub const KIND_NEW: &str = "new";
+pub const KIND_EDIT: &str = "edit";
+pub const KIND_DELETE: &str = "delete";
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_ACCEPTED: &str = "accepted";
+pub const STATUS_REJECTED: &str = "rejected";
+
+pub const DIFF_SAME: &str = "same";
+/// Only in the suggested text
+pub const DIFF_ADDED: &str = "added";
+/// Only in the current text
+pub const DIFF_REMOVED: &str = "removed";
+
+pub fn is_valid_kind(kind: &str) -> bool {
+    matches!(kind, KIND_NEW | KIND_EDIT | KIND_DELETE)
+}
+
+/// ## Tells if a user can suggest changes to a stack.
+///
+/// Suggestions are for public stacks of other users, the owner and editors change the stack directly.
+///
+/// ### Arguments
+///
+/// * `stack` - &models::StackFull
+/// * `role` - Option<&str>, role of the user in the stack, see `members::role`
+/// * `user_id` - &str
+pub fn can_suggest(stack: &models::StackFull, role: Option<&str>, user_id: &str) -> bool {
+    stack.visibility && stack.owner_id != user_id && ! members::allows(stack, role, Permission::Edit)
+}
+
+fn new_card(stack_id: &str, card_type: &str) -> models::Card {
+    models::Card {
+        unique_id: String::new(),
+        stack_id: stack_id.to_owned(),
+        frontside: String::new(),
+        backside: String::new(),
+        card_type: card_type.to_owned(),
+        directions: String::new(),
+        note_type_id: String::new(),
+        fields: String::new(),
+    }
+}
+
+/// Sides of a card of the built-in Basic note type, checked like create_card and update_card check them
+fn set_sides(card: &mut models::Card, frontside: String, backside: String) -> Result<(), &'static str> {
+    card.frontside = frontside;
+    card.backside = backside;
+    card.note_type_id = String::new();
+    card.fields = String::new();
+    card.check_basic()
+}
+
+/// ## Card the way a new suggestion would leave it.
+///
+/// Deletions carry no content, new cards need both sides and edits may leave out the ones they don't change.
+///
+/// ### Arguments
+///
+/// * `kind` - &str, one of the `KIND_` kinds
+/// * `stack_id` - &str
+/// * `current` - Option<&models::Card>, card an edit or deletion is for, of the same stack
+/// * `frontside`, `backside`, `card_type` - Option<String>, suggested content
+///
+/// ### Returns
+/// The card with the suggested content or why it can't be suggested
+pub fn suggested_card(
+    kind: &str,
+    stack_id: &str,
+    current: Option<&models::Card>,
+    frontside: Option<String>,
+    backside: Option<String>,
+    card_type: Option<String>,
+) -> Result<models::Card, &'static str> {
+    let mut card = match (kind, current) {
+        (KIND_NEW, None) => new_card(stack_id, cloze::CARD_TYPE_BASIC),
+        (KIND_EDIT | KIND_DELETE, Some(value)) => value.clone(),
+        _ => return Err("only edits and deletions are for an existing card"),
+    };
+
+    if kind == KIND_DELETE {
+        if frontside.is_some() || backside.is_some() || card_type.is_some() {
+            return Err("deletions have no content")
+        }
+        card.frontside = String::new();
+        card.backside = String::new();
+        return Ok(card)
+    }
+
+    // Only cards of the built-in Basic note type have a frontside and backside to correct
+    if card.note_type_id != notes::BASIC_NOTE_TYPE_ID {
+        return Err("cards of note types can't be corrected")
+    }
+    if current.is_none() && (frontside.is_none() || backside.is_none()) {
+        return Err("new cards need a frontside and backside")
+    }
+
+    if let Some(value) = card_type {
+        card.card_type = value;
+    }
+    let frontside = frontside.unwrap_or(card.frontside.clone());
+    let backside = backside.unwrap_or(card.backside.clone());
+    set_sides(&mut card, frontside, backside)?;
+
+    if current.is_some_and(|value| value.frontside == card.frontside && value.backside == card.backside && value.card_type == card.card_type) {
+        return Err("edit changes nothing")
+    }
+    Ok(card)
+}
+
+/// ## Card to store when a suggestion is accepted, checked like create_card and update_card check cards.
+///
+/// ### Arguments
+///
+/// * `suggestion` - &models::Suggestion
+/// * `current` - Option<models::Card>, card the suggestion is for if it still exists
+///
+/// ### Returns
+/// The new or changed card (new ones without an id), None when the card is deleted, or why the suggestion can't be applied
+pub fn accepted_card(suggestion: &models::Suggestion, current: Option<models::Card>) -> Result<Option<models::Card>, &'static str> {
+    match (suggestion.kind.as_str(), current) {
+        (KIND_NEW, _) => {
+            let mut card = new_card(&suggestion.stack_id, &suggestion.card_type);
+            set_sides(&mut card, suggestion.frontside.clone(), suggestion.backside.clone())?;
+            Ok(Some(card))
+        }
+        (KIND_EDIT, Some(mut card)) => {
+            // The owner may have moved the card to another note type in the meantime
+            if card.note_type_id != notes::BASIC_NOTE_TYPE_ID {
+                return Err("card has a note type by now")
+            }
+            card.card_type = suggestion.card_type.clone();
+            set_sides(&mut card, suggestion.frontside.clone(), suggestion.backside.clone())?;
+            Ok(Some(card))
+        }
+        (KIND_DELETE, Some(_)) => Ok(None),
+        _ => Err("card was deleted"),
+    }
+}
+
+/// Marks a pending suggestion as accepted or rejected
+pub fn resolve(suggestion: &mut models::Suggestion, status: &str, now: i64) {
+    suggestion.status = status.to_owned();
+    suggestion.resolved_at = Some(now);
+}
+
+/// Words and the whitespace between them, so a diff keeps the text as it is
+fn tokens(text: &str) -> Vec<&str> {
+    let mut output: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut whitespace: Option<bool> = None;
+    for (i, c) in text.char_indices() {
+        if whitespace.is_some_and(|value| value != c.is_whitespace()) {
+            output.push(&text[start..i]);
+            start = i;
+        }
+        whitespace = Some(c.is_whitespace());
+    }
+    if start < text.len() {
+        output.push(&text[start..]);
+    }
+    output
+}
+
+/// ## Word diff of the current and the suggested text, based on the longest common subsequence.
+///
+/// Neighbouring words of the same kind are merged, joining the texts of `same` and `removed` segments gives the current text
+/// and joining the ones of `same` and `added` segments the suggested text.
+pub fn diff(current: &str, suggested: &str) -> Vec<DiffSegment> {
+    let (current, suggested) = (tokens(current), tokens(suggested));
+
+    // lcs[i][j] - longest common subsequence of current[i..] and suggested[j..]
+    let mut lcs = vec![vec![0usize; suggested.len() + 1]; current.len() + 1];
+    for i in (0..current.len()).rev() {
+        for j in (0..suggested.len()).rev() {
+            lcs[i][j] = if current[i] == suggested[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut output: Vec<DiffSegment> = Vec::new();
+    let mut push = |kind: &'static str, text: &str| match output.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(text),
+        _ => output.push(DiffSegment { kind, text: text.to_owned() }),
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < current.len() || j < suggested.len() {
+        if i < current.len() && j < suggested.len() && current[i] == suggested[j] {
+            push(DIFF_SAME, current[i]);
+            i += 1;
+            j += 1;
+        } else if j < suggested.len() && (i == current.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            push(DIFF_ADDED, suggested[j]);
+            j += 1;
+        } else {
+            push(DIFF_REMOVED, current[i]);
+            i += 1;
+        }
+    }
+    output
+}
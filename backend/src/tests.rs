@@ -1,5 +1,28 @@
 #[cfg(test)]
 mod tests {
+    /// Private stack with the default study settings, tests change what they need
+    fn test_stack(owner_id: &str) -> crate::models::StackFull {
+        use crate::{models, scheduler};
+
+        models::StackFull {
+            unique_id: "stack".to_owned(),
+            owner_id: owner_id.to_owned(),
+            name: "German".to_owned(),
+            visibility: false,
+            cards_count: 0,
+            tags: String::new(),
+            learning_steps: scheduler::steps::DEFAULT_LEARNING_STEPS.to_owned(),
+            relearning_steps: scheduler::steps::DEFAULT_RELEARNING_STEPS.to_owned(),
+            leech_threshold: scheduler::steps::DEFAULT_LEECH_THRESHOLD,
+            leech_suspend: false,
+            scheduler: String::new(),
+            leitner_intervals: scheduler::leitner::DEFAULT_INTERVALS.to_owned(),
+            directions: scheduler::DIRECTION_FORWARD.to_owned(),
+            upstream_id: None,
+            forks_count: 0,
+        }
+    }
+
     #[test]
     fn add_ints() {
         assert_eq!(2 + 2, 4);
@@ -38,6 +61,1506 @@ mod tests {
         let parsed_hash = PasswordHash::new(&password_hash).unwrap();
         assert!(Argon2::default().verify_password(password, &parsed_hash).is_ok());
     }
+
+    #[test]
+    fn sm2_review_sequence() {
+        use crate::{models, scheduler::{self, sm2, Scheduler}};
+
+        let mut state = models::CardState::new("user", "card", 0);
+
+        sm2::Sm2.review(&mut state, 5, 0);
+        assert_eq!((state.repetitions, state.interval_days), (1, 1));
+        sm2::Sm2.review(&mut state, 4, 0);
+        assert_eq!((state.repetitions, state.interval_days), (2, 6));
+        sm2::Sm2.review(&mut state, 4, 0);
+        assert_eq!((state.repetitions, state.interval_days), (3, 16));
+        assert_eq!(state.due, 16 * scheduler::SECONDS_IN_DAY);
+
+        // Lapse resets the repetitions, ease factor never drops below the minimum
+        for _ in 0..10 {
+            sm2::Sm2.review(&mut state, 0, 0);
+        }
+        assert_eq!((state.repetitions, state.interval_days), (0, 1));
+        assert_eq!(state.ease_factor, sm2::MIN_EASE_FACTOR);
+    }
+
+    #[test]
+    fn due_cards_order() {
+        use crate::{models, scheduler};
+
+        let card = |id: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: String::new(),
+            backside: String::new(),
+            card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let state = |id: &str, due: i64| models::CardState {
+            due,
+            phase: scheduler::steps::PHASE_REVIEW.to_owned(),
+            ..models::CardState::new("user", id, 0)
+        };
+
+        let cards = vec![card("new"), card("later"), card("due"), card("overdue")];
+        let states = vec![state("later", 200), state("due", 100), state("overdue", 50)];
+
+        let due: Vec<String> = scheduler::due_cards(&scheduler::sm2::Sm2, cards, &states, 100)
+            .into_iter().map(|card| card.unique_id).collect();
+        assert_eq!(due, vec!["overdue", "due", "new"]);
+
+        // Cards of another stack are judged by that stack's scheduler
+        struct NeverDue;
+        impl scheduler::Scheduler for NeverDue {
+            fn review(&self, _state: &mut models::CardState, _grade: u8, _now: i64) {}
+            fn is_due(&self, _state: &models::CardState, _now: i64) -> bool { false }
+        }
+        let mut cards = vec![card("due"), card("overdue")];
+        cards[1].stack_id = "other".to_owned();
+        let (reviews, _) = scheduler::split_due_cards_by(
+            |card: &models::Card| -> &dyn scheduler::Scheduler {
+                if card.stack_id == "other" { &NeverDue } else { &scheduler::sm2::Sm2 }
+            },
+            cards, &states, 100,
+        );
+        let reviews: Vec<String> = reviews.into_iter().map(|card| card.unique_id).collect();
+        assert_eq!(reviews, vec!["due"]);
+    }
+
+    #[test]
+    fn learning_steps() {
+        use crate::{models, scheduler::{sm2, steps, Scheduler, SECONDS_IN_DAY}};
+
+        assert_eq!(steps::parse_steps("1m, 10m,1d"), Some(vec![60, 600, SECONDS_IN_DAY]));
+        assert_eq!(steps::parse_steps(""), Some(vec![]));
+        assert_eq!(steps::parse_steps("10x"), None);
+        assert_eq!(steps::parse_steps("0m"), None);
+
+        let scheduler = steps::LearningSteps::new(Box::new(sm2::Sm2), vec![60, 600], vec![600]);
+        let mut state = models::CardState::new("user", "card", 0);
+
+        // Again restarts the steps, good moves on, after the last step the card graduates
+        scheduler.review(&mut state, 1, 0);
+        assert_eq!((state.phase.as_str(), state.step, state.due), (steps::PHASE_LEARNING, 0, 60));
+        scheduler.review(&mut state, 4, 60);
+        assert_eq!((state.phase.as_str(), state.step, state.due), (steps::PHASE_LEARNING, 1, 660));
+        scheduler.review(&mut state, 4, 660);
+        assert_eq!(state.phase, steps::PHASE_REVIEW);
+        assert_eq!(state.due, 660 + SECONDS_IN_DAY);
+
+        // Lapse goes into relearning with an ease penalty
+        let ease_factor = state.ease_factor;
+        scheduler.review(&mut state, 0, SECONDS_IN_DAY);
+        assert_eq!((state.phase.as_str(), state.lapses), (steps::PHASE_RELEARNING, 1));
+        assert!(state.ease_factor < ease_factor);
+        assert_eq!(state.due, SECONDS_IN_DAY + 600);
+
+        scheduler.review(&mut state, 4, SECONDS_IN_DAY + 600);
+        assert_eq!(state.phase, steps::PHASE_REVIEW);
+        assert_eq!(state.due, SECONDS_IN_DAY + 600 + state.interval_days as i64 * SECONDS_IN_DAY);
+    }
+
+    #[test]
+    fn leech_suspension() {
+        use crate::{models, scheduler::{self, sm2, steps, Scheduler}};
+
+        let scheduler = steps::LearningSteps::new(Box::new(sm2::Sm2), vec![], vec![]).with_leeches(3, true);
+        let mut state = models::CardState::new("user", "card", 0);
+        scheduler.review(&mut state, 5, 0);
+
+        // Every lapse counts, the third one makes the card a leech and suspends it
+        for lapse in 1..=3 {
+            assert!(! state.leech && ! state.suspended);
+            let now = state.due;
+            scheduler.review(&mut state, 0, now);
+            assert_eq!(state.lapses, lapse);
+        }
+        assert!(state.leech && state.suspended);
+
+        // Suspended and buried cards are never due
+        let card = |id: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: String::new(),
+            backside: String::new(),
+            card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let buried = models::CardState { buried_until: 200, ..models::CardState::new("user", "buried", 0) };
+        let due: Vec<String> = scheduler::due_cards(&sm2::Sm2, vec![card("card"), card("buried"), card("new")], &[state, buried], 100)
+            .into_iter().map(|card| card.unique_id).collect();
+        assert_eq!(due, vec!["new"]);
+    }
+
+    #[test]
+    fn study_queue_limits() {
+        use crate::{models, scheduler};
+
+        let cards = |prefix: &str, count: usize| -> Vec<models::Card> {
+            (0..count).map(|i| models::Card {
+                unique_id: format!("{}{}", prefix, i),
+                stack_id: "stack".to_owned(),
+                frontside: String::new(),
+                backside: String::new(),
+                card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+                directions: String::new(),
+                note_type_id: String::new(),
+                fields: String::new(),
+            }).collect()
+        };
+
+        let queue: Vec<String> = scheduler::build_queue(cards("r", 10), cards("n", 5), 6, 2)
+            .into_iter().map(|card| card.unique_id).collect();
+        assert_eq!(queue, vec!["r0", "r1", "r2", "n0", "r3", "r4", "r5", "n1"]);
+
+        assert_eq!(scheduler::build_queue(cards("r", 3), cards("n", 3), 0, 10).len(), 3);
+        assert!(scheduler::build_queue(cards("r", 3), Vec::new(), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn leitner_boxes() {
+        use crate::{models, scheduler::{leitner, Scheduler, SECONDS_IN_DAY}};
+
+        assert_eq!(leitner::parse_intervals("1, 3,7"), Some(vec![1, 3, 7]));
+        assert_eq!(leitner::parse_intervals("1"), None);
+        assert_eq!(leitner::parse_intervals("1,0"), None);
+
+        let leitner = leitner::Leitner::from_intervals_str("1,3,7");
+        let mut state = models::CardState::new("user", "card", 0);
+
+        // Correct answers move the card up until the last box, a wrong one sends it back
+        for expected in [3, 7, 7] {
+            leitner.review(&mut state, 4, 0);
+            assert_eq!(state.interval_days, expected);
+        }
+        leitner.review(&mut state, 2, 0);
+        assert_eq!((state.repetitions, state.interval_days, state.due), (0, 1, SECONDS_IN_DAY));
+    }
+
+    #[test]
+    fn answer_checking() {
+        use crate::answers;
+
+        assert_eq!(answers::normalize("  Zażółć   GĘŚLĄ jaźń! "), "zazolc gesla jazn");
+
+        let check = answers::check_answer("Don't stop", "dont  STOP!");
+        assert!(check.correct);
+        assert_eq!((check.similarity, check.suggested_grade), (1.0, 5));
+
+        // One typo in ten characters
+        let check = answers::check_answer("necessary", "neccessary");
+        assert!(! check.correct);
+        assert_eq!(check.suggested_grade, 4);
+        let diff: Vec<(&str, &str)> = check.diff.iter().map(|segment| (segment.kind, segment.text.as_str())).collect();
+        assert_eq!(diff, vec![
+            (answers::DIFF_MATCH, "nec"),
+            (answers::DIFF_EXTRA, "c"),
+            (answers::DIFF_MATCH, "essary"),
+        ]);
+
+        let check = answers::check_answer("photograph", "fotograf");
+        let diff: Vec<(&str, &str)> = check.diff.iter().map(|segment| (segment.kind, segment.text.as_str())).collect();
+        assert_eq!(diff, vec![
+            (answers::DIFF_EXTRA, "f"),
+            (answers::DIFF_MISSING, "ph"),
+            (answers::DIFF_MATCH, "otogra"),
+            (answers::DIFF_EXTRA, "f"),
+            (answers::DIFF_MISSING, "ph"),
+        ]);
+
+        assert_eq!(answers::check_answer("cat", "").suggested_grade, 0);
+    }
+
+    #[test]
+    fn quiz_generation() {
+        use crate::{models, quiz};
+
+        let card = |id: &str, backside: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: format!("Question {}", id),
+            backside: backside.to_owned(),
+            card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let cards = [
+            card("a", "1848"), card("b", "1914"), card("c", "1939"), card("d", "1989"),
+            card("e", "Napoleon"), card("f", "The treaty of Versailles"), card("g", "1914"),
+        ];
+        let cards: Vec<models::SubCard> = cards.iter().flat_map(|card| card.sub_cards(&models::CardLayout { directions: crate::scheduler::DIRECTION_FORWARD, note_types: &[] })).collect();
+
+        let questions = quiz::generate_questions(&cards, 5, 7);
+        assert_eq!(questions, quiz::generate_questions(&cards, 5, 7));
+        assert_eq!(questions.len(), 5);
+
+        for question in &questions {
+            let card = cards.iter().find(|card| card.card_id == question.card_id).unwrap();
+            assert_eq!(question.options[question.correct], card.answer);
+            assert_eq!(question.options.len(), quiz::OPTIONS_COUNT);
+            // Options are distinct and never repeat the correct answer
+            assert_eq!(question.options.iter().filter(|option| **option == card.answer).count(), 1);
+        }
+
+        // Years get other years as distractors
+        for question in questions.iter().filter(|question| question.options[question.correct].parse::<i32>().is_ok()) {
+            assert!(question.options.iter().all(|option| option.parse::<i32>().is_ok()));
+        }
+
+        let selected: Vec<i32> = questions.iter().map(|question| question.correct as i32).collect();
+        assert_eq!(quiz::grade(&questions, &selected), vec![true; 5]);
+        assert_eq!(quiz::grade(&questions, &[-1]), vec![false; 5]);
+
+        // Answers are stored comma separated
+        assert_eq!(quiz::selected_to_string(&[2, 0, -1, 3]), "2,0,-1,3");
+        assert_eq!(quiz::parse_selected("2,0,-1,3"), vec![2, 0, -1, 3]);
+        assert_eq!(quiz::parse_selected(""), Vec::<i32>::new());
+        assert_eq!(quiz::parse_selected(&quiz::selected_to_string(&selected)), selected);
+    }
+
+    #[test]
+    fn test_generation() {
+        use crate::{models, quiz::{self, TestAnswer, TestQuestion}};
+
+        let cards: Vec<models::Card> = (0..12).map(|i| models::Card {
+            unique_id: format!("card{}", i),
+            stack_id: "stack".to_owned(),
+            frontside: format!("Question {}", i),
+            backside: format!("Answer {}", i),
+            card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        }).collect();
+        let cards: Vec<models::SubCard> = cards.iter().flat_map(|card| card.sub_cards(&models::CardLayout { directions: crate::scheduler::DIRECTION_FORWARD, note_types: &[] })).collect();
+
+        let questions = quiz::generate_test(&cards, 6, 3);
+        assert_eq!(questions, quiz::generate_test(&cards, 6, 3));
+
+        // Types take turns and every card is used once
+        let types: Vec<&str> = questions.iter().map(|question| match question {
+            TestQuestion::TrueFalse { .. } => "true_false",
+            TestQuestion::Written { .. } => "written",
+            TestQuestion::Matching { .. } => "matching",
+        }).collect();
+        assert_eq!(types, vec!["true_false", "written", "matching", "true_false", "written", "matching"]);
+        let used: usize = questions.iter().map(|question| question.max_points() as usize).sum();
+        assert_eq!(used, 12);
+
+        // Perfect answers get every point
+        let perfect: Vec<Option<TestAnswer>> = questions.iter().map(|question| Some(match question {
+            TestQuestion::TrueFalse { correct, .. } => TestAnswer::TrueFalse(*correct),
+            TestQuestion::Written { expected, .. } => TestAnswer::Written(expected.to_lowercase()),
+            TestQuestion::Matching { correct, .. } => TestAnswer::Matching(correct.iter().map(|i| *i as i32).collect()),
+        })).collect();
+        let feedback = quiz::grade_test(&questions, &perfect);
+        assert!(feedback.iter().all(|question| question.points == question.max_points));
+
+        // Skipped and mistyped answers get none
+        let feedback = quiz::grade_test(&questions, &[None, Some(TestAnswer::TrueFalse(true))]);
+        assert!(feedback.iter().all(|question| question.points == 0));
+
+        // Answers are told apart by their JSON type
+        let parsed: Vec<Option<TestAnswer>> = serde_json::from_str(r#"[true, [1, 0], "text", null]"#).unwrap();
+        assert_eq!(parsed, vec![
+            Some(TestAnswer::TrueFalse(true)),
+            Some(TestAnswer::Matching(vec![1, 0])),
+            Some(TestAnswer::Written("text".to_owned())),
+            None,
+        ]);
+    }
+
+    #[test]
+    fn cloze_cards() {
+        use crate::{cloze, models, scheduler};
+
+        assert!(cloze::is_valid_card(cloze::CARD_TYPE_CLOZE, "{{c1::Paris}} is in {{c2::France::country}}"));
+        assert!(cloze::is_valid_card(cloze::CARD_TYPE_BASIC, "No markers"));
+        for text in ["No markers", "{{c0::Paris}}", "{{c1::}}", "{{c1::Paris}", "{{c51::Paris}}"] {
+            assert!(! cloze::is_valid_card(cloze::CARD_TYPE_CLOZE, text), "{}", text);
+        }
+        assert!(! cloze::is_valid_card("image", "{{c1::Paris}}"));
+
+        let card = models::Card {
+            unique_id: "card".to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: "{{c1::Paris}} is the capital of {{c2::France::country}}, {{c1::Lyon}} is not".to_owned(),
+            backside: "Extra".to_owned(),
+            card_type: cloze::CARD_TYPE_CLOZE.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+
+        let forward = models::CardLayout { directions: scheduler::DIRECTION_FORWARD, note_types: &[] };
+        let both = models::CardLayout { directions: scheduler::DIRECTIONS_BOTH, note_types: &[] };
+
+        // Markers with the same number are hidden together
+        let sub_cards = card.sub_cards(&both);
+        assert_eq!(sub_cards.iter().map(|sub_card| sub_card.ordinal).collect::<Vec<i32>>(), vec![1, 2]);
+        assert_eq!(sub_cards[0].frontside, "[...] is the capital of France, [...] is not");
+        assert_eq!(sub_cards[0].answer, "Paris, Lyon");
+        assert_eq!(sub_cards[1].frontside, "Paris is the capital of [country], Lyon is not");
+        assert_eq!(sub_cards[1].backside, "Paris is the capital of France, Lyon is not\n\nExtra");
+        // Cloze cards are only studied forward
+        assert_eq!(sub_cards.len(), 2);
+        assert!(card.sub_card(&both, 0, scheduler::DIRECTION_FORWARD).is_none());
+
+        // Every cloze is scheduled on its own
+        let reviewed = models::CardState {
+            ordinal: 1,
+            due: 200,
+            phase: scheduler::steps::PHASE_REVIEW.to_owned(),
+            ..models::CardState::new("user", "card", 0)
+        };
+        let due: Vec<i32> = scheduler::due_cards(&scheduler::sm2::Sm2, sub_cards, &[reviewed], 100)
+            .into_iter().map(|sub_card| sub_card.ordinal).collect();
+        assert_eq!(due, vec![2]);
+
+        let mut session = models::StudySession {
+            unique_id: "session".to_owned(),
+            user_id: "user".to_owned(),
+            stack_id: None,
+            started_at: 0,
+            finished_at: None,
+            queue: "basic,card:2,basic:0:backward".to_owned(),
+            new_count: 0,
+            position: 0,
+            answered_count: 0,
+            correct_count: 0,
+            mode: scheduler::SESSION_REVIEW.to_owned(),
+        };
+        let pushed = card.sub_card(&forward, 1, scheduler::DIRECTION_FORWARD).unwrap();
+        assert!(pushed.is_sub_card("card", 1, scheduler::DIRECTION_FORWARD));
+        assert!(!pushed.is_sub_card("card", 2, scheduler::DIRECTION_FORWARD));
+        assert!(!pushed.is_sub_card("card", 1, scheduler::DIRECTION_BACKWARD));
+        assert!(!pushed.is_sub_card("basic", 1, scheduler::DIRECTION_FORWARD));
+        session.push_queue_item(&pushed);
+        assert_eq!(session.queue_items(), vec![
+            ("basic", 0, scheduler::DIRECTION_FORWARD),
+            ("card", 2, scheduler::DIRECTION_FORWARD),
+            ("basic", 0, scheduler::DIRECTION_BACKWARD),
+            ("card", 1, scheduler::DIRECTION_FORWARD),
+        ]);
+    }
+
+    #[test]
+    fn reversible_cards() {
+        use crate::{cloze, models, scheduler};
+
+        let card = |id: &str, directions: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: "Front".to_owned(),
+            backside: "Back".to_owned(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: directions.to_owned(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+
+        let forward = models::CardLayout { directions: scheduler::DIRECTION_FORWARD, note_types: &[] };
+        let both = models::CardLayout { directions: scheduler::DIRECTIONS_BOTH, note_types: &[] };
+
+        // Cards follow the stack unless they set their own directions
+        assert_eq!(card("a", "").sub_cards(&forward).len(), 1);
+        assert_eq!(card("a", "").sub_cards(&both).len(), 2);
+        assert_eq!(card("a", scheduler::DIRECTION_FORWARD).sub_cards(&both).len(), 1);
+
+        let backward = card("a", scheduler::DIRECTIONS_BOTH)
+            .sub_card(&forward, 0, scheduler::DIRECTION_BACKWARD)
+            .unwrap();
+        assert_eq!((backward.frontside.as_str(), backward.backside.as_str(), backward.answer.as_str()), ("Back", "Front", "Front"));
+
+        // Both directions are scheduled independently
+        let cards: Vec<models::SubCard> = [card("a", ""), card("b", "")].iter()
+            .flat_map(|card| card.sub_cards(&both))
+            .collect();
+        let state = |id: &str, direction: &str| models::CardState {
+            due: 200,
+            phase: scheduler::steps::PHASE_REVIEW.to_owned(),
+            direction: direction.to_owned(),
+            ..models::CardState::new("user", id, 0)
+        };
+        let states = vec![state("a", scheduler::DIRECTION_FORWARD), state("b", scheduler::DIRECTION_BACKWARD)];
+
+        let due: Vec<(String, String)> = scheduler::due_cards(&scheduler::sm2::Sm2, cards, &states, 100)
+            .into_iter().map(|card| (card.card_id, card.direction)).collect();
+        assert_eq!(due, vec![
+            ("a".to_owned(), scheduler::DIRECTION_BACKWARD.to_owned()),
+            ("b".to_owned(), scheduler::DIRECTION_FORWARD.to_owned()),
+        ]);
+
+        assert!(scheduler::is_valid_card_directions(""));
+        assert!(! scheduler::is_valid_stack_directions(""));
+        assert!(! scheduler::is_valid_stack_directions(scheduler::DIRECTION_BACKWARD));
+    }
+
+    #[test]
+    fn cram_queue() {
+        use crate::{models, scheduler::{cram, SECONDS_IN_DAY}, utils};
+
+        let card = |id: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: String::new(),
+            backside: String::new(),
+            card_type: crate::cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let log = |id: &str, reviewed_at: i64, grade: i32| models::ReviewLogFull {
+            unique_id: 0,
+            user_id: "user".to_owned(),
+            card_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            reviewed_at,
+            grade,
+            previous_interval: 0,
+            next_interval: 0,
+            time_taken: 0,
+            phase: String::new(),
+            ordinal: 0,
+            direction: crate::scheduler::DIRECTION_FORWARD.to_owned(),
+        };
+        let cards = || vec![card("new"), card("easy"), card("hard"), card("old")];
+        let states = vec![models::CardState { interval_days: 10, ..models::CardState::new("user", "easy", 0) }];
+        let now = 30 * SECONDS_IN_DAY;
+        let logs = vec![log("hard", now, 1), log("hard", now, 0), log("easy", now, 5), log("old", 0, 2)];
+        let ids = |queue: Vec<models::Card>| -> Vec<String> { queue.into_iter().map(|card| card.unique_id).collect() };
+
+        let filter = cram::CramFilter::default();
+        let queue = cram::build_cram_queue(cards(), &states, &logs, &filter, cram::ORDER_WEAKEST, now, 0);
+        assert_eq!(ids(queue), vec!["hard", "old", "new", "easy"]);
+
+        let filter = cram::CramFilter { min_failures: Some(1), reviewed_within_days: Some(7) };
+        let queue = cram::build_cram_queue(cards(), &states, &logs, &filter, cram::ORDER_WEAKEST, now, 0);
+        assert_eq!(ids(queue), vec!["hard"]);
+
+        // Random order only depends on the seed
+        let mut items: Vec<i32> = (0..100).collect();
+        utils::shuffle(&mut items, 7);
+        assert_ne!(items, (0..100).collect::<Vec<i32>>());
+        let mut same: Vec<i32> = (0..100).collect();
+        utils::shuffle(&mut same, 7);
+        assert_eq!(items, same);
+        items.sort();
+        assert_eq!(items, (0..100).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn fsrs_review_sequence() {
+        use crate::{models, scheduler::{fsrs, Scheduler, SECONDS_IN_DAY}};
+
+        let fsrs = fsrs::Fsrs::default();
+        let mut state = models::CardState::new("user", "card", 0);
+
+        // First "good" review takes the initial stability straight from the parameters
+        fsrs.review(&mut state, 4, 0);
+        assert_eq!(state.stability, fsrs::DEFAULT_PARAMETERS[2]);
+        assert_eq!(state.difficulty, fsrs::DEFAULT_PARAMETERS[4]);
+        assert_eq!(state.interval_days, 4);
+
+        let mut now = state.due;
+        let mut last_interval = state.interval_days;
+        for _ in 0..3 {
+            fsrs.review(&mut state, 4, now);
+            assert!(state.interval_days > last_interval);
+            last_interval = state.interval_days;
+            now = state.due;
+        }
+
+        // Lapse drops the stability and makes the card harder
+        let (stability, difficulty) = (state.stability, state.difficulty);
+        fsrs.review(&mut state, 1, now);
+        assert!(state.stability < stability);
+        assert!(state.difficulty > difficulty);
+        assert_eq!(state.repetitions, 0);
+        assert_eq!(state.due, now + state.interval_days as i64 * SECONDS_IN_DAY);
+    }
+
+    #[test]
+    fn fsrs_optimize() {
+        use crate::{models, scheduler::{self, fsrs, SECONDS_IN_DAY}};
+
+        // Learner that forgets much faster than the default parameters expect
+        let sequences: Vec<Vec<fsrs::ReviewRecord>> = (0..20).map(|i| {
+            [0, 3, 10, 30].iter().enumerate().map(|(n, day)| fsrs::ReviewRecord {
+                timestamp: day * SECONDS_IN_DAY,
+                grade: if n > 0 && (i + n) % 2 == 0 { 1 } else { 4 },
+            }).collect()
+        }).collect();
+
+        let optimized = fsrs::optimize(&fsrs::DEFAULT_PARAMETERS, &sequences).unwrap();
+        assert!(fsrs::is_valid_parameters(&optimized));
+        assert!(fsrs::loss(&optimized, &sequences).0 < fsrs::loss(&fsrs::DEFAULT_PARAMETERS, &sequences).0);
+
+        // Not enough reviews to optimize on
+        assert!(fsrs::optimize(&fsrs::DEFAULT_PARAMETERS, &sequences[..2]).is_none());
+
+        let stored = fsrs::parameters_to_string(&optimized);
+        assert!(fsrs::parse_parameters(&stored).is_some());
+        assert!(fsrs::parse_parameters("").is_none());
+
+        // optimize_scheduler reads the same reviews back from the review log, newest first
+        let mut logs: Vec<models::ReviewLogFull> = sequences.iter().enumerate().flat_map(|(i, sequence)| {
+            sequence.iter().map(move |record| models::ReviewLogFull {
+                unique_id: 0,
+                user_id: "user".to_owned(),
+                card_id: format!("card{:02}", i),
+                stack_id: "stack".to_owned(),
+                reviewed_at: record.timestamp,
+                grade: record.grade as i32,
+                previous_interval: 0,
+                next_interval: 0,
+                time_taken: 0,
+                phase: String::new(),
+                ordinal: 0,
+                direction: scheduler::DIRECTION_FORWARD.to_owned(),
+            })
+        }).collect();
+        logs.reverse();
+        assert_eq!(fsrs::sequences_from_logs(&logs), sequences);
+        assert_eq!(fsrs::optimize(&fsrs::DEFAULT_PARAMETERS, &fsrs::sequences_from_logs(&logs)), Some(optimized));
+    }
+
+    #[test]
+    fn review_history() {
+        use crate::{models, scheduler::{self, fsrs}, transfer};
+
+        let log = |card_id: &str, reviewed_at: i64, grade: i32, ordinal: i32, direction: &str| models::ReviewLogFull {
+            unique_id: 0,
+            user_id: "user".to_owned(),
+            card_id: card_id.to_owned(),
+            stack_id: "stack".to_owned(),
+            reviewed_at,
+            grade,
+            previous_interval: 1,
+            next_interval: 3,
+            time_taken: 5300,
+            phase: scheduler::steps::PHASE_REVIEW.to_owned(),
+            ordinal,
+            direction: direction.to_owned(),
+        };
+        let logs = vec![
+            log("b", 300, 1, 0, scheduler::DIRECTION_FORWARD),
+            log("a", 200, 4, 0, scheduler::DIRECTION_BACKWARD),
+            log("a", 100, 3, 0, scheduler::DIRECTION_FORWARD),
+            log("a", 400, 5, 0, scheduler::DIRECTION_FORWARD),
+            log("a", 500, 2, 1, scheduler::DIRECTION_FORWARD),
+        ];
+
+        // Every ordinal and direction of a card is a sequence of its own, sorted by review time
+        let record = |timestamp: i64, grade: u8| fsrs::ReviewRecord { timestamp, grade };
+        assert_eq!(fsrs::sequences_from_logs(&logs), vec![
+            vec![record(200, 4)],
+            vec![record(100, 3), record(400, 5)],
+            vec![record(500, 2)],
+            vec![record(300, 1)],
+        ]);
+        assert!(fsrs::sequences_from_logs(&[]).is_empty());
+
+        let csv = transfer::review_logs_to_csv(&logs[..2]);
+        assert_eq!(csv, format!(
+            "card_id,stack_id,reviewed_at,grade,previous_interval,next_interval,time_taken,phase,ordinal,direction\n\
+            b,stack,300,1,1,3,5300,{phase},0,forward\n\
+            a,stack,200,4,1,3,5300,{phase},0,backward\n",
+            phase = scheduler::steps::PHASE_REVIEW,
+        ));
+        assert_eq!(transfer::review_logs_to_csv(&[]).lines().count(), 1);
+    }
+
+    #[test]
+    fn note_types() {
+        use crate::{cloze, models, notes, scheduler};
+
+        let field_names = vec!["Word".to_owned(), "Reading".to_owned(), "Meaning".to_owned()];
+        let templates = vec![
+            notes::Template {
+                name: "Recognition".to_owned(),
+                front: "{{Word}}".to_owned(),
+                back: "{{FrontSide}}\n{{#Reading}}({{Reading}}){{/Reading}}\n{{Meaning}}".to_owned(),
+            },
+            notes::Template {
+                name: "Reading".to_owned(),
+                front: "{{#Reading}}{{Word}}{{/Reading}}".to_owned(),
+                back: "{{Reading}}".to_owned(),
+            },
+        ];
+
+        assert!(notes::is_valid_field_names(&field_names));
+        assert!(notes::is_valid_templates(&templates, &field_names));
+        assert!(! notes::is_valid_field_names(&["Word".to_owned(), "Word".to_owned()]));
+        assert!(! notes::is_valid_field_names(&[notes::FRONT_SIDE.to_owned()]));
+        for front in ["{{Unknown}}", "{{FrontSide}}", "{{#Word}}open", "{{/Word}}", "{{Word}", "No fields"] {
+            assert!(! notes::is_valid_template(front, &field_names, false), "{}", front);
+        }
+        assert!(notes::is_valid_template("{{FrontSide}}", &field_names, true));
+
+        // Every template renders its own card, a template with an empty front is left out
+        let fields = notes::Fields::from([("Word".to_owned(), "Hund".to_owned()), ("Meaning".to_owned(), "dog".to_owned())]);
+        assert!(notes::is_valid_fields(&fields, &field_names));
+        assert!(! notes::is_valid_fields(&notes::Fields::from([("Other".to_owned(), "x".to_owned())]), &field_names));
+        let rendered = notes::render_cards(&templates, &fields);
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(rendered[0].back, "Hund\n\ndog");
+        assert_eq!(rendered[0].answer, "dog");
+
+        let note_type = models::NoteType {
+            unique_id: "note".to_owned(),
+            stack_id: "stack".to_owned(),
+            name: "Vocabulary".to_owned(),
+            fields: serde_json::to_string(&field_names).unwrap(),
+            templates: serde_json::to_string(&templates).unwrap(),
+        };
+        let mut card = models::Card {
+            unique_id: "card".to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: String::new(),
+            backside: String::new(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+
+        // Basic cards keep their fields in frontside and backside
+        card.frontside = "Front".to_owned();
+        assert_eq!(card.field_values()[notes::BASIC_FRONT_FIELD], "Front");
+
+        let mut fields = fields;
+        fields.insert("Reading".to_owned(), "hʊnt".to_owned());
+        assert!(card.set_fields(&note_type, fields));
+        assert_eq!(card.note_type_id, "note");
+        assert_eq!((card.frontside.as_str(), card.backside.as_str()), ("Hund", "Hund\n(hʊnt)\ndog"));
+        assert!(! card.set_fields(&note_type, notes::Fields::new()));
+
+        // Note type cards are only studied forward, once for every template
+        let layout = models::CardLayout { directions: scheduler::DIRECTIONS_BOTH, note_types: std::slice::from_ref(&note_type) };
+        let sub_cards = card.sub_cards(&layout);
+        assert_eq!(sub_cards.iter().map(|sub_card| (sub_card.ordinal, sub_card.direction.as_str())).collect::<Vec<(i32, &str)>>(), vec![
+            (0, scheduler::DIRECTION_FORWARD),
+            (1, scheduler::DIRECTION_FORWARD),
+        ]);
+        assert_eq!(sub_cards[1].answer, "hʊnt");
+
+        // Cards of note types the stack doesn't have can't be studied
+        assert!(card.sub_cards(&models::CardLayout { directions: scheduler::DIRECTIONS_BOTH, note_types: &[] }).is_empty());
+    }
+
+    #[test]
+    fn anki_import() {
+        use crate::{cloze, scheduler, transfer::{self, anki}};
+        use std::io::Write;
+
+        assert_eq!(anki::html_to_text("<div>Hund&nbsp;&amp; Katze</div><div><br></div><div><b>dog</b></div>[sound:hund.mp3]"), "Hund & Katze\n\ndog");
+        let fields = vec!["Word".to_owned(), "Meaning".to_owned()];
+        assert_eq!(anki::convert_template("{{text:Word}}<br>{{type:Meaning}}{{Tags}}", &fields), "{{Word}}");
+
+        // Collection with a basic, a reversed, a cloze and an unsupported note type
+        let path = std::env::temp_dir().join(format!("anki-import-test-{}.sqlite", std::process::id()));
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let models = serde_json::json!({
+            "1": { "name": "Basic", "type": 0, "flds": [{ "name": "Front", "ord": 0 }, { "name": "Back", "ord": 1 }],
+                "tmpls": [{ "name": "Card 1", "ord": 0, "qfmt": "{{Front}}", "afmt": "{{FrontSide}}<hr id=answer>{{Back}}" }] },
+            "2": { "name": "Basic (and reversed card)", "type": 0, "flds": [{ "name": "Front", "ord": 0 }, { "name": "Back", "ord": 1 }],
+                "tmpls": [{ "name": "Card 1", "ord": 0, "qfmt": "{{Front}}", "afmt": "{{FrontSide}}<hr id=answer>{{Back}}" },
+                    { "name": "Card 2", "ord": 1, "qfmt": "{{Back}}", "afmt": "{{FrontSide}}<hr id=answer>{{Front}}" }] },
+            "3": { "name": "Cloze", "type": 1, "flds": [{ "name": "Text", "ord": 0 }, { "name": "Back Extra", "ord": 1 }],
+                "tmpls": [{ "name": "Cloze", "ord": 0, "qfmt": "{{cloze:Text}}", "afmt": "{{cloze:Text}}<br>{{Back Extra}}" }] },
+            "4": { "name": "Image Occlusion", "type": 0, "flds": [{ "name": "Image", "ord": 0 }],
+                "tmpls": [{ "name": "Card 1", "ord": 0, "qfmt": "{{Unknown}}", "afmt": "" }] },
+        });
+        let decks = serde_json::json!({ "1": { "name": "Default" }, "10": { "name": "Languages::German" } });
+        conn.execute_batch("
+            CREATE TABLE col (models TEXT, decks TEXT);
+            CREATE TABLE notes (id INTEGER, mid INTEGER, tags TEXT, flds TEXT);
+            CREATE TABLE cards (id INTEGER, nid INTEGER, did INTEGER, ord INTEGER);
+            CREATE TABLE revlog (id INTEGER, cid INTEGER, ease INTEGER, ivl INTEGER, lastIvl INTEGER, time INTEGER, type INTEGER);
+            INSERT INTO notes VALUES (100, 1, ' german nouns ', 'Hund\x1fdog'), (101, 2, ' german ', 'Katze\x1fcat'),
+                (102, 3, '', '{{c1::Berlin}} is in {{c2::Germany}}\x1f'), (103, 4, '', '<img src=\"a.png\">'), (104, 1, '', '\x1fno front');
+            INSERT INTO cards VALUES (200, 100, 10, 0), (201, 101, 10, 0), (202, 101, 10, 1), (203, 102, 10, 0), (204, 102, 10, 1), (205, 103, 10, 0), (206, 104, 10, 0);
+            INSERT INTO revlog VALUES (1700000000000, 200, 3, -600, 0, 4000, 0), (1700086400000, 200, 1, 1, -600, 9000, 1), (1700090000000, 202, 4, 4, 0, 2000, 0);
+        ").unwrap();
+        conn.execute("INSERT INTO col VALUES (?1, ?2)", [models.to_string(), decks.to_string()]).unwrap();
+        drop(conn);
+
+        let mut package = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        package.start_file("collection.anki2", zip::write::FileOptions::default()).unwrap();
+        package.write_all(&std::fs::read(&path).unwrap()).unwrap();
+        let package = package.finish().unwrap().into_inner();
+        std::fs::remove_file(&path).unwrap();
+
+        let (stacks, warnings) = anki::parse(&package, true).unwrap();
+        assert_eq!(stacks.len(), 1);
+        let stack = &stacks[0];
+        assert_eq!((stack.name.as_str(), stack.tags.as_str()), ("German", "german,nouns"));
+        assert_eq!(stack.cards, vec![
+            transfer::ImportedCard { reviews: stack.cards[0].reviews.clone(), ..transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "Hund", "dog") },
+            transfer::ImportedCard {
+                directions: scheduler::DIRECTIONS_BOTH.to_owned(),
+                reviews: stack.cards[1].reviews.clone(),
+                ..transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "Katze", "cat")
+            },
+            transfer::ImportedCard::basic(cloze::CARD_TYPE_CLOZE, "{{c1::Berlin}} is in {{c2::Germany}}", ""),
+        ]);
+        assert_eq!(warnings, vec![
+            transfer::Warning::new("note 104", "front is empty"),
+            transfer::Warning::new("Image Occlusion", "unsupported note type, 1 notes left out"),
+        ]);
+
+        // Reviews keep their sub-card, the first answer of a card is the one of a new card
+        let reviews = &stack.cards[0].reviews;
+        assert_eq!(reviews.iter().map(|review| (review.grade, review.phase.as_str(), review.next_interval)).collect::<Vec<(i32, &str, i32)>>(), vec![
+            (4, scheduler::steps::PHASE_NEW, 0),
+            (1, scheduler::steps::PHASE_REVIEW, 1),
+        ]);
+        assert_eq!(reviews[0].reviewed_at, 1700000000);
+        assert_eq!(stack.cards[1].reviews[0].direction, scheduler::DIRECTION_BACKWARD);
+
+        assert!(anki::parse(&package, false).unwrap().0[0].cards.iter().all(|card| card.reviews.is_empty()));
+        assert!(anki::parse(b"not a zip", false).is_none());
+    }
+
+    #[test]
+    fn anki_export() {
+        use crate::{cloze, models, notes, scheduler, transfer::{self, anki}};
+
+        let stack = models::StackFull {
+            visibility: true,
+            cards_count: 4,
+            tags: "german,a1".to_owned(),
+            ..test_stack("user")
+        };
+        let field_names = vec!["Word".to_owned(), "Reading".to_owned(), "Meaning".to_owned()];
+        let templates = vec![notes::Template {
+            name: "Recognition".to_owned(),
+            front: "{{Word}}".to_owned(),
+            back: "{{FrontSide}}\n{{Reading}}\n{{Meaning}}".to_owned(),
+        }];
+        let note_type = models::NoteType {
+            unique_id: "note".to_owned(),
+            stack_id: "stack".to_owned(),
+            name: "Vocabulary".to_owned(),
+            fields: serde_json::to_string(&field_names).unwrap(),
+            templates: serde_json::to_string(&templates).unwrap(),
+        };
+        let card = |id: &str, card_type: &str, directions: &str, frontside: &str, backside: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            card_type: card_type.to_owned(),
+            directions: directions.to_owned(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let mut vocabulary = card("vocabulary", cloze::CARD_TYPE_BASIC, "", "", "");
+        let fields = notes::Fields::from([
+            ("Word".to_owned(), "Maus".to_owned()),
+            ("Reading".to_owned(), "die Maus".to_owned()),
+            ("Meaning".to_owned(), "mouse & <rat>".to_owned()),
+        ]);
+        assert!(vocabulary.set_fields(&note_type, fields.clone()));
+        let cards = vec![
+            card("basic", cloze::CARD_TYPE_BASIC, "", "Hund", "dog"),
+            card("reversed", cloze::CARD_TYPE_BASIC, scheduler::DIRECTIONS_BOTH, "Katze", "cat"),
+            card("cloze", cloze::CARD_TYPE_CLOZE, "", "{{c1::Berlin}} is in {{c2::Germany}}", ""),
+            vocabulary,
+        ];
+        let review = |card_id: &str, reviewed_at: i64, grade: i32, direction: &str| models::ReviewLogFull {
+            unique_id: 0,
+            user_id: "user".to_owned(),
+            card_id: card_id.to_owned(),
+            stack_id: "stack".to_owned(),
+            reviewed_at,
+            grade,
+            previous_interval: 0,
+            next_interval: 1,
+            time_taken: 3000,
+            phase: scheduler::steps::PHASE_NEW.to_owned(),
+            ordinal: 0,
+            direction: direction.to_owned(),
+        };
+        // Both reviews happened in the same second, the second one is of a sub-card that doesn't exist
+        let reviews = vec![
+            review("reversed", 1700000000, 5, scheduler::DIRECTION_BACKWARD),
+            review("basic", 1700000000, 2, scheduler::DIRECTION_FORWARD),
+            review("basic", 1700000001, 4, scheduler::DIRECTION_BACKWARD),
+        ];
+
+        assert_eq!(anki::text_to_html("a < b\nc"), "a &lt; b<br>c");
+
+        // Exported stacks import as the same cards
+        let package = anki::write(&stack, &[note_type], &cards, &reviews, 1700001000).unwrap();
+        let (stacks, warnings) = anki::parse(&package, true).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(stacks.len(), 1);
+        let imported = &stacks[0];
+        assert_eq!((imported.name.as_str(), imported.tags.as_str()), ("German", "german,a1"));
+        assert_eq!(imported.note_types, vec![transfer::ImportedNoteType { name: "Vocabulary".to_owned(), field_names, templates }]);
+        assert_eq!(imported.cards.iter().map(|card| transfer::ImportedCard { reviews: Vec::new(), ..card.clone() }).collect::<Vec<transfer::ImportedCard>>(), vec![
+            transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "Hund", "dog"),
+            transfer::ImportedCard { directions: scheduler::DIRECTIONS_BOTH.to_owned(), ..transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "Katze", "cat") },
+            transfer::ImportedCard::basic(cloze::CARD_TYPE_CLOZE, "{{c1::Berlin}} is in {{c2::Germany}}", ""),
+            transfer::ImportedCard { note_type: Some(0), fields, ..transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "", "") },
+        ]);
+
+        assert_eq!(imported.cards[0].reviews.iter().map(|review| (review.reviewed_at, review.grade)).collect::<Vec<(i64, i32)>>(), vec![(1700000000, 1)]);
+        assert_eq!(imported.cards[1].reviews[0].direction, scheduler::DIRECTION_BACKWARD);
+        assert_eq!(imported.cards[1].reviews[0].grade, 5);
+
+        let without_reviews = anki::write(&stack, &[], &cards[..3], &[], 1700001000).unwrap();
+        assert!(anki::parse(&without_reviews, true).unwrap().0[0].cards.iter().all(|card| card.reviews.is_empty()));
+    }
+
+    #[test]
+    fn delimited_import() {
+        use crate::{cloze, models, scheduler, transfer::delimited};
+
+        let csv = delimited::Format::default();
+        let tsv = delimited::Format { delimiter: '\t', quote: None, header: true };
+        assert!(! delimited::Format { quote: Some(','), ..csv }.is_valid());
+
+        // Quoted values keep delimiters, quotes and line breaks, empty lines are left out
+        let rows = delimited::read("\u{feff}Hund,dog\r\n\r\n\"Katze, die\",\"\"\"cat\"\"\"\n\"two\nlines\",x\nlast", &csv).unwrap();
+        assert_eq!(rows.iter().map(|row| (row.line, row.values.clone())).collect::<Vec<(usize, Vec<String>)>>(), vec![
+            (1, vec!["Hund".to_owned(), "dog".to_owned()]),
+            (3, vec!["Katze, die".to_owned(), "\"cat\"".to_owned()]),
+            (4, vec!["two\nlines".to_owned(), "x".to_owned()]),
+            (6, vec!["last".to_owned()]),
+        ]);
+        assert_eq!(delimited::read("a,b\n\"open,c\nd", &csv), Err(2));
+        assert_eq!(delimited::read("\"a\"\tb", &tsv).unwrap()[0].values, vec!["\"a\"", "b"]);
+
+        let values = vec!["a,b".to_owned(), "say \"hi\"".to_owned(), "two\nlines".to_owned(), "plain".to_owned()];
+        let written = delimited::write(std::slice::from_ref(&values), &csv);
+        assert_eq!(written, "\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",plain\n");
+        assert_eq!(delimited::read(&written, &csv).unwrap()[0].values, values);
+        assert_eq!(delimited::write(&[vec!["a\tb".to_owned(), "c\nd".to_owned()]], &tsv), "a b\tc d\n");
+
+        // Columns map to card properties or to fields of a note type
+        let field_names = vec!["Word".to_owned(), "Meaning".to_owned()];
+        let columns = |columns: &[&str]| columns.iter().map(|column| column.to_string()).collect::<Vec<String>>();
+        assert!(delimited::is_valid_columns(&columns(&["backside", "", "frontside", "card_type"]), None));
+        assert!(! delimited::is_valid_columns(&columns(&["frontside", "frontside"]), None));
+        assert!(! delimited::is_valid_columns(&columns(&["Word"]), None));
+        assert!(! delimited::is_valid_columns(&columns(&["", ""]), None));
+        assert!(delimited::is_valid_columns(&columns(&["Meaning", "Word", "directions"]), Some(&field_names)));
+        assert!(! delimited::is_valid_columns(&columns(&["frontside"]), Some(&field_names)));
+
+        let row = delimited::Row { line: 1, values: vec!["dog".to_owned(), "ignored".to_owned(), "{{c1::Hund}}".to_owned(), "cloze".to_owned()] };
+        let card = delimited::row_to_card(&row, &columns(&["backside", "", "frontside", "card_type"]), false).unwrap();
+        assert_eq!((card.frontside.as_str(), card.backside.as_str(), card.card_type.as_str()), ("{{c1::Hund}}", "dog", cloze::CARD_TYPE_CLOZE));
+        assert!(delimited::row_to_card(&row, &columns(&["frontside"]), false).is_err());
+
+        let row = delimited::Row { line: 1, values: vec!["dog".to_owned(), "Hund".to_owned(), scheduler::DIRECTIONS_BOTH.to_owned()] };
+        let card = delimited::row_to_card(&row, &columns(&["Meaning", "Word", "directions"]), true).unwrap();
+        assert_eq!((card.note_type, card.fields.get("Word").map(String::as_str), card.directions.as_str()), (Some(0), Some("Hund"), scheduler::DIRECTIONS_BOTH));
+
+        // Skipped columns don't become fields of the note type
+        let note_type_columns = columns(&["Meaning", "", "Word"]);
+        assert!(delimited::is_valid_columns(&note_type_columns, Some(&field_names)));
+        let card = delimited::row_to_card(&row, &note_type_columns, true).unwrap();
+        assert_eq!(card.fields.len(), 2);
+        assert_eq!((card.fields.get("Meaning").map(String::as_str), card.fields.get("Word").map(String::as_str)), (Some("dog"), Some(scheduler::DIRECTIONS_BOTH)));
+        assert!(! card.fields.contains_key(delimited::COLUMN_SKIPPED));
+
+        // Cards are checked like create_card checks them
+        let new_card = |frontside: &str, card_type: &str| models::Card {
+            unique_id: String::new(),
+            stack_id: "stack".to_owned(),
+            frontside: frontside.to_owned(),
+            backside: String::new(),
+            card_type: card_type.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        assert!(new_card("{{c1::Hund}}", cloze::CARD_TYPE_CLOZE).check_basic().is_ok());
+        assert_eq!(new_card("Hund", cloze::CARD_TYPE_CLOZE).check_basic(), Err("invalid card type or cloze markers"));
+        assert!(new_card(&"a".repeat(256), cloze::CARD_TYPE_BASIC).check_basic().is_err());
+
+        let exported = new_card("Hund, der", cloze::CARD_TYPE_BASIC);
+        let export_columns = delimited::export_columns(None);
+        let file = delimited::write(&[export_columns.clone(), delimited::card_to_row(&exported, &export_columns)], &csv);
+        assert_eq!(file, "frontside,backside,card_type,directions\n\"Hund, der\",,basic,\n");
+    }
+
+    #[test]
+    fn text_import() {
+        use crate::transfer::text;
+
+        let cards = |terms: Vec<text::ParsedTerm>| terms.into_iter()
+            .map(|term| (term.line, term.card.map(|card| (card.frontside, card.backside))))
+            .collect::<Vec<(usize, Result<(String, String), &'static str>)>>();
+        let card = |front: &str, back: &str| Ok((front.to_owned(), back.to_owned()));
+
+        // Terms keep everything after the first separator, empty lines are left out
+        let parsed = text::parse("Hund\tdog\r\n\n  Katze\t cat \nno definition\n\tno term\nMaus\tmouse\tsmall\n", &text::Separators::default());
+        assert_eq!(cards(parsed), vec![
+            (1, card("Hund", "dog")),
+            (3, card("Katze", "cat")),
+            (4, Err("term and definition separator is missing")),
+            (5, Err("term is empty")),
+            (6, card("Maus", "mouse\tsmall")),
+        ]);
+
+        let separators = text::Separators { term: " - ".to_owned(), card: ";".to_owned() };
+        assert_eq!(cards(text::parse("Hund - dog;\nKatze - cat - Tier;", &separators)), vec![
+            (1, card("Hund", "dog")),
+            (2, card("Katze", "cat - Tier")),
+        ]);
+
+        // Separators with Windows line breaks match texts with either kind of line breaks
+        let separators = text::Separators { term: " = ".to_owned(), card: "\r\n\r\n".to_owned() };
+        let expected = vec![(1, card("Hund", "dog\nbarks")), (4, card("Katze", "cat"))];
+        assert_eq!(cards(text::parse("Hund = dog\r\nbarks\r\n\r\nKatze = cat", &separators)), expected);
+        assert_eq!(cards(text::parse("Hund = dog\nbarks\n\nKatze = cat", &separators)), expected);
+
+        assert!(! text::Separators { term: "\n".to_owned(), card: "\n\n".to_owned() }.is_valid());
+        assert!(! text::Separators { term: String::new(), card: "\n".to_owned() }.is_valid());
+    }
+
+    #[test]
+    fn markdown_transfer() {
+        use crate::{cloze, models, notes, scheduler, transfer::{self, markdown}};
+
+        let stack = models::StackFull {
+            visibility: true,
+            cards_count: 4,
+            tags: "german,a1".to_owned(),
+            learning_steps: "1m,10m,1h".to_owned(),
+            leech_threshold: 5,
+            leech_suspend: true,
+            scheduler: scheduler::SCHEDULER_LEITNER.to_owned(),
+            leitner_intervals: "1,3,7".to_owned(),
+            directions: scheduler::DIRECTIONS_BOTH.to_owned(),
+            ..test_stack("user")
+        };
+        let card = |id: &str, card_type: &str, directions: &str, frontside: &str, backside: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            card_type: card_type.to_owned(),
+            directions: directions.to_owned(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        // Lines that look like markers, empty lines at the end and spaces at the start have to survive
+        let cards = vec![
+            card("a", cloze::CARD_TYPE_BASIC, "", "Hund", "dog"),
+            card("b", cloze::CARD_TYPE_BASIC, scheduler::DIRECTION_FORWARD, " Katze\nA: not the answer", "cat\n\nQ: no question\n\\ backslash\n\n"),
+            card("c", cloze::CARD_TYPE_CLOZE, "", "{{c1::Berlin}} is in {{c2::Germany}}", ""),
+            card("d", cloze::CARD_TYPE_BASIC, "", "<!-- card_type: cloze -->", "# no heading"),
+            card("e", cloze::CARD_TYPE_BASIC, "", "front", "back\n  <!-- a: b -->\n\t<!-- directions: both -->"),
+        ];
+
+        // Cards of note types become a basic card for every template
+        let note_type = models::NoteType {
+            unique_id: "note".to_owned(),
+            stack_id: "stack".to_owned(),
+            name: "Vocabulary".to_owned(),
+            fields: serde_json::to_string(&["Word", "Meaning"]).unwrap(),
+            templates: serde_json::to_string(&[
+                notes::Template { name: "Recognize".to_owned(), front: "{{Word}}".to_owned(), back: "{{Meaning}}".to_owned() },
+                notes::Template { name: "Recall".to_owned(), front: "{{Meaning}}".to_owned(), back: "{{Word}}".to_owned() },
+            ]).unwrap(),
+        };
+        let fields = notes::Fields::from([("Word".to_owned(), "Maus".to_owned()), ("Meaning".to_owned(), "mouse".to_owned())]);
+        let mut with_note_type = card("f", cloze::CARD_TYPE_BASIC, "", "", "");
+        assert!(with_note_type.set_fields(&note_type, fields));
+        let mut exported = cards.clone();
+        exported.push(with_note_type);
+
+        let text = markdown::write(&stack, std::slice::from_ref(&note_type), &exported);
+        assert!(text.contains("\n# German\n\nQ: Hund\nA: dog\n\n<!-- directions: forward -->\nQ:  Katze\n\\A: not the answer\n"));
+        assert!(text.contains("\nQ: front\nA: back\n\\  <!-- a: b -->\n\\\t<!-- directions: both -->\n"));
+        let (imported, warnings) = markdown::parse(&text);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!((imported.name.as_str(), imported.tags.as_str(), imported.visibility), ("German", "german,a1", true));
+        assert_eq!(imported.settings, transfer::ImportedSettings {
+            learning_steps: stack.learning_steps.clone(),
+            relearning_steps: stack.relearning_steps.clone(),
+            leech_threshold: 5,
+            leech_suspend: true,
+            scheduler: stack.scheduler.clone(),
+            leitner_intervals: stack.leitner_intervals.clone(),
+            directions: stack.directions.clone(),
+        });
+        let mut expected: Vec<transfer::ImportedCard> = cards.iter().map(|card| transfer::ImportedCard {
+            directions: card.directions.clone(),
+            ..transfer::ImportedCard::basic(&card.card_type, &card.frontside, &card.backside)
+        }).collect();
+        for (front, back) in [("Maus", "mouse"), ("mouse", "Maus")] {
+            expected.push(transfer::ImportedCard {
+                directions: scheduler::DIRECTION_FORWARD.to_owned(),
+                ..transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, front, back)
+            });
+        }
+        assert_eq!(imported.cards, expected);
+
+        // Blocks with a separator line, settings that can't be used fall back to the defaults
+        let (imported, warnings) = markdown::parse("---\ntags: [german, \"a1\"]\nleech_threshold: 500\ncolor: red\n---\n# Blocks\n\nHund\n---\ndog\nbig\n\nno back\n\n<!-- card_type: cloze -->\nQ: no cloze\nA:\n");
+        assert_eq!((imported.name.as_str(), imported.tags.as_str()), ("Blocks", "german,a1"));
+        assert_eq!(imported.settings, transfer::ImportedSettings::default());
+        assert_eq!(imported.cards, vec![transfer::ImportedCard::basic(cloze::CARD_TYPE_BASIC, "Hund", "dog\nbig")]);
+        assert_eq!(warnings, vec![
+            transfer::Warning::new("line 3", "invalid leech_threshold, the default is used"),
+            transfer::Warning::new("line 4", "unknown key color"),
+            transfer::Warning::new("line 13", "back is missing"),
+            transfer::Warning::new("line 16", "invalid card type or cloze markers"),
+        ]);
+    }
+
+    #[test]
+    fn account_archive() {
+        use crate::{cloze, models, scheduler, transfer::{self, account}};
+
+        let user = |id: &str, country: &str| models::User {
+            unique_id: id.to_owned(),
+            email: format!("{}@example.com", id),
+            username: id.to_owned(),
+            password_hash: "hash".to_owned(),
+            date_of_registration: 1700000000,
+            country: country.to_owned(),
+            scheduler: scheduler::SCHEDULER_FSRS.to_owned(),
+            fsrs_parameters: String::new(),
+            new_cards_per_day: 15,
+            reviews_per_day: 150,
+        };
+        let stack = models::StackFull {
+            cards_count: 3,
+            tags: "german".to_owned(),
+            learning_steps: "1m,10m,1h".to_owned(),
+            leech_threshold: 5,
+            leech_suspend: true,
+            directions: scheduler::DIRECTIONS_BOTH.to_owned(),
+            ..test_stack("old")
+        };
+        let card = |id: &str, card_type: &str, frontside: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: frontside.to_owned(),
+            backside: "back".to_owned(),
+            card_type: card_type.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let cards = vec![
+            card("hund", cloze::CARD_TYPE_BASIC, "Hund"),
+            card("berlin", cloze::CARD_TYPE_CLOZE, "{{c1::Berlin}}"),
+            card("broken", cloze::CARD_TYPE_CLOZE, "no cloze"),
+        ];
+        let state = |card_id: &str| models::CardState { interval_days: 4, repetitions: 2, ..models::CardState::new("old", card_id, 1700000000) };
+        let review = |card_id: &str, stack_id: &str| models::ReviewLogFull {
+            unique_id: 1,
+            user_id: "old".to_owned(),
+            card_id: card_id.to_owned(),
+            stack_id: stack_id.to_owned(),
+            reviewed_at: 1700000000,
+            grade: 4,
+            previous_interval: 0,
+            next_interval: 4,
+            time_taken: 2000,
+            phase: scheduler::steps::PHASE_REVIEW.to_owned(),
+            ordinal: 0,
+            direction: scheduler::DIRECTION_FORWARD.to_owned(),
+        };
+
+        let archive = account::Archive {
+            format: account::FORMAT.to_owned(),
+            version: account::VERSION,
+            exported_at: 1700001000,
+            profile: account::ArchivedProfile::from(&user("old", "XX")),
+            stacks: vec![account::ArchivedStack::new(&stack, &[], &cards)],
+            card_states: [state("hund"), state("broken"), state("public")].into_iter().map(account::ArchivedCardState::from).collect(),
+            reviews: [review("hund", "stack"), review("public", "other")].into_iter().map(account::ArchivedReview::from).collect(),
+            study_sessions: Vec::new(),
+            quiz_attempts: Vec::new(),
+        };
+        // The password hash is never part of an archive
+        let json = serde_json::to_string(&archive).unwrap();
+        assert!(! json.contains("password_hash"));
+        let archive: account::Archive = serde_json::from_str(&json).unwrap();
+
+        // Profile settings go to the account, invalid ones keep the values of the account
+        let mut new_user = user("new", "DEU");
+        new_user.scheduler = scheduler::SCHEDULER_SM2.to_owned();
+        let restored = account::restore(archive.clone(), &mut new_user).unwrap();
+        assert_eq!((new_user.unique_id.as_str(), new_user.country.as_str(), new_user.scheduler.as_str()), ("new", "DEU", scheduler::SCHEDULER_FSRS));
+
+        assert_eq!(restored.stacks.len(), 1);
+        let imported = &restored.stacks[0];
+        assert_eq!((imported.name.as_str(), imported.tags.as_str(), imported.settings.learning_steps.as_str()), ("German", "german", "1m,10m,1h"));
+        assert_eq!(imported.cards.len(), 2);
+        assert_eq!((imported.cards[0].frontside.as_str(), imported.cards[0].reviews.len()), ("Hund", 1));
+        assert_eq!(imported.cards[0].states, vec![models::CardState { user_id: String::new(), card_id: String::new(), ..state("hund") }]);
+        assert!(imported.cards[1].states.is_empty());
+
+        // History of cards outside of the archive keeps the old card ids, the one of a left out card is dropped
+        assert_eq!(restored.other_states.iter().map(|state| state.card_id.as_str()).collect::<Vec<&str>>(), vec!["public"]);
+        assert_eq!(restored.other_reviews.iter().map(|review| review.card_id.as_str()).collect::<Vec<&str>>(), vec!["public"]);
+
+        // Restoring into an account keeps its newer progress and doesn't log the same review twice
+        let archived_at = restored.other_states[0].last_review;
+        let newer = models::CardState { user_id: "new".to_owned(), last_review: archived_at + 1, ..state("public") };
+        let (states, reviews) = account::new_history(restored.other_states.clone(), restored.other_reviews.clone(), &[newer], &[review("public", "other")]);
+        assert!(states.is_empty() && reviews.is_empty());
+        let older = models::CardState { user_id: "new".to_owned(), last_review: archived_at - 1, ..state("public") };
+        let other_card = models::ReviewLogFull { reviewed_at: 1700000001, ..review("public", "other") };
+        let (states, reviews) = account::new_history(restored.other_states.clone(), restored.other_reviews.clone(), &[older], &[other_card]);
+        assert_eq!((states.len(), reviews.len()), (1, 1));
+        let (states, reviews) = account::new_history(restored.other_states.clone(), restored.other_reviews.clone(), &[], &[]);
+        assert_eq!((states, reviews.len()), (restored.other_states.clone(), 1));
+        assert_eq!(restored.warnings, vec![
+            transfer::Warning::new("profile", "invalid country, the one of the account is kept"),
+            transfer::Warning::new("card broken", "invalid card type or cloze markers"),
+        ]);
+
+        // Archives of unknown formats or newer versions are refused
+        assert!(account::restore(account::Archive { version: account::VERSION + 1, ..archive.clone() }, &mut new_user).is_none());
+        assert!(account::restore(account::Archive { format: "other".to_owned(), ..archive }, &mut new_user).is_none());
+    }
+
+    #[test]
+    fn upstream_sync() {
+        use crate::{cloze, models, notes, upstream};
+
+        let card = |id: &str, stack_id: &str, frontside: &str, backside: &str| models::Card {
+            unique_id: id.to_owned(),
+            stack_id: stack_id.to_owned(),
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let content = |frontside: &str, backside: &str| upstream::CardContent {
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            ..upstream::CardContent::default()
+        };
+        let lineage = |upstream_card_id: &str, card_id: Option<&str>, base: &upstream::CardContent| models::CardLineage {
+            stack_id: "fork".to_owned(),
+            upstream_card_id: upstream_card_id.to_owned(),
+            card_id: card_id.map(str::to_owned),
+            base: serde_json::to_string(base).unwrap(),
+        };
+
+        // Keys changed on one side keep that change, keys changed on both sides keep the local edit
+        let (merged, conflicts) = upstream::merge(&content("Hund", "dog"), &content("Hund", "the dog"), &content("der Hund", "dog"));
+        assert_eq!((merged, conflicts), (content("der Hund", "the dog"), Vec::new()));
+        let (merged, conflicts) = upstream::merge(&content("Hund", "dog"), &content("Hund", "the dog"), &content("der Hund", "a dog"));
+        assert_eq!((merged, conflicts), (content("der Hund", "the dog"), vec!["backside".to_owned()]));
+
+        let upstream_cards = vec![
+            card("u-same", "stack", "Katze", "cat"),
+            card("u-changed", "stack", "der Hund", "dog"),
+            card("u-conflict", "stack", "Maus", "a mouse"),
+            card("u-local", "stack", "Vogel", "bird"),
+            card("u-added", "stack", "Pferd", "horse"),
+            card("u-deleted-locally", "stack", "Fisch", "a fish"),
+        ];
+        let fork_cards = vec![
+            card("f-same", "fork", "Katze", "cat"),
+            card("f-changed", "fork", "Hund", "dog"),
+            card("f-conflict", "fork", "Maus", "the mouse"),
+            card("f-local", "fork", "Vogel", "the bird"),
+            card("f-removed", "fork", "Kuh", "cow"),
+            card("f-edited-removed", "fork", "Ziege", "the goat"),
+        ];
+        let links = vec![
+            lineage("u-same", Some("f-same"), &content("Katze", "cat")),
+            lineage("u-changed", Some("f-changed"), &content("Hund", "dog")),
+            lineage("u-conflict", Some("f-conflict"), &content("Maus", "mouse")),
+            lineage("u-local", Some("f-local"), &content("Vogel", "bird")),
+            lineage("u-removed", Some("f-removed"), &content("Kuh", "cow")),
+            lineage("u-deleted-locally", None, &content("Fisch", "fish")),
+            lineage("u-edited-removed", Some("f-edited-removed"), &content("Ziege", "goat")),
+        ];
+
+        // Local edits and cards deleted in the fork are not changes to pull
+        let changes = upstream::changes(&links, (&fork_cards, &[]), (&upstream_cards, &[]));
+        let kinds: Vec<(&str, Option<&str>, &str)> = changes.iter().map(|change| (change.upstream_card_id.as_str(), change.card_id.as_deref(), change.kind)).collect();
+        assert_eq!(kinds, vec![
+            ("u-changed", Some("f-changed"), upstream::CHANGE_CHANGED),
+            ("u-conflict", Some("f-conflict"), upstream::CHANGE_CONFLICT),
+            ("u-added", None, upstream::CHANGE_ADDED),
+            ("u-removed", Some("f-removed"), upstream::CHANGE_REMOVED),
+            ("u-edited-removed", Some("f-edited-removed"), upstream::CHANGE_CONFLICT),
+        ]);
+        assert_eq!(changes[1].conflicts, vec!["backside".to_owned()]);
+        assert_eq!(changes[1].upstream, Some(content("Maus", "a mouse")));
+
+        // Pulling a conflict keeps the edit of the fork
+        let mut pulled = fork_cards.iter().find(|card| card.unique_id == "f-conflict").unwrap().clone();
+        upstream::apply(&mut pulled, changes[1].merged.clone().unwrap(), &[]).unwrap();
+        assert_eq!((pulled.frontside.as_str(), pulled.backside.as_str()), ("Maus", "the mouse"));
+        assert_eq!(changes[2].merged, Some(content("Pferd", "horse")));
+        assert_eq!(changes[3].merged, None);
+
+        // Cards edited in the fork and deleted upstream are conflicts, the edit is kept instead of deleting the card
+        assert_eq!(upstream::change_kind(Some(&content("Ziege", "goat")), Some(&content("Ziege", "the goat")), None), Some(upstream::CHANGE_CONFLICT));
+        assert_eq!(upstream::change_kind(Some(&content("Kuh", "cow")), Some(&content("Kuh", "cow")), None), Some(upstream::CHANGE_REMOVED));
+        assert_eq!((changes[4].merged.as_ref(), changes[4].upstream.as_ref()), (None, None));
+        assert_eq!(changes[4].local, Some(content("Ziege", "the goat")));
+        assert_eq!(changes[4].conflicts, vec!["backside".to_owned()]);
+
+        // Cards of note types are matched by the name of the note type
+        let field_names = vec!["Word".to_owned(), "Meaning".to_owned()];
+        let templates = vec![notes::Template { name: "Card".to_owned(), front: "{{Word}}".to_owned(), back: "{{Meaning}}".to_owned() }];
+        let note_type = models::NoteType {
+            unique_id: "fork-note".to_owned(),
+            stack_id: "fork".to_owned(),
+            name: "Vocabulary".to_owned(),
+            fields: serde_json::to_string(&field_names).unwrap(),
+            templates: serde_json::to_string(&templates).unwrap(),
+        };
+        let fields = notes::Fields::from([("Word".to_owned(), "Hase".to_owned()), ("Meaning".to_owned(), "rabbit".to_owned())]);
+        let vocabulary = upstream::CardContent { note_type: "Vocabulary".to_owned(), fields, ..content("", "") };
+
+        let mut new_card = card("", "fork", "", "");
+        upstream::apply(&mut new_card, vocabulary.clone(), std::slice::from_ref(&note_type)).unwrap();
+        assert_eq!((new_card.note_type_id.as_str(), new_card.frontside.as_str(), new_card.backside.as_str()), ("fork-note", "Hase", "rabbit"));
+        assert_eq!(upstream::CardContent::new(&new_card, &[note_type]), vocabulary);
+        assert_eq!(upstream::apply(&mut card("", "fork", "", ""), vocabulary, &[]), Err("note type is missing in the fork"));
+    }
+
+    #[test]
+    fn suggestion_diff() {
+        use crate::suggestions;
+
+        let diff = suggestions::diff("the quick brown fox", "the quick red fox jumps");
+        let segments: Vec<(&str, &str)> = diff.iter().map(|segment| (segment.kind, segment.text.as_str())).collect();
+        assert_eq!(segments, vec![
+            (suggestions::DIFF_SAME, "the quick "),
+            (suggestions::DIFF_ADDED, "red"),
+            (suggestions::DIFF_REMOVED, "brown"),
+            (suggestions::DIFF_SAME, " fox"),
+            (suggestions::DIFF_ADDED, " jumps"),
+        ]);
+
+        // Both texts can be put back together from the segments
+        let join = |kind: &str| -> String {
+            diff.iter().filter(|segment| segment.kind == suggestions::DIFF_SAME || segment.kind == kind).map(|segment| segment.text.as_str()).collect()
+        };
+        assert_eq!(join(suggestions::DIFF_REMOVED), "the quick brown fox");
+        assert_eq!(join(suggestions::DIFF_ADDED), "the quick red fox jumps");
+
+        // New cards are all added, deleted ones all removed
+        assert_eq!(suggestions::diff("", "Katze").iter().map(|segment| segment.kind).collect::<Vec<_>>(), vec![suggestions::DIFF_ADDED]);
+        assert_eq!(suggestions::diff("Katze", "").iter().map(|segment| segment.kind).collect::<Vec<_>>(), vec![suggestions::DIFF_REMOVED]);
+        assert!(suggestions::diff("", "").is_empty());
+    }
+
+    #[test]
+    fn suggestion_review() {
+        use crate::{cloze, members, models, suggestions};
+
+        // Only public stacks of other users take suggestions, editors change them directly
+        let stack = models::StackFull { visibility: true, ..test_stack("owner") };
+        assert!(suggestions::can_suggest(&stack, None, "reader"));
+        assert!(suggestions::can_suggest(&stack, Some(members::ROLE_VIEWER), "viewer"));
+        assert!(! suggestions::can_suggest(&stack, Some(members::ROLE_OWNER), "owner"));
+        assert!(! suggestions::can_suggest(&stack, Some(members::ROLE_EDITOR), "editor"));
+        assert!(! suggestions::can_suggest(&models::StackFull { visibility: false, ..stack.clone() }, Some(members::ROLE_VIEWER), "viewer"));
+
+        let card = models::Card {
+            unique_id: "card".to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: "Hund".to_owned(),
+            backside: "cat".to_owned(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let text = |value: &str| Some(value.to_owned());
+
+        // New cards need both sides, edits and deletions need the card, deletions carry no content
+        let new = suggestions::suggested_card(suggestions::KIND_NEW, "stack", None, text("Katze"), text("cat"), None).unwrap();
+        assert_eq!((new.unique_id.as_str(), new.stack_id.as_str(), new.frontside.as_str(), new.backside.as_str()), ("", "stack", "Katze", "cat"));
+        assert!(suggestions::suggested_card(suggestions::KIND_NEW, "stack", None, text("Katze"), None, None).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_NEW, "stack", Some(&card), text("Katze"), text("cat"), None).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_EDIT, "stack", None, None, text("dog"), None).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_EDIT, "stack", Some(&card), None, text("cat"), None).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_EDIT, "stack", Some(&card), text("Hund"), None, text(cloze::CARD_TYPE_CLOZE)).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_DELETE, "stack", Some(&card), None, text("dog"), None).is_err());
+        assert!(suggestions::suggested_card(suggestions::KIND_EDIT, "stack", Some(&models::Card { note_type_id: "note".to_owned(), ..card.clone() }), None, text("dog"), None).is_err());
+
+        let edit = suggestions::suggested_card(suggestions::KIND_EDIT, "stack", Some(&card), None, text("dog"), None).unwrap();
+        assert_eq!((edit.unique_id.as_str(), edit.frontside.as_str(), edit.backside.as_str()), ("card", "Hund", "dog"));
+        let deletion = suggestions::suggested_card(suggestions::KIND_DELETE, "stack", Some(&card), None, None, None).unwrap();
+        assert_eq!((deletion.frontside.as_str(), deletion.backside.as_str()), ("", ""));
+
+        let suggestion = |kind: &str, card_id: Option<&str>, frontside: &str, backside: &str| models::Suggestion {
+            unique_id: "suggestion".to_owned(),
+            stack_id: "stack".to_owned(),
+            author_id: "reader".to_owned(),
+            kind: kind.to_owned(),
+            card_id: card_id.map(str::to_owned),
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            status: suggestions::STATUS_PENDING.to_owned(),
+            created_at: 1000,
+            resolved_at: None,
+        };
+
+        // Accepting adds, changes or deletes the card
+        let added = suggestions::accepted_card(&suggestion(suggestions::KIND_NEW, None, "Katze", "cat"), None).unwrap().unwrap();
+        assert_eq!((added.unique_id.as_str(), added.stack_id.as_str(), added.frontside.as_str(), added.backside.as_str()), ("", "stack", "Katze", "cat"));
+        let changed = suggestions::accepted_card(&suggestion(suggestions::KIND_EDIT, Some("card"), "Hund", "dog"), Some(card.clone())).unwrap().unwrap();
+        assert_eq!((changed.unique_id.as_str(), changed.frontside.as_str(), changed.backside.as_str()), ("card", "Hund", "dog"));
+        assert!(suggestions::accepted_card(&suggestion(suggestions::KIND_DELETE, Some("card"), "", ""), Some(card.clone())).unwrap().is_none());
+
+        // Cards deleted or moved to a note type since can't take the suggestion, it is still checked like update_card checks cards
+        assert!(suggestions::accepted_card(&suggestion(suggestions::KIND_EDIT, Some("card"), "Hund", "dog"), None).is_err());
+        assert!(suggestions::accepted_card(&suggestion(suggestions::KIND_DELETE, Some("card"), "", ""), None).is_err());
+        assert!(suggestions::accepted_card(&suggestion(suggestions::KIND_EDIT, Some("card"), "Hund", "dog"), Some(models::Card { note_type_id: "note".to_owned(), ..card.clone() })).is_err());
+        assert!(suggestions::accepted_card(&suggestion(suggestions::KIND_NEW, None, &"a".repeat(256), "cat"), None).is_err());
+
+        // Accepted and rejected suggestions are resolved at the given time
+        let mut accepted = suggestion(suggestions::KIND_NEW, None, "Katze", "cat");
+        suggestions::resolve(&mut accepted, suggestions::STATUS_ACCEPTED, 2000);
+        assert_eq!((accepted.status.as_str(), accepted.resolved_at), (suggestions::STATUS_ACCEPTED, Some(2000)));
+        let mut rejected = suggestion(suggestions::KIND_EDIT, Some("card"), "Hund", "dog");
+        suggestions::resolve(&mut rejected, suggestions::STATUS_REJECTED, 3000);
+        assert_eq!((rejected.status.as_str(), rejected.resolved_at, rejected.frontside.as_str()), (suggestions::STATUS_REJECTED, Some(3000), "Hund"));
+    }
+
+    #[test]
+    fn stack_roles() {
+        use crate::{members::{self, Permission}, models};
+
+        let stack = test_stack("owner");
+        let member = |user_id: &str, role: &str, accepted: bool| models::StackMember {
+            stack_id: "stack".to_owned(),
+            user_id: user_id.to_owned(),
+            role: role.to_owned(),
+            accepted,
+            invited_at: 0,
+        };
+
+        // Pending invites and memberships of other users or stacks don't count
+        assert_eq!(members::role(&stack, None, "owner"), Some(members::ROLE_OWNER));
+        assert_eq!(members::role(&stack, Some(&member("editor", members::ROLE_EDITOR, true)), "editor"), Some(members::ROLE_EDITOR));
+        assert_eq!(members::role(&stack, Some(&member("viewer", members::ROLE_VIEWER, true)), "viewer"), Some(members::ROLE_VIEWER));
+        assert_eq!(members::role(&stack, Some(&member("invited", members::ROLE_EDITOR, false)), "invited"), None);
+        assert_eq!(members::role(&stack, Some(&member("editor", members::ROLE_EDITOR, true)), "stranger"), None);
+        assert_eq!(members::role(&stack, Some(&models::StackMember { stack_id: "other".to_owned(), ..member("editor", members::ROLE_EDITOR, true) }), "editor"), None);
+
+        let allowed = |role: Option<&str>| -> Vec<bool> {
+            [Permission::Read, Permission::Edit, Permission::Manage].into_iter().map(|permission| members::allows(&stack, role, permission)).collect()
+        };
+        assert_eq!(allowed(Some(members::ROLE_OWNER)), vec![true, true, true]);
+        assert_eq!(allowed(Some(members::ROLE_EDITOR)), vec![true, true, false]);
+        assert_eq!(allowed(Some(members::ROLE_VIEWER)), vec![true, false, false]);
+        assert_eq!(allowed(None), vec![false, false, false]);
+
+        // Public stacks can be read by everyone
+        assert!(members::allows(&models::StackFull { visibility: true, ..stack.clone() }, None, Permission::Read));
+        assert!(! members::allows(&models::StackFull { visibility: true, ..stack.clone() }, None, Permission::Edit));
+        assert!(members::is_valid_member_role(members::ROLE_VIEWER) && ! members::is_valid_member_role(members::ROLE_OWNER));
+    }
+
+    #[test]
+    fn share_tokens() {
+        use crate::{members, models, utils};
+
+        let token = |expires_at: Option<i64>| models::ShareToken {
+            token: utils::random_token(),
+            stack_id: "stack".to_owned(),
+            created_at: 1000,
+            expires_at,
+        };
+
+        // Tokens only open their own stack and stop working once they expire
+        assert!(members::is_valid_token(&token(None), "stack", 5000));
+        assert!(members::is_valid_token(&token(Some(2000)), "stack", 1999));
+        assert!(! members::is_valid_token(&token(Some(2000)), "stack", 2000));
+        assert!(! members::is_valid_token(&token(None), "other", 1000));
+
+        let (first, second) = (utils::random_token(), utils::random_token());
+        assert_eq!(first.len(), 32);
+        assert!(first.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn stack_fork() {
+        use crate::{cloze, models, notes, upstream};
+
+        let stack = models::StackFull { visibility: true, cards_count: 2, tags: "german".to_owned(), forks_count: 2, ..test_stack("owner") };
+        let note_type = models::NoteType {
+            unique_id: "note".to_owned(),
+            stack_id: "stack".to_owned(),
+            name: "Vocabulary".to_owned(),
+            fields: serde_json::to_string(&["Word", "Meaning"]).unwrap(),
+            templates: serde_json::to_string(&[
+                notes::Template { name: "Recognize".to_owned(), front: "{{Word}}".to_owned(), back: "{{Meaning}}".to_owned() },
+            ]).unwrap(),
+        };
+        let basic = models::Card {
+            unique_id: "basic".to_owned(),
+            stack_id: "stack".to_owned(),
+            frontside: "Hund".to_owned(),
+            backside: "dog".to_owned(),
+            card_type: cloze::CARD_TYPE_BASIC.to_owned(),
+            directions: String::new(),
+            note_type_id: String::new(),
+            fields: String::new(),
+        };
+        let mut vocabulary = models::Card { unique_id: "vocabulary".to_owned(), ..basic.clone() };
+        let fields = notes::Fields::from([("Word".to_owned(), "Maus".to_owned()), ("Meaning".to_owned(), "mouse".to_owned())]);
+        assert!(vocabulary.set_fields(&note_type, fields));
+
+        let fork = upstream::fork(
+            "forker",
+            &stack,
+            "fork",
+            &[(note_type.clone(), "note-copy".to_owned())],
+            &[(basic.clone(), "basic-copy".to_owned()), (vocabulary.clone(), "vocabulary-copy".to_owned())],
+        );
+
+        // The copy is private, belongs to the forking user and remembers its upstream stack
+        assert_eq!((fork.stack.unique_id.as_str(), fork.stack.owner_id.as_str(), fork.stack.visibility), ("fork", "forker", false));
+        assert_eq!(fork.stack.upstream_id.as_deref(), Some("stack"));
+        assert_eq!((fork.stack.name.as_str(), fork.stack.tags.as_str()), ("German", "german"));
+        assert_eq!((fork.stack.learning_steps.as_str(), fork.stack.directions.as_str()), (stack.learning_steps.as_str(), stack.directions.as_str()));
+
+        // Note types and cards are copied into the fork, cards of note types use the copied note type
+        assert_eq!(fork.note_types.iter().map(|copy| (copy.unique_id.as_str(), copy.stack_id.as_str(), copy.name.as_str(), copy.templates.as_str())).collect::<Vec<_>>(), vec![
+            ("note-copy", "fork", "Vocabulary", note_type.templates.as_str()),
+        ]);
+        assert_eq!(fork.cards.iter().map(|copy| (copy.unique_id.as_str(), copy.stack_id.as_str(), copy.note_type_id.as_str(), copy.frontside.as_str(), copy.fields.as_str())).collect::<Vec<_>>(), vec![
+            ("basic-copy", "fork", "", "Hund", ""),
+            ("vocabulary-copy", "fork", "note-copy", "Maus", vocabulary.fields.as_str()),
+        ]);
+
+        // Every card remembers its upstream card and its content at the time of the fork
+        assert_eq!(fork.lineage.iter().map(|lineage| (lineage.stack_id.as_str(), lineage.upstream_card_id.as_str(), lineage.card_id.as_deref())).collect::<Vec<_>>(), vec![
+            ("fork", "basic", Some("basic-copy")),
+            ("fork", "vocabulary", Some("vocabulary-copy")),
+        ]);
+        let base: upstream::CardContent = serde_json::from_str(&fork.lineage[1].base).unwrap();
+        assert_eq!(base, upstream::CardContent::new(&vocabulary, std::slice::from_ref(&note_type)));
+        assert_eq!(base.note_type, "Vocabulary");
+
+        // A pull right after the fork finds nothing to change
+        assert_eq!(upstream::CardContent::new(&fork.cards[1], &fork.note_types), base);
+    }
 }
 
 /*
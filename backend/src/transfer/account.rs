@@ -0,0 +1,529 @@
+//! ## Account archives
+//!
+//! Everything stored about a user in one versioned JSON document: the profile without the password hash,
+//! own stacks with their note types and cards, card states and review history, study sessions and quiz attempts.
+//!
+//! Restoring adds the stacks as new ones with new ids and keeps the study history of their cards.
+//! History of cards in stacks of other users is kept when those cards exist, study sessions and quiz attempts are not restored.
+
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::{models, notes, scheduler, utils};
+use super::{ImportedStack, ImportedSettings, ImportedNoteType, ImportedCard, ImportedReview, Warning};
+
+pub const FORMAT: &str = "flashcard-account";
+/// Raised whenever the archive changes, older versions have to stay readable
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Archive {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: i64,
+    pub profile: ArchivedProfile,
+    pub stacks: Vec<ArchivedStack>,
+    /// Of every card the user studied, including cards in stacks of other users
+    pub card_states: Vec<ArchivedCardState>,
+    pub reviews: Vec<ArchivedReview>,
+    pub study_sessions: Vec<ArchivedStudySession>,
+    pub quiz_attempts: Vec<ArchivedQuizAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedProfile {
+    pub unique_id: String,
+    pub email: String,
+    pub username: String,
+    pub date_of_registration: i64,
+    pub country: String,
+    pub scheduler: String,
+    pub fsrs_parameters: String,
+    pub new_cards_per_day: i32,
+    pub reviews_per_day: i32,
+}
+
+impl From<&models::User> for ArchivedProfile {
+    fn from(val: &models::User) -> Self {
+        ArchivedProfile {
+            unique_id: val.unique_id.clone(),
+            email: val.email.clone(),
+            username: val.username.clone(),
+            date_of_registration: val.date_of_registration,
+            country: val.country.clone(),
+            scheduler: val.scheduler.clone(),
+            fsrs_parameters: val.fsrs_parameters.clone(),
+            new_cards_per_day: val.new_cards_per_day,
+            reviews_per_day: val.reviews_per_day,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedStack {
+    pub unique_id: String,
+    pub name: String,
+    pub visibility: bool,
+    pub tags: String,
+    pub learning_steps: String,
+    pub relearning_steps: String,
+    pub leech_threshold: i32,
+    pub leech_suspend: bool,
+    pub scheduler: String,
+    pub leitner_intervals: String,
+    pub directions: String,
+    pub note_types: Vec<ArchivedNoteType>,
+    pub cards: Vec<ArchivedCard>,
+}
+
+impl ArchivedStack {
+    /// Stack with those of the note types and cards that belong to it
+    pub fn new(stack: &models::StackFull, note_types: &[models::NoteType], cards: &[models::Card]) -> Self {
+        ArchivedStack {
+            unique_id: stack.unique_id.clone(),
+            name: stack.name.clone(),
+            visibility: stack.visibility,
+            tags: stack.tags.clone(),
+            learning_steps: stack.learning_steps.clone(),
+            relearning_steps: stack.relearning_steps.clone(),
+            leech_threshold: stack.leech_threshold,
+            leech_suspend: stack.leech_suspend,
+            scheduler: stack.scheduler.clone(),
+            leitner_intervals: stack.leitner_intervals.clone(),
+            directions: stack.directions.clone(),
+            note_types: note_types.iter().filter(|note_type| note_type.stack_id == stack.unique_id).map(|note_type| ArchivedNoteType {
+                unique_id: note_type.unique_id.clone(),
+                name: note_type.name.clone(),
+                fields: note_type.field_names(),
+                templates: note_type.template_list(),
+            }).collect(),
+            cards: cards.iter().filter(|card| card.stack_id == stack.unique_id).map(|card| ArchivedCard {
+                unique_id: card.unique_id.clone(),
+                card_type: card.card_type.clone(),
+                directions: card.directions.clone(),
+                note_type_id: card.note_type_id.clone(),
+                frontside: card.frontside.clone(),
+                backside: card.backside.clone(),
+                fields: if card.note_type_id == notes::BASIC_NOTE_TYPE_ID { notes::Fields::new() } else { card.field_values() },
+            }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedNoteType {
+    pub unique_id: String,
+    pub name: String,
+    pub fields: Vec<String>,
+    pub templates: Vec<notes::Template>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedCard {
+    pub unique_id: String,
+    pub card_type: String,
+    pub directions: String,
+    /// Empty for the built-in Basic note type
+    pub note_type_id: String,
+    pub frontside: String,
+    pub backside: String,
+    /// Only set for cards of other note types
+    pub fields: notes::Fields,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedCardState {
+    pub card_id: String,
+    pub ordinal: i32,
+    pub direction: String,
+    pub ease_factor: f64,
+    pub interval_days: i32,
+    pub repetitions: i32,
+    pub due: i64,
+    pub stability: f64,
+    pub difficulty: f64,
+    pub last_review: i64,
+    pub phase: String,
+    pub step: i32,
+    pub lapses: i32,
+    pub leech: bool,
+    pub suspended: bool,
+    pub buried_until: i64,
+}
+
+impl From<models::CardState> for ArchivedCardState {
+    fn from(val: models::CardState) -> Self {
+        ArchivedCardState {
+            card_id: val.card_id,
+            ordinal: val.ordinal,
+            direction: val.direction,
+            ease_factor: val.ease_factor,
+            interval_days: val.interval_days,
+            repetitions: val.repetitions,
+            due: val.due,
+            stability: val.stability,
+            difficulty: val.difficulty,
+            last_review: val.last_review,
+            phase: val.phase,
+            step: val.step,
+            lapses: val.lapses,
+            leech: val.leech,
+            suspended: val.suspended,
+            buried_until: val.buried_until,
+        }
+    }
+}
+
+impl ArchivedCardState {
+    /// State of the user for a card, ex. a card with a new id
+    pub fn to_state(&self, user_id: &str, card_id: &str) -> models::CardState {
+        models::CardState {
+            user_id: user_id.to_owned(),
+            card_id: card_id.to_owned(),
+            ease_factor: self.ease_factor,
+            interval_days: self.interval_days,
+            repetitions: self.repetitions,
+            due: self.due,
+            stability: self.stability,
+            difficulty: self.difficulty,
+            last_review: self.last_review,
+            phase: self.phase.clone(),
+            step: self.step,
+            lapses: self.lapses,
+            leech: self.leech,
+            suspended: self.suspended,
+            buried_until: self.buried_until,
+            ordinal: self.ordinal,
+            direction: self.direction.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedReview {
+    pub card_id: String,
+    pub stack_id: String,
+    pub reviewed_at: i64,
+    pub grade: i32,
+    pub previous_interval: i32,
+    pub next_interval: i32,
+    pub time_taken: i32,
+    pub phase: String,
+    pub ordinal: i32,
+    pub direction: String,
+}
+
+impl From<models::ReviewLogFull> for ArchivedReview {
+    fn from(val: models::ReviewLogFull) -> Self {
+        ArchivedReview {
+            card_id: val.card_id,
+            stack_id: val.stack_id,
+            reviewed_at: val.reviewed_at,
+            grade: val.grade,
+            previous_interval: val.previous_interval,
+            next_interval: val.next_interval,
+            time_taken: val.time_taken,
+            phase: val.phase,
+            ordinal: val.ordinal,
+            direction: val.direction,
+        }
+    }
+}
+
+impl ArchivedReview {
+    fn to_imported(&self) -> ImportedReview {
+        ImportedReview {
+            ordinal: self.ordinal,
+            direction: self.direction.clone(),
+            reviewed_at: self.reviewed_at,
+            grade: self.grade,
+            previous_interval: self.previous_interval,
+            next_interval: self.next_interval,
+            time_taken: self.time_taken,
+            phase: self.phase.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedStudySession {
+    pub unique_id: String,
+    pub stack_id: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub queue: String,
+    pub new_count: i32,
+    pub position: i32,
+    pub answered_count: i32,
+    pub correct_count: i32,
+    pub mode: String,
+}
+
+impl From<models::StudySession> for ArchivedStudySession {
+    fn from(val: models::StudySession) -> Self {
+        ArchivedStudySession {
+            unique_id: val.unique_id,
+            stack_id: val.stack_id,
+            started_at: val.started_at,
+            finished_at: val.finished_at,
+            queue: val.queue,
+            new_count: val.new_count,
+            position: val.position,
+            answered_count: val.answered_count,
+            correct_count: val.correct_count,
+            mode: val.mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedQuizAttempt {
+    pub unique_id: String,
+    pub stack_id: String,
+    pub kind: String,
+    pub seed: u32,
+    pub questions: String,
+    pub answers: Option<String>,
+    pub score: Option<i32>,
+    pub question_count: i32,
+    pub max_score: i32,
+    pub created_at: i64,
+    pub submitted_at: Option<i64>,
+}
+
+impl From<models::QuizAttempt> for ArchivedQuizAttempt {
+    fn from(val: models::QuizAttempt) -> Self {
+        ArchivedQuizAttempt {
+            unique_id: val.unique_id,
+            stack_id: val.stack_id,
+            kind: val.kind,
+            seed: val.seed,
+            questions: val.questions,
+            answers: val.answers,
+            score: val.score,
+            question_count: val.question_count,
+            max_score: val.max_score,
+            created_at: val.created_at,
+            submitted_at: val.submitted_at,
+        }
+    }
+}
+
+/// What an archive restores into an account
+#[derive(Debug, Clone)]
+pub struct Restored {
+    pub stacks: Vec<ImportedStack>,
+    /// States of cards that aren't in the archive, the user id is empty
+    pub other_states: Vec<models::CardState>,
+    /// Reviews of cards that aren't in the archive
+    pub other_reviews: Vec<ArchivedReview>,
+    pub warnings: Vec<Warning>,
+}
+
+/// ## Turns an archive into stacks to import and applies the profile settings to the user.
+///
+/// Email, username and registration date stay the ones of the account.
+/// Settings, stacks and cards that the endpoints wouldn't accept are left out with a warning.
+///
+/// ### Arguments
+///
+/// * `archive` - Archive
+/// * `user` - &mut models::User, account the archive is restored into
+///
+/// ### Returns
+/// What to store or None if the archive isn't one this version can read
+pub fn restore(archive: Archive, user: &mut models::User) -> Option<Restored> {
+    if archive.format != FORMAT || archive.version == 0 || archive.version > VERSION {
+        return None
+    }
+
+    let mut warnings: Vec<Warning> = Vec::new();
+    restore_profile(&archive.profile, user, &mut warnings);
+
+    let mut card_states: Vec<ArchivedCardState> = archive.card_states;
+    card_states.retain(|state| is_valid_history(&state.phase, &state.direction));
+    let mut reviews: Vec<ArchivedReview> = archive.reviews;
+    reviews.retain(|review| is_valid_history(&review.phase, &review.direction) && (0..=5).contains(&review.grade));
+
+    // History by card, so every card only looks at its own
+    let mut reviews_of: HashMap<&str, Vec<&ArchivedReview>> = HashMap::new();
+    for review in &reviews {
+        reviews_of.entry(review.card_id.as_str()).or_default().push(review);
+    }
+    let mut states_of: HashMap<&str, Vec<&ArchivedCardState>> = HashMap::new();
+    for state in &card_states {
+        states_of.entry(state.card_id.as_str()).or_default().push(state);
+    }
+
+    let mut stacks: Vec<ImportedStack> = Vec::with_capacity(archive.stacks.len());
+    let mut archived_cards: HashSet<String> = HashSet::new();
+    for stack in archive.stacks {
+        let item = format!("stack {}", stack.unique_id);
+        let settings = ImportedSettings {
+            learning_steps: stack.learning_steps,
+            relearning_steps: stack.relearning_steps,
+            leech_threshold: stack.leech_threshold,
+            leech_suspend: stack.leech_suspend,
+            scheduler: stack.scheduler,
+            leitner_intervals: stack.leitner_intervals,
+            directions: stack.directions,
+        };
+        let settings = if settings.is_valid() {
+            settings
+        } else {
+            warnings.push(Warning::new(&item, "invalid study settings, the defaults are used"));
+            ImportedSettings::default()
+        };
+
+        let tags: Vec<String> = stack.tags.split(',').map(str::to_owned).collect();
+        let (tags, left_out) = super::stack_tags(&tags);
+        if left_out > 0 {
+            warnings.push(Warning::new(&item, &format!("{} tags left out, stacks have up to 10 tags of up to 20 characters", left_out)));
+        }
+
+        let note_type_ids: Vec<String> = stack.note_types.iter().map(|note_type| note_type.unique_id.clone()).collect();
+        let note_types: Vec<ImportedNoteType> = stack.note_types.into_iter().map(|note_type| ImportedNoteType {
+            name: note_type.name,
+            field_names: note_type.fields,
+            templates: note_type.templates,
+        }).collect();
+
+        let mut cards: Vec<ImportedCard> = Vec::with_capacity(stack.cards.len());
+        for card in stack.cards {
+            archived_cards.insert(card.unique_id.clone());
+            let item = format!("card {}", card.unique_id);
+            let note_type = if card.note_type_id == notes::BASIC_NOTE_TYPE_ID {
+                None
+            } else {
+                match note_type_ids.iter().position(|id| *id == card.note_type_id) {
+                    Some(index) if is_valid_note_type(&note_types[index]) => Some(index),
+                    _ => {
+                        warnings.push(Warning::new(&item, "note type is missing or invalid"));
+                        continue;
+                    }
+                }
+            };
+
+            let imported = ImportedCard {
+                card_type: card.card_type,
+                directions: card.directions,
+                note_type,
+                frontside: if note_type.is_none() { card.frontside } else { String::new() },
+                backside: if note_type.is_none() { card.backside } else { String::new() },
+                fields: card.fields,
+                reviews: reviews_of.get(card.unique_id.as_str())
+                    .map(|reviews| reviews.iter().map(|review| review.to_imported()).collect())
+                    .unwrap_or_default(),
+                states: states_of.get(card.unique_id.as_str())
+                    .map(|states| states.iter().map(|state| state.to_state("", "")).collect())
+                    .unwrap_or_default(),
+            };
+            if let Err(message) = super::check_card(&imported, &note_types) {
+                warnings.push(Warning::new(&item, message));
+                continue;
+            }
+            cards.push(imported);
+        }
+
+        stacks.push(ImportedStack {
+            name: super::stack_name(&stack.name),
+            tags,
+            visibility: stack.visibility,
+            settings,
+            note_types,
+            cards,
+        });
+    }
+
+    // History of left out cards isn't kept either
+    let is_other = |card_id: &String| ! archived_cards.contains(card_id);
+    Some(Restored {
+        stacks,
+        other_states: card_states.iter().filter(|state| is_other(&state.card_id)).map(|state| state.to_state("", &state.card_id)).collect(),
+        other_reviews: reviews.into_iter().filter(|review| is_other(&review.card_id)).collect(),
+        warnings,
+    })
+}
+
+/// ## Leaves out history of cards outside of the archive that the account already has.
+///
+/// An archived state only replaces a state of the account that was reviewed earlier, so newer progress is kept.
+/// Reviews with the same card, time, ordinal and direction as a logged one are skipped,
+/// restoring the same archive again doesn't duplicate the history.
+///
+/// ### Arguments
+///
+/// * `states` - Vec<models::CardState>, archived states
+/// * `reviews` - Vec<ArchivedReview>, archived reviews
+/// * `existing_states` - &[models::CardState], states of the account
+/// * `existing_logs` - &[models::ReviewLogFull], review log of the account
+///
+/// ### Returns
+/// The states and reviews to store
+pub fn new_history(
+    states: Vec<models::CardState>,
+    reviews: Vec<ArchivedReview>,
+    existing_states: &[models::CardState],
+    existing_logs: &[models::ReviewLogFull],
+) -> (Vec<models::CardState>, Vec<ArchivedReview>) {
+    let last_reviews: HashMap<(&str, i32, &str), i64> = existing_states.iter()
+        .map(|state| ((state.card_id.as_str(), state.ordinal, state.direction.as_str()), state.last_review))
+        .collect();
+    let states = states.into_iter()
+        .filter(|state| last_reviews.get(&(state.card_id.as_str(), state.ordinal, state.direction.as_str()))
+            .map_or(true, |last_review| state.last_review > *last_review))
+        .collect();
+
+    let logged: HashSet<(&str, i64, i32, &str)> = existing_logs.iter()
+        .map(|log| (log.card_id.as_str(), log.reviewed_at, log.ordinal, log.direction.as_str()))
+        .collect();
+    let reviews = reviews.into_iter()
+        .filter(|review| ! logged.contains(&(review.card_id.as_str(), review.reviewed_at, review.ordinal, review.direction.as_str())))
+        .collect();
+    (states, reviews)
+}
+
+fn restore_profile(profile: &ArchivedProfile, user: &mut models::User, warnings: &mut Vec<Warning>) {
+    let mut invalid: Vec<&str> = Vec::new();
+
+    if utils::is_valid_country_code(&profile.country) {
+        user.country = profile.country.clone();
+    } else {
+        invalid.push("country");
+    }
+    if scheduler::is_valid_scheduler_name(&profile.scheduler) {
+        user.scheduler = profile.scheduler.clone();
+    } else {
+        invalid.push("scheduler");
+    }
+    if profile.fsrs_parameters.is_empty() || scheduler::fsrs::parse_parameters(&profile.fsrs_parameters).is_some() {
+        user.fsrs_parameters = profile.fsrs_parameters.clone();
+    } else {
+        invalid.push("fsrs_parameters");
+    }
+    if utils::is_valid_daily_limit(profile.new_cards_per_day) {
+        user.new_cards_per_day = profile.new_cards_per_day;
+    } else {
+        invalid.push("new_cards_per_day");
+    }
+    if utils::is_valid_daily_limit(profile.reviews_per_day) {
+        user.reviews_per_day = profile.reviews_per_day;
+    } else {
+        invalid.push("reviews_per_day");
+    }
+
+    for name in invalid {
+        warnings.push(Warning::new("profile", &format!("invalid {}, the one of the account is kept", name)));
+    }
+}
+
+fn is_valid_note_type(note_type: &ImportedNoteType) -> bool {
+    notes::is_valid_name(&note_type.name) &&
+        notes::is_valid_field_names(&note_type.field_names) &&
+        notes::is_valid_templates(&note_type.templates, &note_type.field_names)
+}
+
+fn is_valid_history(phase: &str, direction: &str) -> bool {
+    use scheduler::steps::{PHASE_NEW, PHASE_LEARNING, PHASE_REVIEW, PHASE_RELEARNING};
+    matches!(phase, PHASE_NEW | PHASE_LEARNING | PHASE_REVIEW | PHASE_RELEARNING) &&
+        matches!(direction, scheduler::DIRECTION_FORWARD | scheduler::DIRECTION_BACKWARD)
+}
@@ -0,0 +1,747 @@
+//! ## Anki packages
+//!
+//! An `.apkg` is a zip with the collection (a SQLite database) in `collection.anki21` or `collection.anki2`,
+//! a `media` file naming the numbered media entries and the media files themselves.
+//! Only the legacy collection format is read, Anki 2.1.50+ has to export with "Support older Anki versions" checked.
+//!
+//! Decks become stacks and notes become cards:
+//! - notes of the Basic note type become basic cards, the reversed variant is studied in both directions
+//! - cloze notes become cloze cards with the first field as the text and the second one as the extra notes
+//! - notes of other note types become cards of a note type of the stack with the same fields and templates
+//!
+//! Fields and templates are HTML in Anki and are turned into plain text, media is left out.
+//!
+//! Exported packages have one deck, stack tags are put on every note and card ids become note guids
+//! so importing a newer export of the same stack updates the notes in Anki. Cards are exported as new.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use crate::{utils, models, notes, cloze, scheduler};
+use super::{Warning, ImportedStack, ImportedNoteType, ImportedCard, ImportedReview};
+
+/// Newest first, a package has one of them
+const COLLECTION_FILES: [&str; 2] = ["collection.anki21", "collection.anki2"];
+/// Collection of Anki 2.1.50+, packages with it only have a placeholder in the legacy files
+const LATEST_COLLECTION_FILE: &str = "collection.anki21b";
+
+/// Written by exports, read by every version of Anki
+const LEGACY_COLLECTION_FILE: &str = "collection.anki2";
+const MEDIA_FILE: &str = "media";
+
+/// Largest unpacked collection that is read, bigger ones are rejected instead of filling the memory
+const MAX_COLLECTION_SIZE: u64 = 256 * 1024 * 1024;
+
+const FIELD_SEPARATOR: char = '\x1f';
+const MODEL_TYPE_STANDARD: i64 = 0;
+const MODEL_TYPE_CLOZE: i64 = 1;
+const DEFAULT_DECK_ID: i64 = 1;
+
+/// Collection schema of Anki 2.1.28 and older
+const COLLECTION_SCHEMA: &str = "
+    CREATE TABLE col (
+        id integer primary key, crt integer not null, mod integer not null, scm integer not null, ver integer not null,
+        dty integer not null, usn integer not null, ls integer not null, conf text not null, models text not null,
+        decks text not null, dconf text not null, tags text not null
+    );
+    CREATE TABLE notes (
+        id integer primary key, guid text not null, mid integer not null, mod integer not null, usn integer not null,
+        tags text not null, flds text not null, sfld integer not null, csum integer not null, flags integer not null, data text not null
+    );
+    CREATE TABLE cards (
+        id integer primary key, nid integer not null, did integer not null, ord integer not null, mod integer not null,
+        usn integer not null, type integer not null, queue integer not null, due integer not null, ivl integer not null,
+        factor integer not null, reps integer not null, lapses integer not null, left integer not null, odue integer not null,
+        odid integer not null, flags integer not null, data text not null
+    );
+    CREATE TABLE revlog (
+        id integer primary key, cid integer not null, usn integer not null, ease integer not null, ivl integer not null,
+        lastIvl integer not null, factor integer not null, time integer not null, type integer not null
+    );
+    CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+    CREATE INDEX ix_notes_usn on notes (usn);
+    CREATE INDEX ix_cards_usn on cards (usn);
+    CREATE INDEX ix_revlog_usn on revlog (usn);
+    CREATE INDEX ix_cards_nid on cards (nid);
+    CREATE INDEX ix_cards_sched on cards (did, queue, due);
+    CREATE INDEX ix_revlog_cid on revlog (cid);
+    CREATE INDEX ix_notes_csum on notes (csum);
+";
+
+const CARD_CSS: &str = ".card {\n font-family: arial;\n font-size: 20px;\n text-align: center;\n color: black;\n background-color: white;\n}\n";
+const LATEX_PRE: &str = "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n";
+const LATEX_POST: &str = "\\end{document}";
+
+#[derive(Debug, Deserialize)]
+struct Model {
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: i64,
+    flds: Vec<ModelField>,
+    tmpls: Vec<ModelTemplate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelField {
+    name: String,
+    #[serde(default)]
+    ord: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelTemplate {
+    name: String,
+    qfmt: String,
+    afmt: String,
+    #[serde(default)]
+    ord: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Deck {
+    name: String,
+}
+
+/// How notes of an Anki note type are imported
+#[derive(Debug, Clone, PartialEq)]
+enum Layout {
+    /// Directions of the cards, empty follows the stack
+    Basic(&'static str),
+    Cloze,
+    NoteType(ImportedNoteType),
+    Unsupported,
+}
+
+/// Copy of the collection on disk as SQLite can only open files, removed once dropped
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn new(content: &[u8]) -> Option<Self> {
+        let path = std::env::temp_dir().join(format!("apkg-{:016x}.sqlite", utils::random_seed()));
+        std::fs::write(&path, content).ok()?;
+        Some(TempFile { path })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+static BREAK_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<\s*(br|hr|/div|/p|/li|/tr|/h\d)\b[^>]*>").unwrap());
+static HTML_TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^<>]*>").unwrap());
+static SOUND_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[sound:[^\]]*\]").unwrap());
+static TEMPLATE_TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{([#^/]?)\s*([^{}]*?)\s*\}\}").unwrap());
+
+/// Plain text of an Anki field or template, line breaks are kept and media references left out
+pub fn html_to_text(html: &str) -> String {
+    let text = BREAK_REGEX.replace_all(html, "\n");
+    let text = HTML_TAG_REGEX.replace_all(&text, "");
+    let text = SOUND_REGEX.replace_all(&text, "");
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    // At most one empty line in a row
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(|line| line.trim()) {
+        if line.is_empty() && matches!(lines.last(), None | Some(&"")) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim().to_owned()
+}
+
+fn has_media(html: &str) -> bool {
+    html.to_lowercase().contains("<img") || html.contains("[sound:")
+}
+
+/// ## Turns an Anki template into a template of the `notes` module.
+///
+/// Filters like `{{text:Field}}` are dropped, type-in boxes and special fields like `{{Tags}}` are left out.
+///
+/// ### Arguments
+///
+/// * `template` - &str, Anki template
+/// * `field_names` - &[String], fields of the note type
+pub fn convert_template(template: &str, field_names: &[String]) -> String {
+    let converted = TEMPLATE_TAG_REGEX.replace_all(template, |captures: &Captures| {
+        let sign = &captures[1];
+        if ! sign.is_empty() {
+            return format!("{{{{{}{}}}}}", sign, &captures[2]);
+        }
+
+        let name = match captures[2].rsplit_once(':') {
+            Some((filters, _)) if filters.split(':').any(|filter| filter.trim() == "type") => return String::new(),
+            Some((_, name)) => name.trim(),
+            None => &captures[2],
+        };
+        if name == notes::FRONT_SIDE || field_names.iter().any(|field| field == name) {
+            format!("{{{{{}}}}}", name)
+        } else {
+            String::new()
+        }
+    });
+    html_to_text(&converted)
+}
+
+fn layout(model: &Model) -> Layout {
+    if model.kind == MODEL_TYPE_CLOZE {
+        return Layout::Cloze;
+    }
+
+    let mut fields: Vec<&ModelField> = model.flds.iter().collect();
+    fields.sort_by_key(|field| field.ord);
+    let field_names: Vec<String> = fields.iter().map(|field| field.name.clone()).collect();
+
+    let mut templates: Vec<&ModelTemplate> = model.tmpls.iter().collect();
+    templates.sort_by_key(|template| template.ord);
+    let templates: Vec<notes::Template> = templates.iter().map(|template| notes::Template {
+        name: template.name.trim().to_owned(),
+        front: convert_template(&template.qfmt, &field_names),
+        back: convert_template(&template.afmt, &field_names),
+    }).collect();
+
+    // Front field on the front, back field on the back, in the other order for the reversed card
+    if field_names.len() == 2 {
+        let field = |i: usize| format!("{{{{{}}}}}", field_names[i]);
+        let shows = |template: &notes::Template, front: usize, back: usize| {
+            template.front == field(front) && template.back.replace(&format!("{{{{{}}}}}", notes::FRONT_SIDE), "").trim() == field(back)
+        };
+
+        match templates.as_slice() {
+            [forward] if shows(forward, 0, 1) => return Layout::Basic(""),
+            [forward, backward] if shows(forward, 0, 1) && shows(backward, 1, 0) => return Layout::Basic(scheduler::DIRECTIONS_BOTH),
+            _ => {}
+        }
+    }
+
+    let name: String = model.name.chars().take(notes::MAX_NAME_LENGTH).collect();
+    if ! notes::is_valid_name(&name) || ! notes::is_valid_field_names(&field_names) || ! notes::is_valid_templates(&templates, &field_names) {
+        return Layout::Unsupported;
+    }
+    Layout::NoteType(ImportedNoteType {
+        name: name.trim().to_owned(),
+        field_names,
+        templates,
+    })
+}
+
+/// Ordinal and direction of the sub-card an Anki card becomes
+fn sub_card(layout: &Layout, ord: i64) -> (i32, &'static str) {
+    match layout {
+        Layout::Basic(_) if ord == 1 => (0, scheduler::DIRECTION_BACKWARD),
+        Layout::Cloze => (ord as i32 + 1, scheduler::DIRECTION_FORWARD),
+        Layout::NoteType(_) => (ord as i32, scheduler::DIRECTION_FORWARD),
+        _ => (0, scheduler::DIRECTION_FORWARD),
+    }
+}
+
+/// Anki answers with 1 (again) - 4 (easy), the inverse of `scheduler::fsrs::grade_to_rating`
+fn ease_to_grade(ease: i64) -> Option<i32> {
+    match ease {
+        1 => Some(1),
+        2 => Some(3),
+        3 => Some(4),
+        4 => Some(5),
+        // Manual rescheduling
+        _ => None,
+    }
+}
+
+/// ## Reads an Anki package.
+///
+/// ### Arguments
+///
+/// * `file` - &[u8], content of the `.apkg`
+/// * `include_history` - bool, if the review log of the cards is imported as well
+///
+/// ### Returns
+/// Stacks with their cards and warnings about what was left out,
+/// None if the file is not a package, only has the collection format of Anki 2.1.50+
+/// or its collection unpacks to more than `MAX_COLLECTION_SIZE` bytes
+pub fn parse(file: &[u8], include_history: bool) -> Option<(Vec<ImportedStack>, Vec<Warning>)> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(file)).ok()?;
+    if archive.by_name(LATEST_COLLECTION_FILE).is_ok() {
+        return None;
+    }
+
+    let name = COLLECTION_FILES.iter().find(|name| archive.by_name(name).is_ok())?;
+    let entry = archive.by_name(name).ok()?;
+    if entry.size() > MAX_COLLECTION_SIZE {
+        return None;
+    }
+
+    // The size in the archive can be made up, so reading stops right after the limit
+    let mut collection = Vec::new();
+    entry.take(MAX_COLLECTION_SIZE + 1).read_to_end(&mut collection).ok()?;
+    if collection.len() as u64 > MAX_COLLECTION_SIZE {
+        return None;
+    }
+
+    let temp_file = TempFile::new(&collection)?;
+    let conn = rusqlite::Connection::open_with_flags(&temp_file.path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    read_collection(&conn, include_history).ok()
+}
+
+fn read_collection(conn: &rusqlite::Connection, include_history: bool) -> rusqlite::Result<(Vec<ImportedStack>, Vec<Warning>)> {
+    let (models, decks): (String, String) = conn.query_row("SELECT models, decks FROM col", [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let models: HashMap<String, Model> = serde_json::from_str(&models).unwrap_or_default();
+    let decks: HashMap<String, Deck> = serde_json::from_str(&decks).unwrap_or_default();
+    let layouts: HashMap<String, Layout> = models.iter().map(|(id, model)| (id.clone(), layout(model))).collect();
+
+    // Id, deck and template of the cards of every note
+    let mut note_cards: HashMap<i64, Vec<(i64, i64, i64)>> = HashMap::new();
+    let mut statement = conn.prepare("SELECT id, nid, did, ord FROM cards ORDER BY ord")?;
+    let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?)))?;
+    for row in rows {
+        let (card_id, note_id, deck_id, ord) = row?;
+        note_cards.entry(note_id).or_default().push((card_id, deck_id, ord));
+    }
+
+    let mut stacks: Vec<ImportedStack> = Vec::new();
+    let mut tags: Vec<Vec<String>> = Vec::new();
+    let mut warnings: Vec<Warning> = Vec::new();
+    let mut stack_of_deck: HashMap<i64, usize> = HashMap::new();
+    // (stack, model id) -> note type of the stack
+    let mut note_type_of: HashMap<(usize, String), usize> = HashMap::new();
+    // Anki card id -> (stack, card, ordinal, direction)
+    let mut sub_card_of: HashMap<i64, (usize, usize, i32, &'static str)> = HashMap::new();
+    // Model id -> left out notes
+    let mut unsupported: BTreeMap<String, usize> = BTreeMap::new();
+
+    let mut statement = conn.prepare("SELECT id, mid, tags, flds FROM notes ORDER BY id")?;
+    let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?)))?;
+    for row in rows {
+        let (note_id, model_id, note_tags, note_fields) = row?;
+        let model_id = model_id.to_string();
+        let item = format!("note {}", note_id);
+
+        let cards = match note_cards.get(&note_id) {
+            Some(value) => value,
+            None => continue,
+        };
+        let layout = match layouts.get(&model_id) {
+            Some(Layout::Unsupported) => {
+                *unsupported.entry(model_id).or_default() += 1;
+                continue;
+            }
+            Some(value) => value,
+            None => {
+                warnings.push(Warning::new(&item, "note type is missing"));
+                continue;
+            }
+        };
+
+        let values: Vec<String> = note_fields.split(FIELD_SEPARATOR).map(html_to_text).collect();
+        let value = |i: usize| values.get(i).cloned().unwrap_or_default();
+
+        let mut card = match layout {
+            Layout::Basic(directions) => ImportedCard {
+                directions: directions.to_string(),
+                ..ImportedCard::basic(cloze::CARD_TYPE_BASIC, &value(0), &value(1))
+            },
+            Layout::Cloze => ImportedCard::basic(cloze::CARD_TYPE_CLOZE, &value(0), &value(1)),
+            Layout::NoteType(note_type) => ImportedCard {
+                // Empty fields are left out like the ones a card of the note type was created without
+                fields: note_type.field_names.iter().cloned().zip(values.iter().cloned()).filter(|(_, value)| ! value.is_empty()).collect(),
+                ..ImportedCard::basic(cloze::CARD_TYPE_BASIC, "", "")
+            },
+            Layout::Unsupported => continue,
+        };
+
+        // Cards of a note can be in several decks, the note goes to the deck of its first card
+        let deck_id = cards[0].1;
+        let stack = *stack_of_deck.entry(deck_id).or_insert_with(|| {
+            let name = decks.get(&deck_id.to_string()).and_then(|deck| deck.name.rsplit("::").next()).unwrap_or("");
+            stacks.push(ImportedStack {
+                name: super::stack_name(name),
+                tags: String::new(),
+                visibility: false,
+                settings: super::ImportedSettings::default(),
+                note_types: Vec::new(),
+                cards: Vec::new(),
+            });
+            tags.push(Vec::new());
+            stacks.len() - 1
+        });
+
+        if let Layout::NoteType(note_type) = layout {
+            let index = *note_type_of.entry((stack, model_id)).or_insert_with(|| {
+                stacks[stack].note_types.push(note_type.clone());
+                stacks[stack].note_types.len() - 1
+            });
+            card.note_type = Some(index);
+        }
+
+        if let Err(message) = super::check_card(&card, &stacks[stack].note_types) {
+            warnings.push(Warning::new(&item, message));
+            continue;
+        }
+        if has_media(&note_fields) {
+            warnings.push(Warning::new(&item, "media is not imported"));
+        }
+
+        for (card_id, _, ord) in cards {
+            let (ordinal, direction) = sub_card(layout, *ord);
+            sub_card_of.insert(*card_id, (stack, stacks[stack].cards.len(), ordinal, direction));
+        }
+        stacks[stack].cards.push(card);
+        tags[stack].extend(note_tags.split_whitespace().map(|tag| tag.to_owned()));
+    }
+
+    for (model_id, count) in unsupported {
+        warnings.push(Warning::new(&models[&model_id].name, &format!("unsupported note type, {} notes left out", count)));
+    }
+
+    for (stack, tags) in stacks.iter_mut().zip(tags) {
+        let (joined, left_out) = super::stack_tags(&tags);
+        stack.tags = joined;
+        if left_out > 0 {
+            warnings.push(Warning::new(&stack.name, &format!("{} tags left out, stacks have up to 10 tags of up to 20 characters", left_out)));
+        }
+    }
+
+    if include_history {
+        let mut reviewed: HashSet<i64> = HashSet::new();
+        let mut statement = conn.prepare("SELECT id, cid, ease, ivl, lastIvl, time, type FROM revlog ORDER BY id")?;
+        let rows = statement.query_map([], |row| Ok((
+            row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?, row.get::<_, i64>(3)?,
+            row.get::<_, i64>(4)?, row.get::<_, i64>(5)?, row.get::<_, i64>(6)?,
+        )))?;
+
+        for row in rows {
+            let (review_id, card_id, ease, interval, last_interval, time, kind) = row?;
+            let (stack, card, ordinal, direction) = match sub_card_of.get(&card_id) {
+                Some(value) => *value,
+                None => continue,
+            };
+            let grade = match ease_to_grade(ease) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            // Negative intervals are learning steps in seconds
+            let phase = match kind {
+                0 if ! reviewed.contains(&card_id) => scheduler::steps::PHASE_NEW,
+                0 => scheduler::steps::PHASE_LEARNING,
+                2 => scheduler::steps::PHASE_RELEARNING,
+                _ => scheduler::steps::PHASE_REVIEW,
+            };
+            reviewed.insert(card_id);
+
+            stacks[stack].cards[card].reviews.push(ImportedReview {
+                ordinal,
+                direction: direction.to_owned(),
+                reviewed_at: review_id / 1000,
+                grade,
+                previous_interval: last_interval.clamp(0, i32::MAX as i64) as i32,
+                next_interval: interval.clamp(0, i32::MAX as i64) as i32,
+                time_taken: time.clamp(0, i32::MAX as i64) as i32,
+                phase: phase.to_owned(),
+            });
+        }
+    }
+
+    Ok((stacks, warnings))
+}
+
+/// HTML of a plain text field or template, the inverse of `html_to_text`
+pub fn text_to_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>")
+}
+
+/// First 8 hex digits of the SHA1 of the sort field, Anki uses it to find duplicates
+fn checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(field.as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as i64
+}
+
+/// Legacy note type, templates are HTML
+fn model_json(id: i64, name: &str, kind: i64, deck_id: i64, field_names: &[String], templates: &[notes::Template], now: i64) -> serde_json::Value {
+    json!({
+        "id": id,
+        "name": name,
+        "type": kind,
+        "mod": now,
+        "usn": -1,
+        "sortf": 0,
+        "did": deck_id,
+        "tmpls": templates.iter().enumerate().map(|(i, template)| json!({
+            "name": template.name,
+            "ord": i,
+            "qfmt": template.front,
+            "afmt": template.back,
+            "did": null,
+            "bqfmt": "",
+            "bafmt": "",
+        })).collect::<Vec<serde_json::Value>>(),
+        "flds": field_names.iter().enumerate().map(|(i, name)| json!({
+            "name": name,
+            "ord": i,
+            "sticky": false,
+            "rtl": false,
+            "font": "Arial",
+            "size": 20,
+            "media": [],
+        })).collect::<Vec<serde_json::Value>>(),
+        "css": CARD_CSS,
+        "latexPre": LATEX_PRE,
+        "latexPost": LATEX_POST,
+        "latexsvg": false,
+        "req": (0..templates.len()).map(|i| json!([i, "any", (0..field_names.len()).collect::<Vec<usize>>()])).collect::<Vec<serde_json::Value>>(),
+        "tags": [],
+        "vers": [],
+    })
+}
+
+fn deck_json(id: i64, name: &str, now: i64) -> serde_json::Value {
+    json!({
+        "id": id,
+        "name": name,
+        "mod": now,
+        "usn": -1,
+        "lrnToday": [0, 0],
+        "revToday": [0, 0],
+        "newToday": [0, 0],
+        "timeToday": [0, 0],
+        "collapsed": false,
+        "browserCollapsed": false,
+        "desc": "",
+        "dyn": 0,
+        "conf": 1,
+        "extendNew": 0,
+        "extendRev": 0,
+    })
+}
+
+fn deck_config_json() -> serde_json::Value {
+    json!({
+        "1": {
+            "id": 1,
+            "name": "Default",
+            "mod": 0,
+            "usn": 0,
+            "maxTaken": 60,
+            "autoplay": true,
+            "timer": 0,
+            "replayq": true,
+            "dyn": false,
+            "new": { "bury": false, "delays": [1.0, 10.0], "initialFactor": 2500, "ints": [1, 4, 0], "order": 1, "perDay": 20 },
+            "lapse": { "delays": [10.0], "leechAction": 1, "leechFails": 8, "minInt": 1, "mult": 0.0 },
+            "rev": { "bury": false, "ease4": 1.3, "ivlFct": 1.0, "maxIvl": 36500, "perDay": 200, "hardFactor": 1.2 },
+        }
+    })
+}
+
+/// Template of the note type an exported sub-card belongs to
+fn template_ord(sub_card: &models::SubCard, note_type_id: &str) -> i64 {
+    if note_type_id != notes::BASIC_NOTE_TYPE_ID {
+        sub_card.ordinal as i64
+    } else if sub_card.card_type == cloze::CARD_TYPE_CLOZE {
+        sub_card.ordinal as i64 - 1
+    } else if sub_card.direction == scheduler::DIRECTION_BACKWARD {
+        1
+    } else {
+        0
+    }
+}
+
+/// ## Writes a stack into an Anki package.
+///
+/// ### Arguments
+///
+/// * `stack` - &models::StackFull
+/// * `note_types` - &[models::NoteType], of the stack
+/// * `cards` - &[models::Card], of the stack
+/// * `reviews` - &[models::ReviewLogFull], of the cards, reviews of sub-cards that don't exist anymore are left out
+/// * `now` - i64, unix timestamp, ids of the package are based on it
+///
+/// ### Returns
+/// Content of the `.apkg` or None if it couldn't be written
+pub fn write(
+    stack: &models::StackFull,
+    note_types: &[models::NoteType],
+    cards: &[models::Card],
+    reviews: &[models::ReviewLogFull],
+    now: i64,
+) -> Option<Vec<u8>> {
+    let temp_file = TempFile::new(&[])?;
+    let conn = rusqlite::Connection::open(&temp_file.path).ok()?;
+    write_collection(&conn, stack, note_types, cards, reviews, now).ok()?;
+    drop(conn);
+    let collection = std::fs::read(&temp_file.path).ok()?;
+
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut package = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    package.start_file(LEGACY_COLLECTION_FILE, options).ok()?;
+    package.write_all(&collection).ok()?;
+    // Stacks don't have media
+    package.start_file(MEDIA_FILE, options).ok()?;
+    package.write_all(b"{}").ok()?;
+    Some(package.finish().ok()?.into_inner())
+}
+
+fn write_collection(
+    conn: &rusqlite::Connection,
+    stack: &models::StackFull,
+    note_types: &[models::NoteType],
+    cards: &[models::Card],
+    reviews: &[models::ReviewLogFull],
+    now: i64,
+) -> rusqlite::Result<()> {
+    conn.execute_batch(COLLECTION_SCHEMA)?;
+
+    // Ids are millisecond timestamps in Anki
+    let deck_id = now * 1000;
+    let basic_model_id = deck_id + 1;
+    let reversed_model_id = deck_id + 2;
+    let cloze_model_id = deck_id + 3;
+    let note_type_model_id = |i: usize| deck_id + 4 + i as i64;
+
+    let (basic_fields, basic_templates) = notes::basic_note_type();
+    // Anki shows the front above the answer
+    let template = |name: &str, front: &str, back: &str| notes::Template {
+        name: name.to_owned(),
+        front: format!("{{{{{}}}}}", front),
+        back: format!("{{{{{}}}}}<hr id=answer>{{{{{}}}}}", notes::FRONT_SIDE, back),
+    };
+    let basic = template(&basic_templates[0].name, notes::BASIC_FRONT_FIELD, notes::BASIC_BACK_FIELD);
+    let reversed = template("Card 2", notes::BASIC_BACK_FIELD, notes::BASIC_FRONT_FIELD);
+    let cloze_fields = vec!["Text".to_owned(), "Back Extra".to_owned()];
+    let cloze_template = notes::Template {
+        name: "Cloze".to_owned(),
+        front: "{{cloze:Text}}".to_owned(),
+        back: "{{cloze:Text}}<br>\n{{Back Extra}}".to_owned(),
+    };
+
+    let mut models = serde_json::Map::new();
+    models.insert(basic_model_id.to_string(), model_json(basic_model_id, notes::BASIC_NOTE_TYPE_NAME, MODEL_TYPE_STANDARD, deck_id, &basic_fields, std::slice::from_ref(&basic), now));
+    models.insert(reversed_model_id.to_string(), model_json(reversed_model_id, "Basic (and reversed card)", MODEL_TYPE_STANDARD, deck_id, &basic_fields, &[basic, reversed], now));
+    models.insert(cloze_model_id.to_string(), model_json(cloze_model_id, "Cloze", MODEL_TYPE_CLOZE, deck_id, &cloze_fields, &[cloze_template], now));
+    for (i, note_type) in note_types.iter().enumerate() {
+        let templates: Vec<notes::Template> = note_type.template_list().into_iter().map(|template| notes::Template {
+            name: template.name,
+            front: text_to_html(&template.front),
+            back: text_to_html(&template.back),
+        }).collect();
+        models.insert(note_type_model_id(i).to_string(), model_json(note_type_model_id(i), &note_type.name, MODEL_TYPE_STANDARD, deck_id, &note_type.field_names(), &templates, now));
+    }
+
+    let decks = json!({
+        DEFAULT_DECK_ID.to_string(): deck_json(DEFAULT_DECK_ID, "Default", now),
+        deck_id.to_string(): deck_json(deck_id, &stack.name, now),
+    });
+    let conf = json!({
+        "activeDecks": [deck_id],
+        "curDeck": deck_id,
+        "newSpread": 0,
+        "collapseTime": 1200,
+        "timeLim": 0,
+        "estTimes": true,
+        "dueCounts": true,
+        "curModel": null,
+        "nextPos": cards.len() + 1,
+        "sortType": "noteFld",
+        "sortBackwards": false,
+        "addToCur": true,
+    });
+    conn.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        rusqlite::params![now, now * 1000, conf.to_string(), serde_json::Value::Object(models).to_string(), decks.to_string(), deck_config_json().to_string()],
+    )?;
+
+    // Anki tags can't have spaces
+    let tags: Vec<String> = stack.tags.split(',').map(|tag| tag.trim().replace(' ', "_")).filter(|tag| ! tag.is_empty()).collect();
+    let tags = if tags.is_empty() { String::new() } else { format!(" {} ", tags.join(" ")) };
+
+    let layout = models::CardLayout::new(stack, note_types);
+    // (card id, ordinal, direction) -> Anki card id
+    let mut card_ids: HashMap<(&str, i32, String), i64> = HashMap::new();
+    let mut next_card_id = deck_id;
+
+    for (i, card) in cards.iter().enumerate() {
+        let sub_cards = card.sub_cards(&layout);
+        if sub_cards.is_empty() {
+            continue;
+        }
+
+        let (model_id, fields): (i64, Vec<String>) = if card.note_type_id != notes::BASIC_NOTE_TYPE_ID {
+            let index = note_types.iter().position(|note_type| note_type.unique_id == card.note_type_id).unwrap_or_default();
+            let values = card.field_values();
+            (note_type_model_id(index), note_types[index].field_names().iter().map(|name| values.get(name).cloned().unwrap_or_default()).collect())
+        } else if card.card_type == cloze::CARD_TYPE_CLOZE {
+            (cloze_model_id, vec![card.frontside.clone(), card.backside.clone()])
+        } else if sub_cards.len() > 1 {
+            (reversed_model_id, vec![card.frontside.clone(), card.backside.clone()])
+        } else {
+            (basic_model_id, vec![card.frontside.clone(), card.backside.clone()])
+        };
+
+        let note_id = deck_id + i as i64;
+        let flds: Vec<String> = fields.iter().map(|field| text_to_html(field)).collect();
+        conn.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+            rusqlite::params![note_id, card.unique_id, model_id, now, tags, flds.join(&FIELD_SEPARATOR.to_string()), fields[0], checksum(&fields[0])],
+        )?;
+
+        for sub_card in sub_cards {
+            next_card_id += 1;
+            conn.execute(
+                "INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, 0, 0, ?6, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                rusqlite::params![next_card_id, note_id, deck_id, template_ord(&sub_card, &card.note_type_id), now, i as i64 + 1],
+            )?;
+            card_ids.insert((card.unique_id.as_str(), sub_card.ordinal, sub_card.direction), next_card_id);
+        }
+    }
+
+    let mut review_ids: HashSet<i64> = HashSet::new();
+    for review in reviews {
+        let card_id = match card_ids.get(&(review.card_id.as_str(), review.ordinal, review.direction.clone())) {
+            Some(value) => *value,
+            None => continue,
+        };
+
+        // Reviews within the same second still need their own id
+        let mut review_id = review.reviewed_at * 1000;
+        while ! review_ids.insert(review_id) {
+            review_id += 1;
+        }
+
+        let kind = match review.phase.as_str() {
+            scheduler::steps::PHASE_NEW | scheduler::steps::PHASE_LEARNING => 0,
+            scheduler::steps::PHASE_RELEARNING => 2,
+            _ => 1,
+        };
+        conn.execute(
+            "INSERT INTO revlog VALUES (?1, ?2, -1, ?3, ?4, ?5, 0, ?6, ?7)",
+            rusqlite::params![
+                review_id,
+                card_id,
+                scheduler::fsrs::grade_to_rating(review.grade.clamp(0, 5) as u8) as i64,
+                review.next_interval,
+                review.previous_interval,
+                review.time_taken,
+                kind,
+            ],
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! ## CSV and TSV files
+//!
+//! Every row is a card, columns are mapped to card properties or to fields of a note type.
+//! Quoted values can hold delimiters, line breaks and doubled quotes. Without a quote char, ex. for TSV copied from
+//! a spreadsheet, values are taken as they are and exports replace delimiters and line breaks in values with spaces.
+
+use crate::{models, notes, cloze};
+use super::ImportedCard;
+
+pub const COLUMN_FRONTSIDE: &str = "frontside";
+pub const COLUMN_BACKSIDE: &str = "backside";
+pub const COLUMN_CARD_TYPE: &str = "card_type";
+pub const COLUMN_DIRECTIONS: &str = "directions";
+/// Columns mapped to it are skipped
+pub const COLUMN_SKIPPED: &str = "";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Format {
+    pub delimiter: char,
+    pub quote: Option<char>,
+    /// Whether the first row names the columns
+    pub header: bool,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format {
+            delimiter: ',',
+            quote: Some('"'),
+            header: false,
+        }
+    }
+}
+
+impl Format {
+    pub fn is_valid(&self) -> bool {
+        let special = |c: char| c == '\n' || c == '\r';
+        ! special(self.delimiter) && self.quote.map_or(true, |quote| ! special(quote) && quote != self.delimiter)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// Line the row starts on, the first line is 1
+    pub line: usize,
+    pub values: Vec<String>,
+}
+
+/// ## Splits a file into rows.
+///
+/// Empty lines are left out, the header row is returned like any other row.
+///
+/// ### Returns
+/// The rows or the line of a quoted value that is never closed
+pub fn read(text: &str, format: &Format) -> Result<Vec<Row>, usize> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+
+    let mut rows: Vec<Row> = Vec::new();
+    let mut values: Vec<String> = Vec::new();
+    let mut value = String::new();
+    let mut line = 1;
+    let mut row_line = 1;
+    // Line a quoted value starts on while inside of it
+    let mut quoted: Option<usize> = None;
+    // Quotes only open a quoted value at its start
+    let mut value_start = true;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted.is_some() {
+            if Some(c) == format.quote {
+                if chars.peek() == format.quote.as_ref() {
+                    chars.next();
+                    value.push(c);
+                } else {
+                    quoted = None;
+                }
+            } else {
+                if c == '\n' {
+                    line += 1;
+                }
+                if c != '\r' || chars.peek() != Some(&'\n') {
+                    value.push(c);
+                }
+            }
+            continue;
+        }
+
+        if value_start && Some(c) == format.quote {
+            quoted = Some(line);
+            value_start = false;
+        } else if c == format.delimiter {
+            values.push(std::mem::take(&mut value));
+            value_start = true;
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            values.push(std::mem::take(&mut value));
+            push_row(&mut rows, row_line, std::mem::take(&mut values));
+            line += 1;
+            row_line = line;
+            value_start = true;
+        } else {
+            value.push(c);
+            value_start = false;
+        }
+    }
+
+    if let Some(start) = quoted {
+        return Err(start)
+    }
+    values.push(value);
+    push_row(&mut rows, row_line, values);
+    Ok(rows)
+}
+
+fn push_row(rows: &mut Vec<Row>, line: usize, values: Vec<String>) {
+    if values.iter().any(|value| ! value.is_empty()) {
+        rows.push(Row { line, values });
+    }
+}
+
+/// ## Joins rows into a file, every row ends with a line break.
+pub fn write(rows: &[Vec<String>], format: &Format) -> String {
+    let mut output = String::new();
+    for row in rows {
+        let values: Vec<String> = row.iter().map(|value| match format.quote {
+            Some(quote) if value.contains([format.delimiter, quote, '\n', '\r']) => {
+                format!("{}{}{}", quote, value.replace(quote, &format!("{}{}", quote, quote)), quote)
+            }
+            Some(_) => value.clone(),
+            None => value.replace([format.delimiter, '\n', '\r'], " "),
+        }).collect();
+        output.push_str(&values.join(&format.delimiter.to_string()));
+        output.push('\n');
+    }
+    output
+}
+
+/// Columns used when neither the request nor a header row names them
+pub fn default_columns(field_names: Option<&[String]>) -> Vec<String> {
+    match field_names {
+        Some(field_names) => field_names.to_vec(),
+        None => vec![COLUMN_FRONTSIDE.to_owned(), COLUMN_BACKSIDE.to_owned()],
+    }
+}
+
+/// Columns of an export, every property of the cards
+pub fn export_columns(field_names: Option<&[String]>) -> Vec<String> {
+    match field_names {
+        Some(field_names) => field_names.iter().cloned().chain([COLUMN_DIRECTIONS.to_owned()]).collect(),
+        None => [COLUMN_FRONTSIDE, COLUMN_BACKSIDE, COLUMN_CARD_TYPE, COLUMN_DIRECTIONS].map(str::to_owned).to_vec(),
+    }
+}
+
+/// ## Checks that every column maps to something a card has and that nothing is mapped twice.
+///
+/// Cards of the built-in Basic note type have frontside, backside, card_type and directions,
+/// cards of other note types have the fields of the note type and directions.
+///
+/// ### Arguments
+///
+/// * `columns` - &[String]
+/// * `field_names` - Option<&[String]>, of the note type, None for the built-in Basic note type
+pub fn is_valid_columns(columns: &[String], field_names: Option<&[String]>) -> bool {
+    let known = |column: &str| match field_names {
+        Some(field_names) => field_names.iter().any(|name| name == column) || column == COLUMN_DIRECTIONS,
+        None => [COLUMN_FRONTSIDE, COLUMN_BACKSIDE, COLUMN_CARD_TYPE, COLUMN_DIRECTIONS].contains(&column),
+    };
+
+    let mapped: Vec<&String> = columns.iter().filter(|column| column.as_str() != COLUMN_SKIPPED).collect();
+    ! mapped.is_empty() &&
+        mapped.iter().all(|column| known(column)) &&
+        mapped.iter().enumerate().all(|(i, column)| ! mapped[..i].contains(column))
+}
+
+/// ## Turns a row into a card, columns have to be checked with `is_valid_columns` first.
+///
+/// Missing values are empty, a row with more values than columns is refused.
+///
+/// ### Arguments
+///
+/// * `row` - &Row
+/// * `columns` - &[String]
+/// * `note_type` - bool, whether the columns map to fields of a note type
+pub fn row_to_card(row: &Row, columns: &[String], note_type: bool) -> Result<ImportedCard, &'static str> {
+    if row.values.len() > columns.len() {
+        return Err("row has more values than there are columns")
+    }
+
+    let mut card = ImportedCard::basic(cloze::CARD_TYPE_BASIC, "", "");
+    if note_type {
+        card.note_type = Some(0);
+    }
+
+    for (column, value) in columns.iter().zip(row.values.iter()) {
+        if column == COLUMN_SKIPPED {
+            continue;
+        }
+
+        // Fields of a note type can be named like properties of basic cards
+        if note_type && column != COLUMN_DIRECTIONS {
+            card.fields.insert(column.clone(), value.clone());
+            continue;
+        }
+
+        match column.as_str() {
+            COLUMN_FRONTSIDE => card.frontside = value.clone(),
+            COLUMN_BACKSIDE => card.backside = value.clone(),
+            COLUMN_CARD_TYPE if ! value.trim().is_empty() => card.card_type = value.trim().to_owned(),
+            COLUMN_DIRECTIONS => card.directions = value.trim().to_owned(),
+            _ => {}
+        }
+    }
+    Ok(card)
+}
+
+/// Values of a card for the columns of an export
+pub fn card_to_row(card: &models::Card, columns: &[String]) -> Vec<String> {
+    let fields = card.field_values();
+    let basic = card.note_type_id == notes::BASIC_NOTE_TYPE_ID;
+    columns.iter().map(|column| match column.as_str() {
+        COLUMN_DIRECTIONS => card.directions.clone(),
+        COLUMN_FRONTSIDE if basic => card.frontside.clone(),
+        COLUMN_BACKSIDE if basic => card.backside.clone(),
+        COLUMN_CARD_TYPE if basic => card.card_type.clone(),
+        _ if basic => String::new(),
+        _ => fields.get(column).cloned().unwrap_or_default(),
+    }).collect()
+}
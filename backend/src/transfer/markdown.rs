@@ -0,0 +1,364 @@
+//! ## Markdown decks
+//!
+//! A stack in a Markdown file, meant to be kept in git next to other notes.
+//!
+//! ```md
+//! ---
+//! tags: german,nouns
+//! visibility: true
+//! ---
+//!
+//! # German
+//!
+//! Q: Hund
+//! A: dog
+//!
+//! <!-- card_type: cloze, directions: both -->
+//! Q: {{c1::Berlin}} is in Germany
+//! A:
+//! ```
+//!
+//! Front matter holds the tags, visibility and study settings of the stack, the first `#` heading is its name.
+//! Cards start with `Q:` and their back with `A:`, both can span lines. A comment line right before `Q:` sets the card type and directions.
+//! Cards can also be blocks of lines with a `---` line between front and back, ending at an empty line.
+//! Lines of a card that would start something else are escaped with a `\` at their start, the last empty lines of a back as well.
+//!
+//! Exports always use `Q:` and `A:`, so basic and cloze cards and the stack settings come back unchanged on import.
+//! Note types are not part of the format, cards of note types are exported as a forward basic card for every template that renders.
+
+use crate::{models, notes, cloze, scheduler, utils};
+use super::{ImportedStack, ImportedSettings, ImportedCard, Warning};
+
+const FRONT_MATTER: &str = "---";
+const BLOCK_SEPARATOR: &str = "---";
+const QUESTION: &str = "Q:";
+const ANSWER: &str = "A:";
+const COMMENT_START: &str = "<!--";
+const COMMENT_END: &str = "-->";
+const ESCAPE: char = '\\';
+
+const KEY_CARD_TYPE: &str = "card_type";
+const KEY_DIRECTIONS: &str = "directions";
+
+/// Card being read, lines are already unescaped
+struct CardLines {
+    line: usize,
+    /// Written with `Q:` and `A:`, otherwise a block
+    question: bool,
+    front: Vec<String>,
+    back: Option<Vec<String>>,
+    /// Lines of the back up to the last one that isn't empty in the file, escaped empty lines count
+    back_length: usize,
+    attributes: Vec<(String, String)>,
+}
+
+/// ## Reads a stack from a Markdown file.
+///
+/// Cards that fail the checks of create_card, unknown keys and invalid settings are left out with a warning.
+///
+/// ### Returns
+/// The stack and the warnings
+pub fn parse(text: &str) -> (ImportedStack, Vec<Warning>) {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text).replace("\r\n", "\n");
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut warnings: Vec<Warning> = Vec::new();
+
+    let mut stack = ImportedStack {
+        name: String::new(),
+        tags: String::new(),
+        visibility: false,
+        settings: ImportedSettings::default(),
+        note_types: Vec::new(),
+        cards: Vec::new(),
+    };
+
+    let mut i = 0;
+    if lines.first().map(|line| line.trim_end()) == Some(FRONT_MATTER) {
+        match lines[1..].iter().position(|line| line.trim_end() == FRONT_MATTER) {
+            Some(end) => {
+                for (j, line) in lines[1..=end].iter().enumerate() {
+                    read_front_matter(&mut stack, line, j + 2, &mut warnings);
+                }
+                i = end + 2;
+            }
+            None => warnings.push(Warning::new("line 1", "front matter is never closed")),
+        }
+    }
+
+    let mut name: Option<String> = None;
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let mut card: Option<CardLines> = None;
+
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+
+        if let Some(current) = &mut card {
+            let ends = if current.question {
+                line.starts_with(QUESTION) || card_attributes(line).is_some()
+            } else {
+                line.trim().is_empty()
+            };
+
+            if ! ends {
+                if current.question && current.back.is_none() && line.starts_with(ANSWER) {
+                    current.back = Some(vec![after_marker(line, ANSWER)]);
+                    current.back_length = 1;
+                } else if ! current.question && current.back.is_none() && line.trim_end() == BLOCK_SEPARATOR {
+                    current.back = Some(Vec::new());
+                } else {
+                    match &mut current.back {
+                        Some(back) => {
+                            back.push(unescape(line));
+                            if ! line.is_empty() {
+                                current.back_length = back.len();
+                            }
+                        }
+                        None => current.front.push(unescape(line)),
+                    }
+                }
+                continue;
+            }
+
+            if let Some(done) = card.take() {
+                finish_card(&mut stack, done, &mut warnings);
+            }
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(values) = card_attributes(line) {
+            attributes = values;
+        } else if line.starts_with(QUESTION) {
+            card = Some(CardLines {
+                line: i,
+                question: true,
+                front: vec![after_marker(line, QUESTION)],
+                back: None,
+                back_length: 0,
+                attributes: std::mem::take(&mut attributes),
+            });
+        } else if line.starts_with("# ") && name.is_none() {
+            name = Some(line[2..].trim().to_owned());
+        } else if line.starts_with('#') || line.trim_start().starts_with(COMMENT_START) {
+            // Other headings and comments only structure the file
+        } else {
+            card = Some(CardLines {
+                line: i,
+                question: false,
+                front: vec![unescape(line)],
+                back: None,
+                back_length: 0,
+                attributes: std::mem::take(&mut attributes),
+            });
+        }
+    }
+    if let Some(done) = card {
+        finish_card(&mut stack, done, &mut warnings);
+    }
+
+    stack.name = super::stack_name(&name.unwrap_or_default());
+    (stack, warnings)
+}
+
+fn read_front_matter(stack: &mut ImportedStack, line: &str, number: usize, warnings: &mut Vec<Warning>) {
+    let item = format!("line {}", number);
+    if line.trim().is_empty() || line.trim_start().starts_with('#') {
+        return
+    }
+    let (key, value) = match line.split_once(':') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => return warnings.push(Warning::new(&item, "front matter lines need a key and a value")),
+    };
+
+    let settings = &mut stack.settings;
+    let valid = match key {
+        "tags" => {
+            let tags: Vec<String> = value.trim_start_matches('[').trim_end_matches(']').split(',')
+                .map(|tag| tag.trim().trim_matches(|c| c == '"' || c == '\'').to_owned()).collect();
+            let (tags, left_out) = super::stack_tags(&tags);
+            if left_out > 0 {
+                warnings.push(Warning::new(&item, &format!("{} tags left out, stacks have up to 10 tags of up to 20 characters", left_out)));
+            }
+            stack.tags = tags;
+            true
+        }
+        "visibility" => parse_bool(value).map(|value| stack.visibility = value).is_some(),
+        "learning_steps" if scheduler::steps::parse_steps(value).is_some() => {
+            settings.learning_steps = scheduler::steps::format_steps(value);
+            true
+        }
+        "relearning_steps" if scheduler::steps::parse_steps(value).is_some() => {
+            settings.relearning_steps = scheduler::steps::format_steps(value);
+            true
+        }
+        "leech_threshold" => value.parse::<i32>().ok().filter(|value| utils::is_valid_leech_threshold(*value))
+            .map(|value| settings.leech_threshold = value).is_some(),
+        "leech_suspend" => parse_bool(value).map(|value| settings.leech_suspend = value).is_some(),
+        "scheduler" if scheduler::is_valid_stack_scheduler_name(value) => {
+            settings.scheduler = value.to_owned();
+            true
+        }
+        "leitner_intervals" if scheduler::leitner::is_valid_intervals(value) => {
+            settings.leitner_intervals = scheduler::leitner::format_intervals(value);
+            true
+        }
+        "directions" if scheduler::is_valid_stack_directions(value) => {
+            settings.directions = value.to_owned();
+            true
+        }
+        "learning_steps" | "relearning_steps" | "scheduler" | "leitner_intervals" | "directions" => false,
+        _ => return warnings.push(Warning::new(&item, &format!("unknown key {}", key))),
+    };
+
+    if ! valid {
+        warnings.push(Warning::new(&item, &format!("invalid {}, the default is used", key)));
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Keys and values of a comment line like `<!-- card_type: cloze, directions: both -->`
+fn card_attributes(line: &str) -> Option<Vec<(String, String)>> {
+    let inner = line.trim().strip_prefix(COMMENT_START)?.strip_suffix(COMMENT_END)?;
+    inner.split(',').map(|pair| {
+        let (key, value) = pair.split_once(':')?;
+        Some((key.trim().to_owned(), value.trim().to_owned()))
+    }).collect()
+}
+
+/// Rest of a line after `Q:` or `A:`, a single space after the marker doesn't belong to the text
+fn after_marker(line: &str, marker: &str) -> String {
+    let rest = &line[marker.len()..];
+    rest.strip_prefix(' ').unwrap_or(rest).to_owned()
+}
+
+fn unescape(line: &str) -> String {
+    line.strip_prefix(ESCAPE).unwrap_or(line).to_owned()
+}
+
+fn finish_card(stack: &mut ImportedStack, lines: CardLines, warnings: &mut Vec<Warning>) {
+    let item = format!("line {}", lines.line);
+
+    let mut back = match lines.back {
+        Some(value) => value,
+        None => return warnings.push(Warning::new(&item, "back is missing")),
+    };
+    // Empty lines between cards don't belong to the back
+    back.truncate(lines.back_length);
+
+    let mut card = ImportedCard::basic(cloze::CARD_TYPE_BASIC, &lines.front.join("\n"), &back.join("\n"));
+    for (key, value) in lines.attributes {
+        match key.as_str() {
+            KEY_CARD_TYPE => card.card_type = value,
+            KEY_DIRECTIONS => card.directions = value,
+            _ => warnings.push(Warning::new(&item, &format!("unknown key {}", key))),
+        }
+    }
+
+    match super::check_card(&card, &[]) {
+        Ok(()) => stack.cards.push(card),
+        Err(message) => warnings.push(Warning::new(&item, message)),
+    }
+}
+
+/// ## Writes a stack into a Markdown file.
+///
+/// Cards of note types become basic cards, one for every rendered template.
+///
+/// ### Arguments
+///
+/// * `stack` - &models::StackFull
+/// * `note_types` - &[models::NoteType], of the stack
+/// * `cards` - &[models::Card], of the stack
+pub fn write(stack: &models::StackFull, note_types: &[models::NoteType], cards: &[models::Card]) -> String {
+    let mut output = String::new();
+    output.push_str(FRONT_MATTER);
+    output.push('\n');
+    for (key, value) in [
+        ("tags", stack.tags.clone()),
+        ("visibility", stack.visibility.to_string()),
+        ("learning_steps", stack.learning_steps.clone()),
+        ("relearning_steps", stack.relearning_steps.clone()),
+        ("leech_threshold", stack.leech_threshold.to_string()),
+        ("leech_suspend", stack.leech_suspend.to_string()),
+        ("scheduler", stack.scheduler.clone()),
+        ("leitner_intervals", stack.leitner_intervals.clone()),
+        ("directions", stack.directions.clone()),
+    ] {
+        output.push_str(format!("{}: {}", key, value).trim_end());
+        output.push('\n');
+    }
+    output.push_str(FRONT_MATTER);
+    output.push_str(&format!("\n\n# {}\n", stack.name));
+
+    for card in cards {
+        if card.note_type_id == notes::BASIC_NOTE_TYPE_ID {
+            output.push_str(&write_card(&card.card_type, &card.directions, &card.frontside, &card.backside));
+            continue;
+        }
+
+        // Every template is studied forward only, the stored sides are the first rendered card
+        let rendered = match note_types.iter().find(|note_type| note_type.unique_id == card.note_type_id) {
+            Some(note_type) => notes::render_cards(&note_type.template_list(), &card.field_values()),
+            None => Vec::new(),
+        };
+        if rendered.is_empty() {
+            output.push_str(&write_card(cloze::CARD_TYPE_BASIC, scheduler::DIRECTION_FORWARD, &card.frontside, &card.backside));
+        }
+        for rendered in rendered {
+            output.push_str(&write_card(cloze::CARD_TYPE_BASIC, scheduler::DIRECTION_FORWARD, &rendered.front, &rendered.back));
+        }
+    }
+    output
+}
+
+/// Card with the comment line of its attributes and the empty line before it
+fn write_card(card_type: &str, directions: &str, frontside: &str, backside: &str) -> String {
+    let mut output = String::from("\n");
+
+    let mut attributes: Vec<String> = Vec::new();
+    if card_type != cloze::CARD_TYPE_BASIC {
+        attributes.push(format!("{}: {}", KEY_CARD_TYPE, card_type));
+    }
+    if ! directions.is_empty() {
+        attributes.push(format!("{}: {}", KEY_DIRECTIONS, directions));
+    }
+    if ! attributes.is_empty() {
+        output.push_str(&format!("{} {} {}\n", COMMENT_START, attributes.join(", "), COMMENT_END));
+    }
+
+    output.push_str(&write_side(QUESTION, frontside));
+    output.push_str(&write_side(ANSWER, backside));
+    output
+}
+
+/// Marker with the first line of a side and the escaped rest of it
+fn write_side(marker: &str, text: &str) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    // Empty lines at the end of the back would be read as the space between cards
+    let last_text = lines.iter().rposition(|line| ! line.is_empty()).unwrap_or(0);
+
+    let mut output = if lines[0].is_empty() { marker.to_owned() } else { format!("{} {}", marker, lines[0]) };
+    output.push('\n');
+    for (i, line) in lines.iter().enumerate().skip(1) {
+        // Comment lines are read with the whitespace around them removed
+        let special = [QUESTION, ANSWER].iter().any(|start| line.starts_with(start))
+            || line.trim_start().starts_with(COMMENT_START)
+            || line.starts_with(ESCAPE);
+        if special || (i > last_text && line.is_empty()) {
+            output.push(ESCAPE);
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
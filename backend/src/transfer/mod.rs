@@ -0,0 +1,222 @@
+//! ## Importing and exporting stacks
+//!
+//! Parsers in here turn files of other apps into stacks and cards that are not stored yet,
+//! nothing in here touches the database.
+//! Items that can't be imported are left out with a warning, the rest of the file is still imported.
+
+use crate::{models, utils, notes, cloze, scheduler};
+
+pub mod account;
+pub mod anki;
+pub mod delimited;
+pub mod markdown;
+pub mod text;
+
+/// Used when the name of an imported stack doesn't fit `utils::is_valid_stack_name`
+pub const DEFAULT_STACK_NAME: &str = "Imported stack";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// What was left out, ex. a note type name or `note 1692893520337`
+    pub item: String,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(item: &str, message: &str) -> Self {
+        Warning {
+            item: item.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedStack {
+    pub name: String,
+    /// Comma separated, valid for `utils::is_valid_tags`
+    pub tags: String,
+    pub visibility: bool,
+    pub settings: ImportedSettings,
+    pub note_types: Vec<ImportedNoteType>,
+    pub cards: Vec<ImportedCard>,
+}
+
+/// Study settings of an imported stack, every one of them valid for `update_stack`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedSettings {
+    pub learning_steps: String,
+    pub relearning_steps: String,
+    pub leech_threshold: i32,
+    pub leech_suspend: bool,
+    pub scheduler: String,
+    pub leitner_intervals: String,
+    pub directions: String,
+}
+
+impl Default for ImportedSettings {
+    fn default() -> Self {
+        ImportedSettings {
+            learning_steps: scheduler::steps::DEFAULT_LEARNING_STEPS.to_owned(),
+            relearning_steps: scheduler::steps::DEFAULT_RELEARNING_STEPS.to_owned(),
+            leech_threshold: scheduler::steps::DEFAULT_LEECH_THRESHOLD,
+            leech_suspend: false,
+            scheduler: String::new(),
+            leitner_intervals: scheduler::leitner::DEFAULT_INTERVALS.to_owned(),
+            directions: scheduler::DIRECTION_FORWARD.to_owned(),
+        }
+    }
+}
+
+impl ImportedSettings {
+    pub fn is_valid(&self) -> bool {
+        scheduler::steps::parse_steps(&self.learning_steps).is_some() &&
+            scheduler::steps::parse_steps(&self.relearning_steps).is_some() &&
+            utils::is_valid_leech_threshold(self.leech_threshold) &&
+            scheduler::is_valid_stack_scheduler_name(&self.scheduler) &&
+            scheduler::leitner::is_valid_intervals(&self.leitner_intervals) &&
+            scheduler::is_valid_stack_directions(&self.directions)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedNoteType {
+    pub name: String,
+    pub field_names: Vec<String>,
+    pub templates: Vec<notes::Template>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedCard {
+    pub card_type: String,
+    pub directions: String,
+    /// Index into the note types of the stack, None for the built-in Basic note type
+    pub note_type: Option<usize>,
+    /// Only used by cards of the built-in Basic note type
+    pub frontside: String,
+    pub backside: String,
+    /// Only used by cards of other note types
+    pub fields: notes::Fields,
+    pub reviews: Vec<ImportedReview>,
+    /// Review states of the importing user, user and card ids are set when the card is stored
+    pub states: Vec<models::CardState>,
+}
+
+impl ImportedCard {
+    pub fn basic(card_type: &str, frontside: &str, backside: &str) -> Self {
+        ImportedCard {
+            card_type: card_type.to_owned(),
+            directions: String::new(),
+            note_type: None,
+            frontside: frontside.to_owned(),
+            backside: backside.to_owned(),
+            fields: notes::Fields::new(),
+            reviews: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+}
+
+/// Review log record of an imported card, the sub-card is picked by ordinal and direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedReview {
+    pub ordinal: i32,
+    pub direction: String,
+    pub reviewed_at: i64,
+    pub grade: i32,
+    pub previous_interval: i32,
+    pub next_interval: i32,
+    pub time_taken: i32,
+    pub phase: String,
+}
+
+/// Shortens a name to fit `utils::is_valid_stack_name`, names that are too short get `DEFAULT_STACK_NAME`
+pub fn stack_name(name: &str) -> String {
+    let mut name = name.trim().to_owned();
+    while name.len() > 25 {
+        name.pop();
+    }
+    let name = name.trim_end().to_owned();
+
+    if utils::is_valid_stack_name(&name) {
+        name
+    } else {
+        DEFAULT_STACK_NAME.to_owned()
+    }
+}
+
+/// ## Joins tags into stack tags.
+///
+/// Duplicates, tags longer than 20 bytes or with a comma and everything past the 10th tag are left out.
+///
+/// ### Returns
+/// Tags valid for `utils::is_valid_tags` and the number of tags left out
+pub fn stack_tags(tags: &[String]) -> (String, usize) {
+    let mut kept: Vec<&str> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim()).filter(|tag| ! tag.is_empty()) {
+        if ! kept.contains(&tag) && kept.len() < 10 && tag.len() <= 20 && ! tag.contains(',') {
+            kept.push(tag);
+        }
+    }
+
+    let mut distinct: Vec<&str> = tags.iter().map(|tag| tag.trim()).filter(|tag| ! tag.is_empty()).collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    (kept.join(","), distinct.len() - kept.len())
+}
+
+/// ## Checks an imported card the same way `create_card` does, cards with an empty front are left out as well.
+///
+/// ### Arguments
+///
+/// * `card` - &ImportedCard
+/// * `note_types` - &[ImportedNoteType], of the stack the card belongs to
+///
+/// ### Returns
+/// Why the card can't be imported
+pub fn check_card(card: &ImportedCard, note_types: &[ImportedNoteType]) -> Result<(), &'static str> {
+    if ! scheduler::is_valid_card_directions(&card.directions) {
+        return Err("invalid directions")
+    }
+
+    let note_type = match card.note_type {
+        Some(index) => note_types.get(index).ok_or("note type is missing")?,
+        None => {
+            if card.frontside.trim().is_empty() {
+                return Err("front is empty")
+            }
+            if card.frontside.len() > 255 || card.backside.len() > 255 {
+                return Err("front or back is longer than 255 bytes")
+            }
+            if ! cloze::is_valid_card(&card.card_type, &card.frontside) {
+                return Err("invalid card type or cloze markers")
+            }
+            return Ok(())
+        }
+    };
+
+    if card.card_type != cloze::CARD_TYPE_BASIC {
+        return Err("cards of a note type can't be cloze cards")
+    }
+    if ! notes::is_valid_fields(&card.fields, &note_type.field_names) {
+        return Err("fields are empty or longer than 255 bytes")
+    }
+    if notes::render_cards(&note_type.templates, &card.fields).is_empty() {
+        return Err("no card template renders a front")
+    }
+    Ok(())
+}
+
+/// ## Writes review log records as CSV, one row per review with a header row.
+///
+/// Ids, phases and directions never contain commas, so no field needs quoting.
+pub fn review_logs_to_csv(logs: &[models::ReviewLogFull]) -> String {
+    let mut output = String::from("card_id,stack_id,reviewed_at,grade,previous_interval,next_interval,time_taken,phase,ordinal,direction\n");
+    for log in logs {
+        output.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            log.card_id, log.stack_id, log.reviewed_at, log.grade, log.previous_interval, log.next_interval, log.time_taken, log.phase, log.ordinal, log.direction
+        ));
+    }
+    output
+}
@@ -0,0 +1,81 @@
+//! ## Plain text lists of terms
+//!
+//! Text copied from Quizlet or a spreadsheet, one card per term and definition.
+//! Separators are plain strings, by default a tab between term and definition and a line break between cards.
+
+use crate::cloze;
+use super::ImportedCard;
+
+pub const DEFAULT_TERM_SEPARATOR: &str = "\t";
+pub const DEFAULT_CARD_SEPARATOR: &str = "\n";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Separators {
+    /// Between the term and its definition
+    pub term: String,
+    /// Between cards
+    pub card: String,
+}
+
+impl Default for Separators {
+    fn default() -> Self {
+        Separators {
+            term: DEFAULT_TERM_SEPARATOR.to_owned(),
+            card: DEFAULT_CARD_SEPARATOR.to_owned(),
+        }
+    }
+}
+
+impl Separators {
+    pub fn is_valid(&self) -> bool {
+        ! self.term.is_empty() && ! self.card.is_empty() && ! self.term.contains(&self.card) && ! self.card.contains(&self.term)
+    }
+}
+
+/// Term of a text that became a card, or why it couldn't
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTerm {
+    /// Line the term starts on, the first line is 1
+    pub line: usize,
+    pub card: Result<ImportedCard, &'static str>,
+}
+
+/// ## Splits a text into basic cards, the term is the frontside and the definition the backside.
+///
+/// Whitespace around terms and definitions is removed and empty parts between card separators are left out.
+/// Only the first term separator of a card counts, the rest belongs to the definition.
+///
+/// ### Arguments
+///
+/// * `text` - &str
+/// * `separators` - &Separators, have to be valid
+pub fn parse(text: &str, separators: &Separators) -> Vec<ParsedTerm> {
+    // Windows line breaks in the separators have to match the ones of the text
+    let text = text.replace("\r\n", "\n");
+    let separators = Separators {
+        term: separators.term.replace("\r\n", "\n"),
+        card: separators.card.replace("\r\n", "\n"),
+    };
+    let mut terms: Vec<ParsedTerm> = Vec::new();
+    let mut line = 1;
+
+    for part in text.split(separators.card.as_str()) {
+        // Line breaks before the term don't belong to it
+        let start = line + part[..part.len() - part.trim_start().len()].matches('\n').count();
+        line += part.matches('\n').count() + separators.card.matches('\n').count();
+
+        if part.trim().is_empty() {
+            continue;
+        }
+
+        let card = match part.split_once(separators.term.as_str()) {
+            Some((term, definition)) if ! term.trim().is_empty() => {
+                Ok(ImportedCard::basic(cloze::CARD_TYPE_BASIC, term.trim(), definition.trim()))
+            }
+            Some(_) => Err("term is empty"),
+            None => Err("term and definition separator is missing"),
+        };
+        terms.push(ParsedTerm { line: start, card });
+    }
+    terms
+}
@@ -0,0 +1,371 @@
+//! ## Syncing forks with their upstream stack
+//!
+//! Every card of a fork remembers the upstream card it came from and what that card looked like when it was
+//! forked or last pulled (the base). Comparing base, fork and upstream tells apart changes made upstream from
+//! edits of the fork owner, so pulling only brings in what changed upstream.
+//!
+//! Content is compared key by key (frontside, backside, card type, directions, note type and every field),
+//! a key changed on both sides in different ways is a conflict.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::{models, notes};
+
+/// Card that is upstream but not in the fork
+pub const CHANGE_ADDED: &str = "added";
+/// Card changed upstream, the fork only has edits of other keys if any
+pub const CHANGE_CHANGED: &str = "changed";
+/// Card changed upstream and in the fork, pulling keeps the value of the fork for the conflicting keys.
+/// Cards edited in the fork and deleted upstream are kept as they are and stop following the upstream card.
+pub const CHANGE_CONFLICT: &str = "conflict";
+/// Card that was deleted upstream and not edited in the fork
+pub const CHANGE_REMOVED: &str = "removed";
+
+const KEY_CARD_TYPE: &str = "card_type";
+const KEY_DIRECTIONS: &str = "directions";
+const KEY_NOTE_TYPE: &str = "note_type";
+const KEY_FRONTSIDE: &str = "frontside";
+const KEY_BACKSIDE: &str = "backside";
+const FIELD_PREFIX: &str = "fields.";
+
+/// What a card says, without ids that differ between a stack and its forks
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CardContent {
+    pub card_type: String,
+    pub directions: String,
+    /// Name of the note type, empty for the built-in Basic note type
+    pub note_type: String,
+    /// Only used by cards of the built-in Basic note type, the other ones render them from their fields
+    pub frontside: String,
+    pub backside: String,
+    /// Only used by cards of other note types
+    pub fields: notes::Fields,
+}
+
+impl CardContent {
+    /// ## Content of a card.
+    ///
+    /// ### Arguments
+    ///
+    /// * `card` - &models::Card
+    /// * `note_types` - &[models::NoteType], of the stack the card belongs to
+    pub fn new(card: &models::Card, note_types: &[models::NoteType]) -> Self {
+        if card.note_type_id == notes::BASIC_NOTE_TYPE_ID {
+            return CardContent {
+                card_type: card.card_type.clone(),
+                directions: card.directions.clone(),
+                note_type: String::new(),
+                frontside: card.frontside.clone(),
+                backside: card.backside.clone(),
+                fields: notes::Fields::new(),
+            }
+        }
+
+        let note_type = note_types.iter().find(|note_type| note_type.unique_id == card.note_type_id);
+        CardContent {
+            card_type: card.card_type.clone(),
+            directions: card.directions.clone(),
+            note_type: note_type.map(|note_type| note_type.name.clone()).unwrap_or_default(),
+            frontside: String::new(),
+            backside: String::new(),
+            fields: card.field_values(),
+        }
+    }
+
+    fn entries(&self) -> BTreeMap<String, String> {
+        let mut entries = BTreeMap::from([
+            (KEY_CARD_TYPE.to_owned(), self.card_type.clone()),
+            (KEY_DIRECTIONS.to_owned(), self.directions.clone()),
+            (KEY_NOTE_TYPE.to_owned(), self.note_type.clone()),
+            (KEY_FRONTSIDE.to_owned(), self.frontside.clone()),
+            (KEY_BACKSIDE.to_owned(), self.backside.clone()),
+        ]);
+        for (name, value) in &self.fields {
+            entries.insert(format!("{}{}", FIELD_PREFIX, name), value.clone());
+        }
+        entries
+    }
+
+    fn from_entries(entries: BTreeMap<String, String>) -> Self {
+        let mut content = CardContent::default();
+        for (key, value) in entries {
+            match key.as_str() {
+                KEY_CARD_TYPE => content.card_type = value,
+                KEY_DIRECTIONS => content.directions = value,
+                KEY_NOTE_TYPE => content.note_type = value,
+                KEY_FRONTSIDE => content.frontside = value,
+                KEY_BACKSIDE => content.backside = value,
+                _ => {
+                    // Empty fields are left out like the ones a card was created without
+                    if let Some(name) = key.strip_prefix(FIELD_PREFIX).filter(|_| ! value.is_empty()) {
+                        content.fields.insert(name.to_owned(), value);
+                    }
+                }
+            }
+        }
+        content
+    }
+}
+
+/// ## Merges the upstream version of a card into the fork.
+///
+/// Keys only changed on one side take that change, keys changed on both sides keep the value of the fork
+/// so pulling never throws away an edit of the fork owner.
+///
+/// ### Arguments
+///
+/// * `base` - &CardContent, upstream card when it was forked or last pulled
+/// * `local` - &CardContent, card in the fork
+/// * `upstream` - &CardContent, upstream card now
+///
+/// ### Returns
+/// The merged content and the keys that were changed on both sides
+pub fn merge(base: &CardContent, local: &CardContent, upstream: &CardContent) -> (CardContent, Vec<String>) {
+    let (base, local, upstream) = (base.entries(), local.entries(), upstream.entries());
+    let mut keys: Vec<&String> = base.keys().chain(local.keys()).chain(upstream.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut merged: BTreeMap<String, String> = BTreeMap::new();
+    let mut conflicts: Vec<String> = Vec::new();
+    for key in keys {
+        let value = |entries: &BTreeMap<String, String>| entries.get(key).cloned().unwrap_or_default();
+        let (base, local, upstream) = (value(&base), value(&local), value(&upstream));
+
+        let value = if local == base || local == upstream {
+            upstream
+        } else if upstream == base {
+            local
+        } else {
+            conflicts.push(key.clone());
+            local
+        };
+        merged.insert(key.clone(), value);
+    }
+    (CardContent::from_entries(merged), conflicts)
+}
+
+/// ## Tells what there is to pull for a card.
+///
+/// Cards deleted in the fork stay deleted, edits made only in the fork are not changes to pull.
+///
+/// ### Arguments
+///
+/// * `base` - Option<&CardContent>, None when the fork never had the upstream card
+/// * `local` - Option<&CardContent>, None when the card isn't in the fork (anymore)
+/// * `upstream` - Option<&CardContent>, None when the card was deleted upstream
+///
+/// ### Returns
+/// One of the `CHANGE_` kinds or None when there is nothing to pull
+pub fn change_kind(base: Option<&CardContent>, local: Option<&CardContent>, upstream: Option<&CardContent>) -> Option<&'static str> {
+    match (base, local, upstream) {
+        (None, _, Some(_)) => Some(CHANGE_ADDED),
+        (Some(base), Some(local), None) if local != base => Some(CHANGE_CONFLICT),
+        (Some(_), Some(_), None) => Some(CHANGE_REMOVED),
+        (Some(base), Some(local), Some(upstream)) if upstream != base => {
+            if merge(base, local, upstream).1.is_empty() {
+                Some(CHANGE_CHANGED)
+            } else {
+                Some(CHANGE_CONFLICT)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Card with something to pull
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub upstream_card_id: String,
+    /// Card in the fork, None for added cards
+    pub card_id: Option<String>,
+    /// One of the `CHANGE_` kinds
+    pub kind: &'static str,
+    pub base: Option<CardContent>,
+    pub local: Option<CardContent>,
+    pub upstream: Option<CardContent>,
+    /// What the card becomes when the change is pulled, None for cards deleted upstream
+    pub merged: Option<CardContent>,
+    /// Keys changed upstream and in the fork, merged keeps the fork's value for them
+    pub conflicts: Vec<String>,
+}
+
+/// ## Lists what changed upstream since a fork was made or last pulled.
+///
+/// ### Arguments
+///
+/// * `lineage` - &[models::CardLineage], of the fork
+/// * `local` - (&[models::Card], &[models::NoteType]), cards and note types of the fork
+/// * `upstream` - (&[models::Card], &[models::NoteType]), cards and note types of the upstream stack
+///
+/// ### Returns
+/// Changes in the order of the upstream cards, removed cards last
+pub fn changes(
+    lineage: &[models::CardLineage],
+    local: (&[models::Card], &[models::NoteType]),
+    upstream: (&[models::Card], &[models::NoteType]),
+) -> Vec<Change> {
+    let local_content = |card_id: &Option<String>| -> Option<(String, CardContent)> {
+        let card = local.0.iter().find(|card| Some(&card.unique_id) == card_id.as_ref())?;
+        Some((card.unique_id.clone(), CardContent::new(card, local.1)))
+    };
+
+    let mut output: Vec<Change> = Vec::new();
+    for card in upstream.0 {
+        let upstream_content = CardContent::new(card, upstream.1);
+        let link = lineage.iter().find(|link| link.upstream_card_id == card.unique_id);
+        let base = link.map(models::CardLineage::base_content);
+        let local = link.and_then(|link| local_content(&link.card_id));
+
+        let kind = match change_kind(base.as_ref(), local.as_ref().map(|(_, content)| content), Some(&upstream_content)) {
+            Some(value) => value,
+            None => continue,
+        };
+        let (merged, conflicts) = match (&base, &local) {
+            (Some(base), Some((_, local))) => merge(base, local, &upstream_content),
+            _ => (upstream_content.clone(), Vec::new()),
+        };
+        output.push(Change {
+            upstream_card_id: card.unique_id.clone(),
+            card_id: local.as_ref().map(|(id, _)| id.clone()),
+            kind,
+            base,
+            local: local.map(|(_, content)| content),
+            upstream: Some(upstream_content),
+            merged: Some(merged),
+            conflicts,
+        });
+    }
+
+    for link in lineage.iter().filter(|link| ! upstream.0.iter().any(|card| card.unique_id == link.upstream_card_id)) {
+        let base = link.base_content();
+        let local = local_content(&link.card_id);
+        let kind = match change_kind(Some(&base), local.as_ref().map(|(_, content)| content), None) {
+            Some(value) => value,
+            None => continue,
+        };
+        // Keys edited in the fork, they are kept with the rest of the card
+        let conflicts = match &local {
+            Some((_, content)) if kind == CHANGE_CONFLICT => {
+                let (base, local) = (base.entries(), content.entries());
+                let mut keys: Vec<String> = base.keys().chain(local.keys()).filter(|key| base.get(*key) != local.get(*key)).cloned().collect();
+                keys.sort_unstable();
+                keys.dedup();
+                keys
+            }
+            _ => Vec::new(),
+        };
+        output.push(Change {
+            upstream_card_id: link.upstream_card_id.clone(),
+            card_id: local.as_ref().map(|(id, _)| id.clone()),
+            kind,
+            base: Some(base),
+            local: local.map(|(_, content)| content),
+            upstream: None,
+            merged: None,
+            conflicts,
+        });
+    }
+    output
+}
+
+/// ## Writes content into a card of a fork, checked the way create_card and update_card check cards.
+///
+/// ### Arguments
+///
+/// * `card` - &mut models::Card
+/// * `content` - CardContent
+/// * `note_types` - &[models::NoteType], of the fork
+///
+/// ### Returns
+/// Why the content can't be stored
+pub fn apply(card: &mut models::Card, content: CardContent, note_types: &[models::NoteType]) -> Result<(), &'static str> {
+    card.card_type = content.card_type;
+    card.directions = content.directions;
+    if content.note_type.is_empty() {
+        card.note_type_id = notes::BASIC_NOTE_TYPE_ID.to_owned();
+        card.fields = String::new();
+        card.frontside = content.frontside;
+        card.backside = content.backside;
+        return card.check_basic()
+    }
+
+    let note_type = note_types.iter().find(|note_type| note_type.name == content.note_type).ok_or("note type is missing in the fork")?;
+    card.set_checked_fields(note_type, content.fields)
+}
+
+/// Stack, note types and cards of a new fork with the lineage of every card
+#[derive(Debug, Clone)]
+pub struct Fork {
+    pub stack: models::Stack,
+    pub note_types: Vec<models::NoteType>,
+    pub cards: Vec<models::Card>,
+    pub lineage: Vec<models::CardLineage>,
+}
+
+/// ## Copies a stack for a fork.
+///
+/// The copy is private and records the stack it was copied from, cards keep their content and
+/// point to the copies of their note types. The base of every card is its content at the time of the fork.
+///
+/// ### Arguments
+///
+/// * `user_id` - &str, owner of the fork
+/// * `upstream` - &models::StackFull, stack that is forked
+/// * `stack_id` - &str, id of the fork
+/// * `note_types` - &[(models::NoteType, String)], of the upstream stack with the ids of their copies
+/// * `cards` - &[(models::Card, String)], of the upstream stack with the ids of their copies
+pub fn fork(
+    user_id: &str,
+    upstream: &models::StackFull,
+    stack_id: &str,
+    note_types: &[(models::NoteType, String)],
+    cards: &[(models::Card, String)],
+) -> Fork {
+    let upstream_note_types: Vec<models::NoteType> = note_types.iter().map(|(note_type, _)| note_type.clone()).collect();
+
+    let stack = models::Stack {
+        unique_id: stack_id.to_owned(),
+        owner_id: user_id.to_owned(),
+        name: upstream.name.clone(),
+        visibility: false,
+        tags: upstream.tags.clone(),
+        learning_steps: upstream.learning_steps.clone(),
+        relearning_steps: upstream.relearning_steps.clone(),
+        leech_threshold: upstream.leech_threshold,
+        leech_suspend: upstream.leech_suspend,
+        scheduler: upstream.scheduler.clone(),
+        leitner_intervals: upstream.leitner_intervals.clone(),
+        directions: upstream.directions.clone(),
+        upstream_id: Some(upstream.unique_id.clone()),
+    };
+
+    let copied_note_types: Vec<models::NoteType> = note_types.iter().map(|(note_type, new_id)| models::NoteType {
+        unique_id: new_id.clone(),
+        stack_id: stack_id.to_owned(),
+        ..note_type.clone()
+    }).collect();
+
+    let mut copied_cards: Vec<models::Card> = Vec::with_capacity(cards.len());
+    let mut lineage: Vec<models::CardLineage> = Vec::with_capacity(cards.len());
+    for (card, new_id) in cards {
+        let note_type_id = match note_types.iter().find(|(note_type, _)| note_type.unique_id == card.note_type_id) {
+            Some((_, new_note_type_id)) => new_note_type_id.clone(),
+            None => card.note_type_id.clone(),
+        };
+        copied_cards.push(models::Card {
+            unique_id: new_id.clone(),
+            stack_id: stack_id.to_owned(),
+            note_type_id,
+            ..card.clone()
+        });
+        lineage.push(models::CardLineage {
+            stack_id: stack_id.to_owned(),
+            upstream_card_id: card.unique_id.clone(),
+            card_id: Some(new_id.clone()),
+            base: serde_json::to_string(&CardContent::new(card, &upstream_note_types)).unwrap_or_default(),
+        });
+    }
+
+    Fork { stack, note_types: copied_note_types, cards: copied_cards, lineage }
+}
@@ -1,4 +1,6 @@
 use std::time::{SystemTime, UNIX_EPOCH};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::Engine;
 use regex::Regex;
 
 pub fn get_unix_timestamp() -> u64 {
@@ -7,6 +9,49 @@ pub fn get_unix_timestamp() -> u64 {
     duration_since_epoch.as_secs()
 }
 
+pub fn random_seed() -> u64 {
+    OsRng.next_u64()
+}
+
+/// 32 url safe characters that are hard to guess, unlike the ids generated by the database
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// splitmix64, the same seed always gives the same numbers
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Number in 0..bound range, bound has to be above 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Fisher-Yates shuffle, the same seed always gives the same order
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SeededRng::new(seed);
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.below(i + 1));
+    }
+}
+
 pub fn is_ascii(input: &str) -> bool {
     for c in input.chars() {
         if (c as u32) > 127 || (c as u32) < 32 {
@@ -77,4 +122,14 @@ pub fn is_valid_tags(tags: &str) -> bool {
     if tags_len == 1 { return tags.len() <= 20; }
     else if tags_len > 10 { return false; }
     ! tags.split(',').map(|part| part.trim()).any(|tag| 20 < tag.len() )
+}
+
+/// 1 - 99
+pub fn is_valid_leech_threshold(threshold: i32) -> bool {
+    (1..=99).contains(&threshold)
+}
+
+/// 0 - 9999
+pub fn is_valid_daily_limit(limit: i32) -> bool {
+    (0..=9999).contains(&limit)
 }
\ No newline at end of file